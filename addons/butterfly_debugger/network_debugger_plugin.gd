@@ -0,0 +1,92 @@
+@tool
+extends EditorDebuggerPlugin
+
+# must match DEBUGGER_CAPTURE in the rust module
+const CAPTURE:String = "butterfly"
+const REFRESH_SECONDS:float = 0.5
+const STATE_NAMES:PackedStringArray = ["connecting", "awaiting id", "initial sync", "connected", "resuming", "disconnected"]
+
+var trees:Dictionary[int, Tree]
+
+func _has_capture(capture:String) -> bool:
+	return capture == CAPTURE
+
+func _setup_session(session_id:int) -> void:
+	var session:EditorDebuggerSession = get_session(session_id)
+	var tree:Tree = Tree.new()
+	tree.name = "Network"
+	tree.columns = 2
+	tree.hide_root = true
+	tree.column_titles_visible = true
+	tree.set_column_title(0, "Name")
+	tree.set_column_title(1, "Value")
+	var timer:Timer = Timer.new()
+	timer.wait_time = REFRESH_SECONDS
+	timer.autostart = true
+	timer.timeout.connect(_request_dump.bind(session_id))
+	tree.add_child(timer)
+	trees[session_id] = tree
+	session.add_session_tab(tree)
+	session.stopped.connect(tree.clear)
+
+func _request_dump(session_id:int) -> void:
+	var session:EditorDebuggerSession = get_session(session_id)
+	if session.is_active() and trees[session_id].is_visible_in_tree():
+		session.send_message(CAPTURE + ":request_dump", [])
+
+func _capture(message:String, data:Array, session_id:int) -> bool:
+	if message != CAPTURE + ":dump" or data.is_empty() or !trees.has(session_id):
+		return false
+	_show_dump(trees[session_id], data[0])
+	return true
+
+func _show_dump(tree:Tree, dump:Dictionary) -> void:
+	# rebuilt from scratch each time, remembering which sections were collapsed
+	var collapsed:Dictionary[String, bool]
+	if tree.get_root() != null:
+		for section:TreeItem in tree.get_root().get_children():
+			collapsed[section.get_text(0)] = section.collapsed
+	tree.clear()
+	var root:TreeItem = tree.create_item()
+	var summary:TreeItem = _add_section(tree, root, "Summary", collapsed)
+	_add_value(tree, summary, "role", dump.get("role", "none"))
+	for key:String in ["id", "tick", "latency_ms", "pending_reliable", "unsent_packets", "message_buffer", "ordered_buffer", "voice_buffer", "pending_updates", "replay_tick"]:
+		if dump.has(key):
+			_add_value(tree, summary, key, dump[key])
+	if dump.has("state"):
+		_add_value(tree, summary, "state", STATE_NAMES[dump["state"]])
+	var nodes:Array = dump.get("nodes", [])
+	var nodes_section:TreeItem = _add_section(tree, root, "Nodes", collapsed)
+	nodes_section.set_text(1, str(nodes.size()))
+	for node:Dictionary in nodes:
+		var item:TreeItem = _add_value(tree, nodes_section, str(node["objectid"]), node["path"])
+		_add_value(tree, item, "owner", node["owner_id"])
+		_add_value(tree, item, "replication", node["replication"])
+		item.collapsed = true
+	if dump.has("owned_nodes"):
+		var owned:Array = dump["owned_nodes"]
+		var owned_section:TreeItem = _add_section(tree, root, "Owned nodes", collapsed)
+		owned_section.set_text(1, str(owned.size()))
+		for node:Dictionary in owned:
+			_add_value(tree, owned_section, str(node["objectid"]), "priority %d" % node["priority"])
+	for client:Dictionary in dump.get("clients", []):
+		var client_section:TreeItem = _add_section(tree, root, "Client %d" % client["id"], collapsed)
+		for key:String in client:
+			if key != "priorities":
+				_add_value(tree, client_section, key, client[key])
+		var priorities:TreeItem = _add_value(tree, client_section, "priorities", client["priorities"].size())
+		priorities.collapsed = true
+		for entry:Dictionary in client["priorities"]:
+			_add_value(tree, priorities, str(entry["objectid"]), "priority %d, last sent tick %d" % [entry["priority"], entry["last_sent_tick"]])
+
+func _add_section(tree:Tree, parent:TreeItem, title:String, collapsed:Dictionary[String, bool]) -> TreeItem:
+	var item:TreeItem = tree.create_item(parent)
+	item.set_text(0, title)
+	item.collapsed = collapsed.get(title, false)
+	return item
+
+func _add_value(tree:Tree, parent:TreeItem, key:String, value:Variant) -> TreeItem:
+	var item:TreeItem = tree.create_item(parent)
+	item.set_text(0, key)
+	item.set_text(1, str(value))
+	return item
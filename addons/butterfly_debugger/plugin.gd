@@ -0,0 +1,13 @@
+@tool
+extends EditorPlugin
+
+const NetworkDebuggerPlugin:Script = preload("res://addons/butterfly_debugger/network_debugger_plugin.gd")
+var debugger_plugin:EditorDebuggerPlugin
+
+func _enter_tree() -> void:
+	debugger_plugin = NetworkDebuggerPlugin.new()
+	add_debugger_plugin(debugger_plugin)
+
+func _exit_tree() -> void:
+	remove_debugger_plugin(debugger_plugin)
+	debugger_plugin = null
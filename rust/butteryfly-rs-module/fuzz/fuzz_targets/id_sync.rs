@@ -0,0 +1,17 @@
+// id sync messages take up the rest of the packet so make sure odd lengths are handled
+#![no_main]
+use butteryfly_rs_module::fuzzing::*;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let bits = to_bits(data);
+    let mut pointer = 0;
+    if let Ok(sync) = IdSync::decode(&bits, &mut pointer) {
+        // the object id is varints so the header length depends on the id
+        let mut header_bits = 0;
+        read_object_id(&bits, &mut header_bits, "synced id").unwrap();
+        read_u16(&bits, &mut header_bits, "synced owner id").unwrap();
+        assert_eq!(sync.path.len() * 8, bits.len() - header_bits);
+        assert_eq!(pointer, bits.len());
+    }
+});
@@ -0,0 +1,48 @@
+// reads message headers, targets, registry entries and schema fields the way clients and the server do
+#![no_main]
+use butteryfly_rs_module::fuzzing::*;
+use libfuzzer_sys::fuzz_target;
+
+const TYPES: [NetworkedValueTypes; 22] = NetworkedValueTypes::ALL;
+
+fuzz_target!(|data: &[u8]| {
+    // the first bytes describe a schema, one byte per field, and the rest is the packet
+    let Some((&field_count, data)) = data.split_first() else {
+        return;
+    };
+    let field_count = (field_count as usize % 8).min(data.len());
+    let (descriptors, packet) = data.split_at(field_count);
+    let schema = Schema {
+        fields: descriptors
+            .iter()
+            .enumerate()
+            .map(|(idx, x)| Field {
+                name: idx.to_string(),
+                value_type: TYPES[(x & 0x3f) as usize % TYPES.len()],
+                optional: x & 0x40 != 0,
+                array: x & 0x80 != 0,
+            })
+            .collect(),
+    };
+    let bits = to_bits(packet);
+
+    // server to client, type then sender and tick
+    let mut pointer = PACKET_HEADER_BYTES * 8;
+    if read_u16(&bits, &mut pointer, "message type").is_ok()
+        && read_u16(&bits, &mut pointer, "sender").is_ok()
+        && read_u64(&bits, &mut pointer, "tick").is_ok()
+    {
+        let _ = schema.decode_fields(&bits, &mut pointer);
+    }
+
+    // client to server, type then target
+    let mut pointer = PACKET_HEADER_BYTES * 8;
+    if read_u16(&bits, &mut pointer, "message type").is_ok()
+        && MessageTarget::decode(&bits, &mut pointer).is_some()
+    {
+        let _ = schema.decode_fields(&bits, &mut pointer);
+    }
+
+    let mut pointer = 0;
+    let _ = decode_entry(&bits, &mut pointer);
+});
@@ -0,0 +1,45 @@
+// feeds a stream of packets through the same demux and split reassembly the client and server use
+#![no_main]
+use butteryfly_rs_module::fuzzing::*;
+use libfuzzer_sys::fuzz_target;
+
+const MAX_SPLIT_CHUNKS: u64 = 4096;
+const WINDOW: u64 = 256;
+
+fuzz_target!(|data: &[u8]| {
+    let mut assembler = SplitAssembler::default();
+    let mut completed: Vec<Vec<u8>> = Vec::new();
+    // each packet is prefixed by a length byte so one input can hold several packets
+    let mut rest = data;
+    while let Some((&length, tail)) = rest.split_first() {
+        let length = (length as usize).min(tail.len());
+        let (packet, tail) = tail.split_at(length);
+        rest = tail;
+        let Ok(RawPacket::Data {
+            channel,
+            packet_number,
+        }) = read_packet(packet)
+        else {
+            continue;
+        };
+        if channel != CHANNEL_SPLIT {
+            let bits = to_bits(packet);
+            // channel 1 is a timestamp followed by framed node entries
+            if channel == 1 {
+                let mut pointer = (PACKET_HEADER_BYTES + 8) * 8;
+                while read_node_entry(&bits, &mut pointer).is_ok() {}
+            }
+            continue;
+        }
+        let _ = assembler.push(
+            packet_number,
+            packet.to_vec(),
+            MAX_SPLIT_CHUNKS,
+            WINDOW,
+            &mut completed,
+        );
+        for packet in completed.drain(..) {
+            let _ = read_packet(&packet);
+        }
+    }
+});
@@ -0,0 +1,37 @@
+// decodes every value type and checks anything that decodes encodes back to the same bits
+#![no_main]
+use bitvec::prelude::*;
+use butteryfly_rs_module::fuzzing::*;
+use libfuzzer_sys::fuzz_target;
+
+const TYPES: [NetworkedValueTypes; 22] = NetworkedValueTypes::ALL;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&selector, data)) = data.split_first() else {
+        return;
+    };
+    // the top bit picks a bounded type with its range taken from the next two bytes
+    let (value_type, data) = match data {
+        [min, span, data @ ..] if selector & 0x80 != 0 => {
+            let min = *min as i8 as i64;
+            let range = BoundedRange::new(min, min + ((*span as i64) << (selector & 0x0f)))
+                .expect("range should be valid");
+            (NetworkedValueTypes::Bounded(range), data)
+        }
+        _ => (TYPES[selector as usize % TYPES.len()], data),
+    };
+    let bits = to_bits(data);
+    let mut pointer = 0;
+    let Some(value) = decode_value(&bits, &mut pointer, &value_type) else {
+        assert_eq!(pointer, 0, "pointer moved on a failed decode");
+        return;
+    };
+    assert!(pointer <= bits.len());
+    assert_eq!(value.value_type(), value_type);
+    let encoded: BitVec<u64, Lsb0> = value.encode();
+    let mut pointer = 0;
+    let decoded =
+        decode_value(&encoded, &mut pointer, &value_type).expect("encoded value failed to decode");
+    assert_eq!(pointer, encoded.len());
+    assert_eq!(decoded.encode(), encoded);
+});
@@ -0,0 +1,449 @@
+// packet captures for reproducing desyncs, networkers write every payload they send or receive along with when it happened and who it was for
+// a client's capture can be played back into a headless NetNodeClient, see NetNodeManager::start_replay
+//
+// all integers are little endian
+// header: MAGIC, VERSION u8, role u8, unix time the capture started in microseconds u64
+// record: kind u8, microseconds since the capture started u64
+//   packet records continue with: peer u16, flags u8, channel u16, packet number u64, payload length u32, payload
+use crate::wire::{self, RawPacket};
+use godot::prelude::*;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const MAGIC: &[u8; 6] = b"BFLYCP";
+const VERSION: u8 = 1;
+const KIND_TICK: u8 = 0;
+const KIND_RECEIVED: u8 = 1;
+const KIND_SENT: u8 = 2;
+// which of the decoded header fields are present, a malformed payload may have neither
+const FLAG_CHANNEL: u8 = 1;
+const FLAG_PACKET_NUMBER: u8 = 2;
+// netcode payloads are far smaller than this, anything larger means the file is corrupt
+const MAX_PAYLOAD_BYTES: u32 = 1 << 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Client = 0,
+    Server = 1,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Received,
+    Sent,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CapturedPacket {
+    pub time: Duration,
+    pub direction: Direction,
+    // the player the packet came from or went to, on a client this is always the server
+    pub peer: u16,
+    pub channel: Option<u16>,
+    // acks have no packet number
+    pub packet_number: Option<u64>,
+    // the whole payload including the header above
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Record {
+    // written at the start of every physics tick, a replay delivers the packets between two of these together
+    Tick(Duration),
+    Packet(CapturedPacket),
+}
+
+impl Record {
+    // for looking through a capture from scripts, see NetNodeManager::read_capture
+    pub fn to_dictionary(&self) -> Dictionary {
+        let mut dictionary = Dictionary::new();
+        match self {
+            Record::Tick(time) => {
+                dictionary.set("kind", "tick");
+                dictionary.set("time_ms", time.as_secs_f64() * 1000.0);
+            }
+            Record::Packet(packet) => {
+                let kind = match packet.direction {
+                    Direction::Received => "received",
+                    Direction::Sent => "sent",
+                };
+                dictionary.set("kind", kind);
+                dictionary.set("time_ms", packet.time.as_secs_f64() * 1000.0);
+                dictionary.set("peer", packet.peer);
+                // -1 if the payload was too short to have them
+                dictionary.set("channel", packet.channel.map_or(-1, |x| x as i64));
+                dictionary.set(
+                    "packet_number",
+                    packet.packet_number.map_or(-1, |x| x as i64),
+                );
+                dictionary.set("payload", PackedByteArray::from(packet.payload.as_slice()));
+            }
+        }
+        dictionary
+    }
+}
+
+pub struct CaptureWriter<W: Write> {
+    output: W,
+    started: Instant,
+    // a capture that failed to write is abandoned rather than erroring on every packet after
+    failed: bool,
+}
+pub type FileCapture = CaptureWriter<BufWriter<File>>;
+impl FileCapture {
+    pub fn create(path: &str, role: Role) -> io::Result<Self> {
+        CaptureWriter::new(BufWriter::new(File::create(path)?), role)
+    }
+}
+impl<W: Write> CaptureWriter<W> {
+    pub fn new(mut output: W, role: Role) -> io::Result<Self> {
+        let started_unix = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        output.write_all(MAGIC)?;
+        output.write_all(&[VERSION, role as u8])?;
+        output.write_all(&(started_unix.as_micros() as u64).to_le_bytes())?;
+        Ok(CaptureWriter {
+            output,
+            started: Instant::now(),
+            failed: false,
+        })
+    }
+    // also flushes so a capture from a game that crashed is complete up to its last tick
+    pub fn tick(&mut self) {
+        let time = self.micros_since_start();
+        self.write(|output| {
+            output.write_all(&[KIND_TICK])?;
+            output.write_all(&time.to_le_bytes())?;
+            output.flush()
+        });
+    }
+    pub fn packet(&mut self, direction: Direction, peer: u16, payload: &[u8]) {
+        let time = self.micros_since_start();
+        let kind = match direction {
+            Direction::Received => KIND_RECEIVED,
+            Direction::Sent => KIND_SENT,
+        };
+        let (channel, packet_number) = match wire::read_packet(payload) {
+            Ok(RawPacket::Acks(_)) => (Some(wire::CHANNEL_ACK), None),
+            Ok(RawPacket::Data {
+                channel,
+                packet_number,
+            }) => (Some(channel), Some(packet_number)),
+            Err(_) => (wire::read_channel(payload).ok(), None),
+        };
+        let flags =
+            channel.map_or(0, |_| FLAG_CHANNEL) | packet_number.map_or(0, |_| FLAG_PACKET_NUMBER);
+        self.write(|output| {
+            output.write_all(&[kind])?;
+            output.write_all(&time.to_le_bytes())?;
+            output.write_all(&peer.to_le_bytes())?;
+            output.write_all(&[flags])?;
+            output.write_all(&channel.unwrap_or_default().to_le_bytes())?;
+            output.write_all(&packet_number.unwrap_or_default().to_le_bytes())?;
+            output.write_all(&(payload.len() as u32).to_le_bytes())?;
+            output.write_all(payload)
+        });
+    }
+    pub fn failed(&self) -> bool {
+        self.failed
+    }
+    fn micros_since_start(&self) -> u64 {
+        self.started.elapsed().as_micros() as u64
+    }
+    fn write(&mut self, write: impl FnOnce(&mut W) -> io::Result<()>) {
+        if !self.failed && write(&mut self.output).is_err() {
+            self.failed = true;
+        }
+    }
+}
+
+pub struct CaptureReader<R: Read> {
+    input: R,
+    pub role: Role,
+    // wall clock time the capture started, packet times are relative to this
+    pub started_unix: Duration,
+}
+impl<R: Read> CaptureReader<R> {
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut magic = [0; MAGIC.len()];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a capture file"));
+        }
+        let [version, role] = read_array(&mut input)?;
+        if version != VERSION {
+            return Err(invalid("unsupported capture version"));
+        }
+        let role = match role {
+            0 => Role::Client,
+            1 => Role::Server,
+            _ => return Err(invalid("invalid capture role")),
+        };
+        let started_unix = Duration::from_micros(u64::from_le_bytes(read_array(&mut input)?));
+        Ok(CaptureReader {
+            input,
+            role,
+            started_unix,
+        })
+    }
+    // None at the end of the capture, a capture that stops part way through a record gives an UnexpectedEof error
+    pub fn next_record(&mut self) -> io::Result<Option<Record>> {
+        let mut kind = [0];
+        if self.input.read(&mut kind)? == 0 {
+            return Ok(None);
+        }
+        let time = Duration::from_micros(u64::from_le_bytes(read_array(&mut self.input)?));
+        let direction = match kind[0] {
+            KIND_TICK => return Ok(Some(Record::Tick(time))),
+            KIND_RECEIVED => Direction::Received,
+            KIND_SENT => Direction::Sent,
+            _ => return Err(invalid("invalid record kind")),
+        };
+        let peer = u16::from_le_bytes(read_array(&mut self.input)?);
+        let [flags] = read_array(&mut self.input)?;
+        let channel = u16::from_le_bytes(read_array(&mut self.input)?);
+        let packet_number = u64::from_le_bytes(read_array(&mut self.input)?);
+        let length = u32::from_le_bytes(read_array(&mut self.input)?);
+        if length > MAX_PAYLOAD_BYTES {
+            return Err(invalid("payload too large"));
+        }
+        let mut payload = vec![0; length as usize];
+        self.input.read_exact(&mut payload)?;
+        Ok(Some(Record::Packet(CapturedPacket {
+            time,
+            direction,
+            peer,
+            channel: (flags & FLAG_CHANNEL != 0).then_some(channel),
+            packet_number: (flags & FLAG_PACKET_NUMBER != 0).then_some(packet_number),
+            payload,
+        })))
+    }
+    // the rest of the records and whether the capture stopped part way through one, as it will if the game crashed
+    pub fn read_to_end(&mut self) -> io::Result<(Vec<Record>, bool)> {
+        let mut records = Vec::new();
+        loop {
+            match self.next_record() {
+                Ok(Some(record)) => records.push(record),
+                Ok(None) => return Ok((records, false)),
+                Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Ok((records, true));
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+impl CaptureReader<BufReader<File>> {
+    pub fn open(path: &str) -> io::Result<Self> {
+        CaptureReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+fn read_array<const N: usize>(input: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+fn invalid(error: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+// plays back what a client received, one captured tick per physics tick
+pub struct Replay {
+    // only ticks and received packets, what the client sent is regenerated by the replaying client
+    records: VecDeque<Record>,
+    started_unix: Duration,
+    // wall clock time of the captured tick being replayed, used in place of the current time for latency
+    clock: Duration,
+    tick: u64,
+    // true if the capture ended part way through a record, everything before it is still replayed
+    truncated: bool,
+    pub paused: bool,
+    // ticks left to run while paused
+    steps: u64,
+}
+impl Replay {
+    pub fn load(path: &str) -> io::Result<Self> {
+        Replay::from_reader(CaptureReader::open(path)?)
+    }
+    fn from_reader<R: Read>(mut reader: CaptureReader<R>) -> io::Result<Self> {
+        if reader.role != Role::Client {
+            return Err(invalid("only client captures can be replayed"));
+        }
+        let (records, truncated) = reader.read_to_end()?;
+        let records = records
+            .into_iter()
+            .filter(|x| !matches!(x, Record::Packet(packet) if packet.direction == Direction::Sent))
+            .collect();
+        Ok(Replay {
+            records,
+            started_unix: reader.started_unix,
+            clock: reader.started_unix,
+            tick: 0,
+            truncated,
+            paused: false,
+            steps: 0,
+        })
+    }
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+    pub fn clock(&self) -> Duration {
+        self.clock
+    }
+    pub fn step(&mut self, ticks: u64) {
+        self.steps += ticks;
+    }
+    // whether the client should run this physics tick
+    pub fn advance(&mut self) -> bool {
+        if !self.paused {
+            return true;
+        }
+        if self.steps == 0 {
+            return false;
+        }
+        self.steps -= 1;
+        true
+    }
+    // the payloads received in the next captured tick, None once the capture has run out
+    pub fn next_tick(&mut self) -> Option<Vec<Vec<u8>>> {
+        if self.records.is_empty() {
+            return None;
+        }
+        if let Some(Record::Tick(time)) = self.records.front() {
+            self.clock = self.started_unix + *time;
+            self.records.pop_front();
+        }
+        self.tick += 1;
+        let mut payloads = Vec::new();
+        while let Some(Record::Packet(_)) = self.records.front() {
+            if let Some(Record::Packet(packet)) = self.records.pop_front() {
+                payloads.push(packet.payload);
+            }
+        }
+        Some(payloads)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data_packet(channel: u16, packet_number: u64, body: &[u8]) -> Vec<u8> {
+        let mut packet = channel.to_le_bytes().to_vec();
+        packet.extend(packet_number.to_le_bytes());
+        packet.extend(body);
+        packet
+    }
+
+    fn capture(role: Role, write: impl FnOnce(&mut CaptureWriter<&mut Vec<u8>>)) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut writer = CaptureWriter::new(&mut bytes, role).unwrap();
+        write(&mut writer);
+        assert!(!writer.failed());
+        bytes
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let packet = data_packet(3, 7, &[1, 2, 3]);
+        let bytes = capture(Role::Server, |writer| {
+            writer.tick();
+            writer.packet(Direction::Received, 4, &packet);
+            writer.packet(Direction::Sent, 4, &[0xff, 0xff, 0]);
+            writer.packet(Direction::Received, 5, &[9]);
+        });
+        let mut reader = CaptureReader::new(bytes.as_slice()).unwrap();
+        assert_eq!(reader.role, Role::Server);
+        assert!(matches!(
+            reader.next_record().unwrap(),
+            Some(Record::Tick(_))
+        ));
+        let Some(Record::Packet(received)) = reader.next_record().unwrap() else {
+            panic!("expected a packet");
+        };
+        assert_eq!(received.direction, Direction::Received);
+        assert_eq!(received.peer, 4);
+        assert_eq!(received.channel, Some(3));
+        assert_eq!(received.packet_number, Some(7));
+        assert_eq!(received.payload, packet);
+        let Some(Record::Packet(ack)) = reader.next_record().unwrap() else {
+            panic!("expected a packet");
+        };
+        assert_eq!(ack.direction, Direction::Sent);
+        assert_eq!(ack.channel, Some(wire::CHANNEL_ACK));
+        assert_eq!(ack.packet_number, None);
+        let Some(Record::Packet(malformed)) = reader.next_record().unwrap() else {
+            panic!("expected a packet");
+        };
+        assert_eq!(malformed.channel, None);
+        assert_eq!(reader.next_record().unwrap(), None);
+    }
+
+    #[test]
+    fn replay_groups_received_packets_by_tick() {
+        let first = data_packet(0, 0, &[1]);
+        let second = data_packet(1, 0, &[2]);
+        let bytes = capture(Role::Client, |writer| {
+            writer.packet(Direction::Sent, 0, &data_packet(0, 0, &[]));
+            writer.tick();
+            writer.packet(Direction::Received, 0, &first);
+            writer.packet(Direction::Received, 0, &second);
+            writer.tick();
+            writer.tick();
+            writer.packet(Direction::Received, 0, &first);
+        });
+        let mut replay =
+            Replay::from_reader(CaptureReader::new(bytes.as_slice()).unwrap()).unwrap();
+        assert_eq!(replay.next_tick(), Some(vec![first.clone(), second]));
+        assert_eq!(replay.next_tick(), Some(vec![]));
+        assert_eq!(replay.next_tick(), Some(vec![first]));
+        assert_eq!(replay.next_tick(), None);
+        assert_eq!(replay.tick(), 3);
+    }
+
+    #[test]
+    fn replay_keeps_records_before_a_truncated_end() {
+        let packet = data_packet(1, 0, &[1, 2, 3, 4]);
+        let mut bytes = capture(Role::Client, |writer| {
+            writer.tick();
+            writer.packet(Direction::Received, 0, &packet);
+            writer.tick();
+            writer.packet(Direction::Received, 0, &packet);
+        });
+        bytes.truncate(bytes.len() - 2);
+        let mut replay =
+            Replay::from_reader(CaptureReader::new(bytes.as_slice()).unwrap()).unwrap();
+        assert!(replay.truncated());
+        assert_eq!(replay.next_tick(), Some(vec![packet]));
+        assert_eq!(replay.next_tick(), Some(vec![]));
+        assert_eq!(replay.next_tick(), None);
+    }
+
+    #[test]
+    fn paused_replays_only_advance_by_steps() {
+        let bytes = capture(Role::Client, |_| {});
+        let mut replay =
+            Replay::from_reader(CaptureReader::new(bytes.as_slice()).unwrap()).unwrap();
+        assert!(replay.advance());
+        replay.paused = true;
+        assert!(!replay.advance());
+        replay.step(2);
+        assert!(replay.advance());
+        assert!(replay.advance());
+        assert!(!replay.advance());
+    }
+
+    #[test]
+    fn server_captures_are_not_replayed() {
+        let bytes = capture(Role::Server, |_| {});
+        assert!(Replay::from_reader(CaptureReader::new(bytes.as_slice()).unwrap()).is_err());
+        assert!(CaptureReader::new(&b"not a capture"[..]).is_err());
+    }
+}
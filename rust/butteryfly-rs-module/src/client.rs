@@ -1,8 +1,14 @@
 // functionallity for the NetNodeManager client
+use crate::capture::{Direction, FileCapture, Replay};
+use crate::logging::{log_debug, log_error, log_info, log_warn};
 use crate::messages::*;
-use crate::net_nodes::NetworkedNode;
-use crate::serializer::*;
+use crate::moderation::ModerationEvent;
+use crate::net_nodes::{self, NetworkedNode, NodeIndex, PendingUpdates};
+use crate::players::PlayerInfo;
+use crate::registry::{self, MessageRegistry, RegistryError};
+use crate::stats::PeerStats;
 use crate::voice;
+use crate::wire::{self, CHANNEL_SPLIT, DecodeError, DecodeErrors, RawPacket, SplitAssembler};
 use bitvec::prelude::*;
 use godot::classes::Engine;
 use godot::prelude::*;
@@ -16,9 +22,18 @@ const BYTE: usize = 8;
 const BYTES2: usize = 16;
 const BYTES8: usize = 64;
 const PACKET_HEADER_SIZE: usize = BYTES2 + BYTES8;
-const PACKET_HEADER_SIZE_ACK: usize = BYTES2;
 const CHANNEL1_HEADER_SIZE: usize = BYTES8;
 const HIT_RATE_HISTORY_LENGTH: usize = 128;
+// the server splits anything too big for one packet, this is far more than it should ever need
+const MAX_SPLIT_CHUNKS: u64 = 4096;
+const CHANNEL_CONTROL: u16 = 0;
+// control packet kinds, the first byte of a channel 0 payload
+const CONTROL_LEAVE: u8 = 0;
+const CONTROL_RESUME: u8 = 1;
+const CONTROL_RESUME_ACCEPTED: u8 = 2;
+const CONTROL_RESUME_REJECTED: u8 = 3;
+const CONTROL_SYNC_FINISHED: u8 = 4;
+const CONTROL_KICKED: u8 = 5;
 #[derive(GodotClass)]
 #[class(init, base=Node)]
 pub struct NetNodeClient {
@@ -26,11 +41,18 @@ pub struct NetNodeClient {
     pub id: u16,
     next_id: u16,
     pub networked_nodes: Vec<Gd<NetworkedNode>>,
+    node_index: NodeIndex,
+    pub pending_updates: PendingUpdates,
     owned_nodes: Vec<(Gd<NetworkedNode>, i64)>,
     pub client_networker: ClientNetworker,
     packet_buffers: VecDeque<Vec<BitVec<u64, Lsb0>>>,
-    message_buffer: VecDeque<BitVec<u64, Lsb0>>,
+    // messages waiting to be sent, along with when unreliable ones expire
+    message_buffer: VecDeque<(BitVec<u64, Lsb0>, DeliveryMode, Option<Instant>)>,
     message_handlers: HashMap<u16, Gd<MessageHandler>>,
+    // named handlers by name, they are added to message_handlers once the server sends their type
+    named_handlers: HashMap<String, Gd<MessageHandler>>,
+    pub message_registry: MessageRegistry,
+    pub players: HashMap<u16, PlayerInfo>,
     c1_miss_rate_average_percent: f32,
     c1_miss_rate_average: f32,
     c1_hit_rate_average: f32,
@@ -39,6 +61,7 @@ pub struct NetNodeClient {
     next_c3_packet_number: u64,
     c3_buffered_packets: HashMap<u64, BitVec<u64, Lsb0>>,
     c0_seen_packets: HashSet<u64>,
+    c6_received: ReceivedPackets,
     remaining_bandwidth: usize,
     voice_manager: voice::VoiceStreamManager,
     next_c5_packet_number: u64,
@@ -54,13 +77,29 @@ pub struct NetNodeClient {
 
 #[godot_api]
 pub impl NetNodeClient {
+    #[signal]
+    pub fn player_info_changed(player: u16);
+    #[signal]
+    pub fn player_info_removed(player: u16);
+    #[signal]
+    pub fn connection_state_changed(state: i64);
+    #[signal]
+    pub fn connected();
+    #[signal]
+    pub fn connection_failed(reason: GString);
+    #[signal]
+    pub fn disconnected(reason: GString);
+    #[signal]
+    pub fn moderation_event(player: u16, action: GString, reason: GString);
     pub fn register_node(&mut self, new_node_ref: Gd<NetworkedNode>, new_node: &mut NetworkedNode) {
         if new_node.owner_id == self.id {
             self.owned_nodes.push((new_node_ref.clone(), 0));
         }
+        self.node_index.insert(new_node.objectid, &new_node_ref);
         self.networked_nodes.push(new_node_ref);
     }
     pub fn unregister_node(&mut self, removed_node_ref: Gd<NetworkedNode>) {
+        self.node_index.remove(&removed_node_ref);
         self.networked_nodes.remove(
             self.networked_nodes
                 .iter()
@@ -75,30 +114,163 @@ pub impl NetNodeClient {
             self.owned_nodes.remove(n);
         }
     }
+    // see NetNodeManager::get_network_stats
+    pub fn network_stats(&self) -> Dictionary {
+        let networker = &self.client_networker;
+        let mut stats = networker.stats.to_dictionary();
+        stats.set("latency_ms", networker.latency.as_secs_f64() * 1000.0);
+        stats.set("late_packets", self.c1_miss_rate_average_percent);
+        stats.set("pending_reliable", networker.reliable_packets.len() as i64);
+        stats.set("jitter_buffer_depth", self.packet_buffers.len() as i64);
+        stats.set("voice_buffer_depth", self.voice_packet_buffer.len() as i64);
+        stats
+    }
+    // see NetNodeManager::get_debug_dump
+    pub fn debug_dump(&self) -> Dictionary {
+        let mut dump = Dictionary::new();
+        dump.set("role", "client");
+        dump.set("id", self.id);
+        dump.set("state", self.client_networker.state as i64);
+        dump.set(
+            "latency_ms",
+            self.client_networker.latency.as_secs_f64() * 1000.0,
+        );
+        dump.set("nodes", net_nodes::debug_dump_nodes(&self.networked_nodes));
+        let mut owned_nodes: Array<Dictionary> = Array::new();
+        for (node, priority) in self.owned_nodes.iter().filter(|x| x.0.is_instance_valid()) {
+            let mut entry = Dictionary::new();
+            entry.set("objectid", node.bind().objectid);
+            entry.set("priority", *priority);
+            owned_nodes.push(&entry);
+        }
+        dump.set("owned_nodes", owned_nodes);
+        dump.set(
+            "pending_reliable",
+            self.client_networker.reliable_packets.len() as i64,
+        );
+        dump.set(
+            "unsent_packets",
+            self.client_networker.unsent_packets.len() as i64,
+        );
+        dump.set("message_buffer", self.message_buffer.len() as i64);
+        dump.set("ordered_buffer", self.c3_buffered_packets.len() as i64);
+        dump.set("voice_buffer", self.voice_packet_buffer.len() as i64);
+        dump.set("pending_updates", self.pending_updates.len() as i64);
+        if let Some(replay) = self.client_networker.replay.as_ref() {
+            dump.set("replay_tick", replay.tick());
+        }
+        dump
+    }
     pub fn unregister_all(&mut self) {
         self.next_id = 0;
         self.networked_nodes.clear();
+        self.node_index.clear();
+        self.pending_updates.clear();
     }
-    pub fn register_message(&mut self, handler: Gd<MessageHandler>, message_type: u16) {
+    pub fn register_message(
+        &mut self,
+        handler: Gd<MessageHandler>,
+        message_type: u16,
+        name: String,
+    ) -> Option<u16> {
+        let message_type = if name.is_empty() {
+            if let Err(error) = registry::check_manual_type(message_type) {
+                log_error!(Messages, "failed to register message handler: {}", error);
+                return None;
+            }
+            message_type
+        } else {
+            if self.named_handlers.contains_key(&name) {
+                let error =
+                    RegistryError::NameInUse(name.clone(), self.message_registry.get(&name));
+                log_error!(Messages, "failed to register message handler: {}", error);
+                return None;
+            }
+            self.named_handlers.insert(name.clone(), handler.clone());
+            // not known yet, it is registered when the server sends it
+            self.message_registry.get(&name)?
+        };
         if self.message_handlers.contains_key(&message_type) {
-            godot_warn!(
-                "tried to register duplicate handlers for message type {:#?}",
-                message_type
-            )
+            log_error!(
+                Messages,
+                "failed to register message handler: {}",
+                RegistryError::TypeInUse(message_type)
+            );
+            return None;
         }
         self.message_handlers.insert(message_type, handler);
+        Some(message_type)
     }
-    pub fn unregister_message(&mut self, message_type: u16) {
-        self.message_handlers.remove(&message_type);
+    pub fn unregister_message(&mut self, handler: Gd<MessageHandler>) {
+        self.message_handlers.retain(|_, x| *x != handler);
+        self.named_handlers.retain(|_, x| *x != handler);
     }
-    pub fn queue_message(&mut self, message: BitVec<u64, Lsb0>) {
-        self.message_buffer.push_back(message);
+    // called when the server sends the type for a name
+    fn apply_registry_entry(
+        registry: &mut MessageRegistry,
+        named_handlers: &HashMap<String, Gd<MessageHandler>>,
+        message_handlers: &mut HashMap<u16, Gd<MessageHandler>>,
+        name: String,
+        message_type: u16,
+    ) {
+        if let Err(error) = registry.insert(&name, message_type) {
+            log_error!(
+                Messages,
+                "message registry mismatch with the server: {}",
+                error
+            );
+            return;
+        }
+        let Some(handler) = named_handlers.get(&name).cloned() else {
+            return;
+        };
+        if let Some(existing) = message_handlers.get(&message_type)
+            && *existing != handler
+        {
+            log_error!(
+                Messages,
+                "failed to register message handler \"{}\": {}",
+                name,
+                RegistryError::TypeInUse(message_type)
+            );
+            return;
+        }
+        handler.clone().bind_mut().message_type = message_type;
+        message_handlers.insert(message_type, handler);
+    }
+    // the target goes right after the message type so the server knows where to route it
+    pub fn queue_message_to(
+        &mut self,
+        message: BitVec<u64, Lsb0>,
+        target: MessageTarget,
+        delivery: Delivery,
+    ) {
+        if message.len() < BYTES2 {
+            log_warn!(Messages, "tried to queue message with invalid size");
+            return;
+        }
+        let mut packet: BitVec<u64, Lsb0> = BitVec::with_capacity(message.len() + BYTES2);
+        packet.extend(&message[..BYTES2]);
+        packet.extend(target.encode());
+        packet.extend(&message[BYTES2..]);
+        self.message_buffer
+            .push_back((packet, delivery.mode, delivery.deadline()));
     }
-    pub fn start_client(&mut self, arr: PackedByteArray) {
+    pub fn start_client(&mut self, arr: PackedByteArray, timeouts: ConnectionTimeouts) {
         self.workaround = Some(Node::new_alloc());
         let reference = self.workaround.clone();
         self.base_mut().add_child(&reference.unwrap());
-        self.client_networker.client = Some(Client::new(&arr.to_vec()).unwrap());
+        self.client_networker.timeouts = timeouts;
+        match Client::new(&arr.to_vec()) {
+            Ok(client) => self.client_networker.client = Some(client),
+            Err(error) => {
+                self.client_networker.set_state(
+                    ClientState::Disconnected,
+                    Some(format!("invalid connect token: {}", error)),
+                );
+                return;
+            }
+        }
         self.client_networker.client.as_mut().unwrap().connect();
         self.client_networker
             .send(BitVec::<u64, Lsb0>::new().as_bitslice(), CHANNEL_ACK);
@@ -106,9 +278,21 @@ pub impl NetNodeClient {
         self.packet_buffers.push_back(Vec::new());
         self.encoder_stream = self.voice_manager.create_encoder();
     }
+    // runs the client from a capture instead of a connection, it starts as if netcode had just connected
+    pub fn start_replay(&mut self, replay: Replay) {
+        self.workaround = Some(Node::new_alloc());
+        let reference = self.workaround.clone();
+        self.base_mut().add_child(&reference.unwrap());
+        self.client_networker.replay = Some(replay);
+        self.client_networker
+            .set_state(ClientState::AwaitingID, None);
+        self.packet_buffers.push_back(Vec::new());
+        self.packet_buffers.push_back(Vec::new());
+        self.encoder_stream = self.voice_manager.create_encoder();
+    }
     pub fn transmit_audio(&mut self, sample_buffer: PackedVector2Array) {
         if sample_buffer.len() != voice::FRAME_LENGTH {
-            godot_warn!("got malformed sample buffer");
+            log_warn!(Voice, "got malformed sample buffer");
             return;
         }
         let tmp: Vec<f32> = sample_buffer
@@ -126,7 +310,74 @@ pub impl NetNodeClient {
     pub fn get_audio(&self) -> Vec<f32> {
         self.audio_output_buffer.clone()
     }
+    // reconnects to the server on a new connection, keeping our id, owned objects and message stream if the server still holds our session
+    pub fn resume(&mut self, token: PackedByteArray) -> bool {
+        if self.client_networker.state != ClientState::Disconnected {
+            log_warn!(Transport, "tried to resume but we are not disconnected");
+            return false;
+        }
+        if self.client_networker.replay.is_some() {
+            log_warn!(Transport, "tried to resume a replay");
+            return false;
+        }
+        let Some(ticket) = self.client_networker.resume_ticket else {
+            log_warn!(
+                Transport,
+                "tried to resume but we have no session to resume"
+            );
+            return false;
+        };
+        let mut client = match Client::new(&token.to_vec()) {
+            Ok(client) => client,
+            Err(error) => {
+                log_warn!(
+                    Transport,
+                    "tried to resume with an invalid token: {}",
+                    error
+                );
+                return false;
+            }
+        };
+        client.connect();
+        self.client_networker.client = Some(client);
+        self.client_networker.start_time = Instant::now();
+        self.client_networker.set_state(ClientState::Resuming, None);
+        let mut packet: BitVec<u64, Lsb0> = BitVec::new();
+        packet.extend(CONTROL_RESUME.view_bits::<Lsb0>());
+        packet.extend((ticket as u64).view_bits::<Lsb0>());
+        packet.extend(((ticket >> 64) as u64).view_bits::<Lsb0>());
+        // the resume request has to be the first packet the server sees from the new connection
+        self.client_networker
+            .unsent_packets
+            .insert(0, (CHANNEL_CONTROL, packet));
+        true
+    }
+    pub fn can_resume(&self) -> bool {
+        self.client_networker.state == ClientState::Disconnected
+            && self.client_networker.resume_ticket.is_some()
+    }
+    pub fn get_connection_state(&self) -> ClientState {
+        self.client_networker.state
+    }
+    pub fn has_id(&self) -> bool {
+        self.client_networker.id_assigned
+    }
     pub fn disconnect(&mut self) -> Result<(), netcode::Error> {
+        if self.client_networker.replay.take().is_some() {
+            self.client_networker.set_state(
+                ClientState::Disconnected,
+                Some("replay stopped".to_string()),
+            );
+            return Ok(());
+        }
+        if self.client_networker.client.is_none() {
+            return Ok(());
+        }
+        // lets the server know this is intentional so it doesnt hold our session open
+        let mut packet: BitVec<u64, Lsb0> = BitVec::new();
+        packet.extend(CONTROL_LEAVE.view_bits::<Lsb0>());
+        self.client_networker
+            .send(packet.as_bitslice(), CHANNEL_CONTROL);
         self.client_networker
             .client
             .as_mut()
@@ -134,15 +385,36 @@ pub impl NetNodeClient {
             .disconnect()?;
         Ok(())
     }
+    // emits signals for every state change since the last call, changes are queued as they can happen while the manager is bound
+    fn emit_state_changes(&mut self) {
+        let changes: Vec<(ClientState, ClientState, Option<String>)> =
+            self.client_networker.state_changes.drain(..).collect();
+        for (from, to, reason) in changes {
+            self.signals().connection_state_changed().emit(to as i64);
+            match to {
+                ClientState::Connected => self.signals().connected().emit(),
+                ClientState::Disconnected => {
+                    let reason = GString::from(reason.unwrap_or_default());
+                    // anything that ends before we were usable counts as a failed connection attempt
+                    if matches!(
+                        from,
+                        ClientState::Connecting | ClientState::AwaitingID | ClientState::Resuming
+                    ) {
+                        self.signals().connection_failed().emit(&reason);
+                    } else {
+                        self.signals().disconnected().emit(&reason);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
     fn tick_client(&mut self) {
         const CHANNEL_CLIENT_ID: u16 = u16::MAX - 1;
         self.client_networker.poll();
-        if !self
-            .client_networker
-            .client
-            .as_mut()
-            .unwrap()
-            .is_connected()
+        self.client_networker.update_connection_state();
+        if self.client_networker.state == ClientState::Disconnected
+            || !self.client_networker.is_connected()
         {
             return;
         }
@@ -150,11 +422,19 @@ pub impl NetNodeClient {
         // missed packets are specifically c1 packets ignored because the jitter buffer couldnt contain them
         let mut current_frame_hit_rate: u64 = 0;
         let mut current_frame_miss_rate: u64 = 0;
+        // signals need all of self so player changes are collected and emitted once packets are handled
+        let mut changed_players: Vec<u16> = Vec::new();
+        let mut removed_players: Vec<u16> = Vec::new();
+        let mut moderation_events: Vec<ModerationEvent> = Vec::new();
 
+        // id syncs are applied deferred and add their node to the index through this
+        let self_ref = self.to_gd();
         let networker = &mut self.client_networker;
-        for packet in networker.packet_buffer.drain(..) {
-            if packet.len() < BYTES2 {
-                godot_warn!("got packet with invalid size");
+        // taken rather than drained so state changes can be made while handling packets
+        for packet in std::mem::take(&mut networker.packet_buffer) {
+            // headers are checked when packets are received so this should never happen
+            if packet.len() < PACKET_HEADER_SIZE {
+                log_warn!(Transport, "got packet with invalid size");
                 continue;
             }
             let mut pointer: usize = 0;
@@ -164,6 +444,41 @@ pub impl NetNodeClient {
             let packet_number: u64 = packet[pointer..pointer + BYTES8].load_le();
             pointer += BYTES8;
             match channelid {
+                // control packets from the server about the connection itself
+                CHANNEL_CONTROL => match packet.get(pointer..pointer + BYTE).map(|x| x.load_le()) {
+                    Some(CONTROL_RESUME_ACCEPTED) => {
+                        log_info!(Transport, "resumed session with id {:#?}", self.id);
+                        networker.set_state(networker.state_before_disconnect, None);
+                    }
+                    Some(CONTROL_RESUME_REJECTED) => {
+                        networker.resume_ticket = None;
+                        networker.set_state(
+                            ClientState::Disconnected,
+                            Some("server rejected session resume".to_string()),
+                        );
+                    }
+                    Some(CONTROL_SYNC_FINISHED) => {
+                        if networker.state == ClientState::InitialSync {
+                            networker.set_state(ClientState::Connected, None);
+                        }
+                    }
+                    Some(CONTROL_KICKED) => {
+                        let reason: Vec<u8> = packet[pointer + BYTE..]
+                            .chunks_exact(BYTE)
+                            .map(|x| x.load_le::<u8>())
+                            .collect();
+                        // the server has already dropped us so there is no session to resume
+                        networker.resume_ticket = None;
+                        if let Some(client) = networker.client.as_mut() {
+                            let _ = client.disconnect();
+                        }
+                        networker.set_state(
+                            ClientState::Disconnected,
+                            Some(String::from_utf8_lossy(&reason).into_owned()),
+                        );
+                    }
+                    _ => log_warn!(Transport, "got invalid control packet"),
+                },
                 // channel 1 is for netnode updates from priority accumulation and is the most common packet type handled
                 1 => {
                     if networker.state == ClientState::AwaitingID {
@@ -171,10 +486,10 @@ pub impl NetNodeClient {
                     }
                     // this will result in ignoring c2 packets arriving out of order after the first c1 packet
                     // but c2 is only for cases where many objects need to be synced so actual effect should be negligible
-                    networker.state = ClientState::Connected;
+                    networker.set_state(ClientState::Connected, None);
 
                     if packet.len() < PACKET_HEADER_SIZE + CHANNEL1_HEADER_SIZE {
-                        godot_warn!("got c1 packet with invalid size");
+                        log_warn!(Transport, "got c1 packet with invalid size");
                         continue;
                     }
 
@@ -203,11 +518,11 @@ pub impl NetNodeClient {
                         1.0 / Engine::singleton().get_physics_ticks_per_second() as f32,
                     );
 
-                    let current_time: Duration =
-                        SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+                    let current_time: Duration = networker.now_utc();
                     let latency: Duration = current_time.saturating_sub(packet_send_time); // this is a bad workaround for an issue with current time sometimes being earlier than send time, todo: fix this
                     if latency > PACKET_LATENCY_DISCARD_THRESHOLD {
-                        godot_warn!(
+                        log_info!(
+                            Transport,
                             "ignoring packet with high latency: {:#?}ms",
                             latency.as_millis()
                         );
@@ -217,6 +532,7 @@ pub impl NetNodeClient {
                         networker.latency_buffer.pop_front();
                     }
                     networker.latency_buffer.push_back(latency);
+                    networker.stats.record_latency(latency);
                     // average latency for all packets, will be different from packet latency because of jitter
                     networker.latency = Duration::from_millis(
                         networker
@@ -247,7 +563,7 @@ pub impl NetNodeClient {
                     if packet_accepted {
                         current_frame_hit_rate += 1;
                     } else {
-                        godot_warn!("ignoring packet due to jitter");
+                        log_debug!(Sync, "ignoring packet due to jitter");
                         current_frame_miss_rate += 1;
                     }
                 }
@@ -264,8 +580,8 @@ pub impl NetNodeClient {
                     }
                     // yes this stores the packet number of every packet we get but c2 is only used once during initial connection
                     if !(self.c0_seen_packets.contains(&packet_number)) {
-                        if self.packet_buffers.len() == 0 {
-                            self.packet_buffers[0].push(packet)
+                        if let Some(buffer) = self.packet_buffers.front_mut() {
+                            buffer.push(packet)
                         }
                         self.c0_seen_packets.insert(packet_number);
                     }
@@ -278,49 +594,163 @@ pub impl NetNodeClient {
                     {
                         self.next_c3_packet_number += 1;
                         let mut pointer: usize = BYTES2 + BYTES8;
-                        let message_type: u16 = packet[pointer..pointer + BYTES2].load_le();
-                        pointer += BYTES2;
+                        // filled in by the server so the sender cant be faked
+                        let header = wire::read_u16(&packet, &mut pointer, "message type")
+                            .and_then(|message_type| {
+                                let sender =
+                                    wire::read_u16(&packet, &mut pointer, "message sender")?;
+                                let tick = wire::read_u64(&packet, &mut pointer, "message tick")?;
+                                Ok((message_type, sender, tick))
+                            });
+                        let (message_type, sender, tick) = match header {
+                            Ok(header) => header,
+                            Err(error) => {
+                                networker.record_decode_error(error);
+                                continue;
+                            }
+                        };
                         let root = self
                             .workaround
                             .as_mut()
                             .and_then(|x| x.get_tree().and_then(|x| x.get_root()));
-                        if message_type == 0 {
+                        if message_type == MESSAGE_TYPE_ID_SYNC {
+                            let sync = match IdSync::decode(packet.as_bitslice(), &mut pointer) {
+                                Ok(sync) => sync,
+                                Err(error) => {
+                                    networker.record_decode_error(error);
+                                    continue;
+                                }
+                            };
+                            let id = sync.id;
+                            let mut client = self_ref.clone();
                             root.clone().unwrap().apply_deferred(move |_this| {
-                                MessageHandler::handle_id_sync_message(
-                                    packet.as_bitslice(),
-                                    &mut pointer,
+                                // nodes found by the sync are in the tree so they have already registered
+                                if let Some(node) = MessageHandler::handle_id_sync_message(
+                                    sync,
                                     root.clone().unwrap().upcast(),
-                                )
+                                ) {
+                                    let mut client = client.bind_mut();
+                                    client.node_index.remove(&node);
+                                    client.node_index.insert(id, &node);
+                                }
                             });
+                        } else if message_type == MESSAGE_TYPE_PLAYER_INFO {
+                            if let Some(info) =
+                                PlayerInfo::decode(packet.as_bitslice(), &mut pointer)
+                            {
+                                changed_players.push(info.id);
+                                self.players.insert(info.id, info);
+                            } else {
+                                log_warn!(Messages, "failed to decode player info");
+                            }
+                        } else if message_type == MESSAGE_TYPE_REGISTRY {
+                            if let Some((name, message_type)) =
+                                registry::decode_entry(packet.as_bitslice(), &mut pointer)
+                            {
+                                NetNodeClient::apply_registry_entry(
+                                    &mut self.message_registry,
+                                    &self.named_handlers,
+                                    &mut self.message_handlers,
+                                    name,
+                                    message_type,
+                                );
+                            } else {
+                                log_warn!(Messages, "failed to decode message registry entry");
+                            }
+                        } else if message_type == MESSAGE_TYPE_MODERATION {
+                            if let Some(event) =
+                                ModerationEvent::decode(packet.as_bitslice(), &mut pointer)
+                            {
+                                moderation_events.push(event);
+                            } else {
+                                log_warn!(Messages, "failed to decode moderation event");
+                            }
+                        } else if message_type == MESSAGE_TYPE_PLAYER_REMOVED {
+                            if let Some(player) = packet.get(pointer..pointer + BYTES2) {
+                                let player: u16 = player.load_le();
+                                self.players.remove(&player);
+                                removed_players.push(player);
+                            }
                         } else if let Some(handler) = self.message_handlers.get_mut(&message_type) {
-                            handler
-                                .bind_mut()
-                                .handle_message(packet.as_bitslice(), &mut pointer);
+                            if let Err(error) = handler.bind_mut().handle_message(
+                                packet.as_bitslice(),
+                                &mut pointer,
+                                MessageContext::create(sender, tick, true),
+                            ) {
+                                networker.record_decode_error(DecodeError::Message(error));
+                            }
                         } else {
-                            godot_warn!(
+                            log_warn!(
+                                Messages,
                                 "received unhandled message with type: {:#?}",
                                 message_type
                             );
                         }
                     }
                 }
+                CHANNEL_MESSAGES_UNORDERED => {
+                    if self.c6_received.first_time(packet_number)
+                        && let Err(error) = NetNodeClient::dispatch_message(
+                            &mut self.message_handlers,
+                            &packet,
+                            true,
+                        )
+                    {
+                        networker.record_decode_error(error);
+                    }
+                }
+                CHANNEL_MESSAGES_UNRELIABLE => {
+                    if let Err(error) =
+                        NetNodeClient::dispatch_message(&mut self.message_handlers, &packet, false)
+                    {
+                        networker.record_decode_error(error);
+                    }
+                }
                 5 => {
                     let buffer: Vec<u8> = packet.chunks(BYTE).map(|x| x.load_le::<u8>()).collect();
                     if self.next_c5_packet_number <= packet_number {
                         self.voice_packet_buffer
-                            .push((packet_number, buffer[10..].into_iter().copied().collect()));
+                            .push((packet_number, buffer[wire::PACKET_HEADER_BYTES..].to_vec()));
                     }
                 }
                 CHANNEL_CLIENT_ID => {
                     // sets the id of the client, must happen before anything else
-                    self.id = packet[pointer..pointer + BYTES8].load_le::<u64>() as u16;
-                    networker.state = ClientState::InitialSync;
+                    match wire::read_u64(&packet, &mut pointer, "client id") {
+                        Ok(id) => self.id = id as u16,
+                        Err(error) => {
+                            networker.record_decode_error(error);
+                            continue;
+                        }
+                    }
+                    // the ticket lets us resume this session if the connection drops
+                    if let Some(ticket) = packet.get(pointer..pointer + BYTES8 * 2) {
+                        networker.resume_ticket = Some(
+                            ticket[..BYTES8].load_le::<u64>() as u128
+                                | (ticket[BYTES8..].load_le::<u64>() as u128) << 64,
+                        );
+                    }
+                    networker.id_assigned = true;
+                    networker.set_state(ClientState::InitialSync, None);
                 }
                 _ => {
-                    godot_warn!("unhandled channel: {:#?}", channelid)
+                    log_warn!(Transport, "unhandled channel: {:#?}", channelid)
                 }
             }
         }
+        for player in changed_players {
+            self.signals().player_info_changed().emit(player);
+        }
+        // moderation events come before removals so handlers can still look up who they were about
+        for event in moderation_events {
+            self.signals().moderation_event().emit(
+                event.player,
+                &GString::from(event.action.as_str()),
+                &GString::from(&event.reason),
+            );
+        }
+        for player in removed_players {
+            self.signals().player_info_removed().emit(player);
+        }
         // logic for updating packet jitter loss calculations
         if current_frame_hit_rate == 0 && current_frame_miss_rate == 0 {
             return;
@@ -343,35 +773,66 @@ pub impl NetNodeClient {
             / (self.c1_hit_rate_average + self.c1_miss_rate_average) as f32;
     }
     fn update_network_nodes(&mut self) {
+        for (node, values) in self.pending_updates.take_ready(&self.node_index) {
+            let _ = node.bind().update_networked_values(&values);
+        }
         for packet in self.packet_buffers.get(0).unwrap() {
             let mut pointer: usize = PACKET_HEADER_SIZE + CHANNEL1_HEADER_SIZE;
             while pointer + BYTES2 <= packet.len() {
-                let next_obj: u16 = packet[pointer..pointer + BYTES2].load_le();
-                pointer += BYTES2;
-                let tmp = self
-                    .networked_nodes
-                    .iter()
-                    .find(|x| Gd::bind(x).objectid == next_obj);
-                if tmp.is_none() {
-                    godot_warn!(
-                        "got update for nonexistant netnode with objectid: {:#?}",
-                        next_obj
-                    ); // will give a few spurious errors if we get sync data for a netnode before the creation event
-                    break; // need to stop here because we dont know how long this missing object is
-                }
-                let node = Gd::bind(tmp.unwrap());
-                let types_buff: Vec<NetworkedValueTypes> = node.get_networked_values_types();
-                if !node.update_networked_values(&mut pointer, packet.as_bitslice(), &types_buff) {
-                    // failed to decode something so need to stop again since dont know remaining length
-                    break;
+                let entry = match wire::read_node_entry(packet, &mut pointer) {
+                    Ok(entry) => entry,
+                    Err(error) => {
+                        log_warn!(Sync, "malformed node update from the server: {}", error);
+                        break;
+                    }
+                };
+                match self.node_index.get(entry.objectid) {
+                    // entries are framed so a failure only loses this node's update
+                    Some(node) => {
+                        let _ = node.bind().update_networked_values(entry.values);
+                    }
+                    // usually sync data for a netnode that arrived before its creation event
+                    None => self.pending_updates.hold(entry.objectid, entry.values),
                 }
             }
         }
     }
+    // handles messages from the unordered and unreliable channels, internal messages always use channel 3 so only user handlers are checked
+    fn dispatch_message(
+        handlers: &mut HashMap<u16, Gd<MessageHandler>>,
+        packet: &BitSlice<u64, Lsb0>,
+        reliable: bool,
+    ) -> Result<(), DecodeError> {
+        let mut pointer: usize = PACKET_HEADER_SIZE;
+        let message_type = wire::read_u16(packet, &mut pointer, "message type")?;
+        let sender = wire::read_u16(packet, &mut pointer, "message sender")?;
+        let tick = wire::read_u64(packet, &mut pointer, "message tick")?;
+        if let Some(handler) = handlers.get_mut(&message_type) {
+            handler
+                .bind_mut()
+                .handle_message(
+                    packet,
+                    &mut pointer,
+                    MessageContext::create(sender, tick, reliable),
+                )
+                .map_err(DecodeError::Message)?;
+        } else {
+            log_warn!(
+                Messages,
+                "received unhandled message with type: {:#?}",
+                message_type
+            );
+        }
+        Ok(())
+    }
     fn send_packets_client(&mut self) {
         const BANDWIDTH_BUDGET: usize = 128000;
         const PACKET_MAX_SIZE_THRESHOLD: usize = 80;
         const MAX_SINGLE_PACKET_PAYLOAD_LENGTH: usize = 4800;
+        // nothing but the resume request can be sent until the server accepts it
+        if self.client_networker.state == ClientState::Resuming {
+            return;
+        }
         self.remaining_bandwidth +=
             BANDWIDTH_BUDGET / Engine::singleton().get_physics_ticks_per_second() as usize;
         while self.remaining_bandwidth > PACKET_MAX_SIZE_THRESHOLD {
@@ -392,14 +853,20 @@ pub impl NetNodeClient {
                 return;
             }
             packet.clear();
-            // channel 3 (messages)
-            while let Some(message) = self.message_buffer.pop_front() {
-                packet.extend(&message);
-                if (self.remaining_bandwidth as i64 - packet.len() as i64) < 0 {
+            // channels 3, 6 and 7 (messages)
+            let now = Instant::now();
+            while let Some((message, mode, deadline)) = self.message_buffer.pop_front() {
+                if deadline.is_some_and(|x| x < now) {
+                    continue;
+                }
+                if self.remaining_bandwidth < message.len() {
+                    // try again next tick rather than losing the message
+                    self.message_buffer.push_front((message, mode, deadline));
                     break;
                 }
-                self.remaining_bandwidth -= packet.len();
-                self.client_networker.send(&packet.as_bitslice(), 3);
+                self.remaining_bandwidth -= message.len();
+                self.client_networker
+                    .send(message.as_bitslice(), mode.channel());
             }
             // channel 1
             packet.clear();
@@ -412,15 +879,14 @@ pub impl NetNodeClient {
             );
             for node_ref in self.owned_nodes.iter_mut() {
                 let node = Gd::bind(&node_ref.0);
-                let types_buff: Vec<NetworkedValueTypes> = node.get_networked_values_types();
-                if node_ref.1 != 0 && node.objectid != 0 {
-                    let tmp = node.get_byte_data(&types_buff);
+                if node_ref.1 != 0 && !node.objectid.is_none() {
+                    let tmp = node.get_byte_data();
                     if tmp.len() + packet.len()
                         > cmp::min(self.remaining_bandwidth, MAX_SINGLE_PACKET_PAYLOAD_LENGTH)
                     {
                         break;
                     }
-                    packet.extend(tmp);
+                    packet.extend_from_bitslice(&tmp);
                     node_ref.1 = 0;
                 }
             }
@@ -452,17 +918,32 @@ pub impl NetNodeClient {
             buffer = Vec::new();
         }
         self.next_c5_packet_number += 1;
-        self.audio_output_buffer = self
+        self.audio_output_buffer = match self
             .voice_manager
-            .decode_stereo_audio(self.decoder_stream.unwrap(), &buffer);
+            .decode_stereo_audio(self.decoder_stream.unwrap(), &buffer)
+        {
+            Ok(audio) => audio,
+            Err(error) => {
+                self.client_networker
+                    .record_decode_error(DecodeError::Voice(error.to_string()));
+                vec![0.0; voice::FRAME_LENGTH * 2]
+            }
+        };
     }
 }
 #[godot_api]
 impl INode for NetNodeClient {
     fn physics_process(&mut self, _delta: f64) {
+        self.emit_state_changes();
         if self.client_networker.state == ClientState::Disconnected {
             return;
         }
+        // a paused replay freezes the whole client until it is stepped
+        if let Some(replay) = self.client_networker.replay.as_mut()
+            && !replay.advance()
+        {
+            return;
+        }
         // check for freed network nodes
         let mut idx = 0;
         while let Some(node) = self.networked_nodes.get(idx) {
@@ -484,48 +965,75 @@ impl INode for NetNodeClient {
         self.update_network_nodes();
         self.handle_audio_input();
         self.send_packets_client();
+        self.emit_state_changes();
     }
 }
 // provides various network functionality for the client, seperation between them is mostly arbritary but maybe will be more cohesive in the future
 pub struct ClientNetworker {
     client: Option<Client<NetcodeSocket>>,
     start_time: Instant,
+    packet_number_c0: u64,
     packet_number_c1: (u64, u64),
     packet_number_c3: (u64, u64),
     packet_number_c4: u64,
     packet_number_c5: u64,
-    c4_remaining_packet_chunks: u64,
-    c4_packet_chunks: Vec<Vec<u8>>,
-    c4_waiting_packets: HashMap<u64, Vec<u8>>,
-    next_c4_packet_number: u64,
+    packet_number_c6: u64,
+    packet_number_c7: u64,
+    c4_assembler: SplitAssembler,
     packet_buffer: Vec<BitVec<u64, Lsb0>>,
     pub state: ClientState,
+    // when the current state was entered, used for phase timeouts
+    state_entered: Instant,
+    // transitions since they were last emitted as signals, (from, to, reason)
+    state_changes: Vec<(ClientState, ClientState, Option<String>)>,
+    timeouts: ConnectionTimeouts,
+    id_assigned: bool,
+    // the state to return to once a dropped session is resumed
+    state_before_disconnect: ClientState,
+    resume_ticket: Option<u128>,
     latency: Duration,
     latency_buffer: VecDeque<Duration>,
     waiting_acks: HashSet<(u16, u64)>,
-    reliable_packets: HashMap<(u16, u64), (Vec<u8>, Instant)>,
+    // the packet, when it was last sent and whether it has been resent
+    reliable_packets: HashMap<(u16, u64), (Vec<u8>, Instant, bool)>,
     unsent_packets: Vec<(u16, BitVec<u64, Lsb0>)>,
+    pub decode_errors: DecodeErrors,
+    pub stats: PeerStats,
+    // every payload sent and received is written here when capturing
+    pub capture: Option<FileCapture>,
+    // set when packets come from a capture instead of the server
+    pub replay: Option<Replay>,
 }
 impl Default for ClientNetworker {
     fn default() -> Self {
         ClientNetworker {
             client: None,
             start_time: Instant::now(),
+            packet_number_c0: 0,
             packet_number_c1: (0u64, 0u64),
             packet_number_c3: (0u64, 0u64),
             packet_number_c4: 0,
             packet_number_c5: 0,
-            c4_remaining_packet_chunks: 0,
-            c4_packet_chunks: Vec::new(),
-            c4_waiting_packets: HashMap::new(),
-            next_c4_packet_number: 0,
+            packet_number_c6: 0,
+            packet_number_c7: 0,
+            c4_assembler: SplitAssembler::default(),
             packet_buffer: Vec::new(),
-            state: ClientState::AwaitingID,
+            state: ClientState::Connecting,
+            state_entered: Instant::now(),
+            state_changes: Vec::new(),
+            timeouts: ConnectionTimeouts::default(),
+            id_assigned: false,
+            state_before_disconnect: ClientState::Connecting,
+            resume_ticket: None,
             latency: Duration::default(),
             latency_buffer: VecDeque::with_capacity(100),
             waiting_acks: HashSet::new(),
             reliable_packets: HashMap::new(),
             unsent_packets: Vec::new(),
+            decode_errors: DecodeErrors::default(),
+            stats: PeerStats::default(),
+            capture: None,
+            replay: None,
         }
     }
 }
@@ -533,13 +1041,25 @@ impl ClientNetworker {
     // netcode works with Vec<u8> so we convert before sending
     fn send(&mut self, packet: &BitSlice<u64, Lsb0>, channel: u16) {
         const PACKET_SPLIT_THRESHOLD: usize = 4800;
-        if !self.client.as_mut().unwrap().is_connected() {
+        // a replay only plays back what the client received, what it sends goes nowhere
+        if self.replay.is_some() {
+            return;
+        }
+        // while resuming nothing but the resume request can go out, otherwise the server would see the new connection as a new player
+        if !self.client.as_mut().unwrap().is_connected()
+            || (self.state == ClientState::Resuming && channel != CHANNEL_CONTROL)
+        {
             self.unsent_packets.push((channel, packet.to_bitvec()));
             return;
         }
         let mut packet_number: Option<u64> = None;
         let reliable: bool;
         match channel {
+            CHANNEL_CONTROL => {
+                reliable = true;
+                packet_number = Some(self.packet_number_c0);
+                self.packet_number_c0 += 1;
+            }
             1 => {
                 reliable = false;
                 packet_number = Some(self.packet_number_c1.0);
@@ -565,9 +1085,19 @@ impl ClientNetworker {
                 packet_number = Some(self.packet_number_c5);
                 self.packet_number_c5 += 1;
             }
+            CHANNEL_MESSAGES_UNORDERED => {
+                reliable = true;
+                packet_number = Some(self.packet_number_c6);
+                self.packet_number_c6 += 1;
+            }
+            CHANNEL_MESSAGES_UNRELIABLE => {
+                reliable = false;
+                packet_number = Some(self.packet_number_c7);
+                self.packet_number_c7 += 1;
+            }
             u16::MAX => reliable = false,
             _ => {
-                godot_warn!("unhandled / invalid channel sent");
+                log_warn!(Transport, "unhandled / invalid channel sent");
                 reliable = false;
             }
         }
@@ -592,10 +1122,14 @@ impl ClientNetworker {
             final_packet.push(bits.load_le::<u8>());
         }
         self.client.as_mut().unwrap().send(&final_packet).unwrap();
+        self.stats.record_sent(channel, final_packet.len());
+        if let Some(capture) = self.capture.as_mut() {
+            capture.packet(Direction::Sent, SERVER_SENDER, &final_packet);
+        }
         if packet_number.is_some() && reliable {
             self.reliable_packets.insert(
                 (channel, packet_number.unwrap()),
-                (final_packet, Instant::now()),
+                (final_packet, Instant::now(), false),
             );
         }
     }
@@ -611,100 +1145,107 @@ impl ClientNetworker {
             self.send(chunk, 4);
         }
     }
+    // checks the header of a packet from the server, acks and split packets are handled here
+    fn receive_packet(&mut self, packet: Vec<u8>) -> Result<(), DecodeError> {
+        if let Ok(channel) = wire::read_channel(&packet) {
+            self.stats.record_received(channel, packet.len());
+        }
+        let (channel, packet_number) = match wire::read_packet(&packet)? {
+            RawPacket::Acks(acks) => {
+                for ack in acks {
+                    self.acknowledge(ack);
+                }
+                return Ok(());
+            }
+            RawPacket::Data {
+                channel,
+                packet_number,
+            } => (channel, packet_number),
+        };
+        if channel == 1 {
+            self.stats.record_sequence(packet_number);
+        }
+        self.waiting_acks.insert((channel, packet_number));
+        if channel != CHANNEL_SPLIT {
+            self.packet_buffer.push(wire::to_bits(&packet));
+            return Ok(());
+        }
+        let mut completed: Vec<Vec<u8>> = Vec::new();
+        let result = self.c4_assembler.push(
+            packet_number,
+            packet,
+            MAX_SPLIT_CHUNKS,
+            u64::MAX,
+            &mut completed,
+        );
+        for packet in completed {
+            match wire::read_packet(&packet)? {
+                RawPacket::Acks(acks) => {
+                    for ack in acks {
+                        self.acknowledge(ack);
+                    }
+                }
+                RawPacket::Data { .. } => self.packet_buffer.push(wire::to_bits(&packet)),
+            }
+        }
+        result
+    }
+    fn acknowledge(&mut self, ack: (u16, u64)) {
+        if let Some((_, sent, resent)) = self.reliable_packets.remove(&ack)
+            && !resent
+        {
+            self.stats.record_rtt(sent.elapsed());
+        }
+    }
+    // bad packets from the server are only counted, there is no one else to connect to
+    fn record_decode_error(&mut self, error: DecodeError) {
+        log_warn!(Transport, "malformed packet from the server: {}", error);
+        self.decode_errors.record(error, 0, 0.0);
+    }
     // netcode works with Vec<u8> so we convert back before sending to the buffer
     fn poll(&mut self) {
-        self.client
-            .as_mut()
-            .unwrap()
-            .update(self.start_time.elapsed().as_secs_f64());
-        while let Some(packet) = self.client.as_mut().unwrap().recv() {
-            let channel: u16 = u16::from_le_bytes([packet[0], packet[1]]);
-            if channel == CHANNEL_ACK {
-                let mut pointer: usize = PACKET_HEADER_SIZE_ACK / BYTE;
-                while pointer + ((BYTES8 + BYTES2) / BYTE) <= packet.len() {
-                    let packet_channel = u16::from_le_bytes([packet[pointer], packet[pointer + 1]]);
-                    pointer += BYTES2 / BYTE;
-                    let packet_num = u64::from_le_bytes([
-                        packet[pointer],
-                        packet[pointer + 1],
-                        packet[pointer + 2],
-                        packet[pointer + 3],
-                        packet[pointer + 4],
-                        packet[pointer + 5],
-                        packet[pointer + 6],
-                        packet[pointer + 7],
-                    ]);
-                    pointer += BYTES8 / BYTE;
-                    self.reliable_packets.remove(&(packet_channel, packet_num));
-                }
-                continue;
+        if let Some(capture) = self.capture.as_mut() {
+            capture.tick();
+            if capture.failed() {
+                log_error!(Transport, "failed to write the packet capture, stopping it");
+                self.capture = None;
             }
-            let packet_number: u64 = u64::from_le_bytes([
-                packet[2], packet[3], packet[4], packet[5], packet[6], packet[7], packet[8],
-                packet[9],
-            ]);
-            self.waiting_acks.insert((channel, packet_number));
-
-            if channel == 4 {
-                self.c4_waiting_packets.insert(packet_number, packet);
-                while let Some(packet) = self.c4_waiting_packets.remove(&self.next_c4_packet_number)
-                {
-                    self.next_c4_packet_number += 1;
-                    if self.c4_remaining_packet_chunks == 0 {
-                        self.c4_remaining_packet_chunks = u64::from_le_bytes([
-                            packet[10], packet[11], packet[12], packet[13], packet[14], packet[15],
-                            packet[16], packet[17],
-                        ]);
-                    } else {
-                        self.c4_packet_chunks.push(packet);
-                        self.c4_remaining_packet_chunks -= 1;
-                        if self.c4_remaining_packet_chunks == 0 {
-                            let mut packet: Vec<u8> = Vec::with_capacity(
-                                self.c4_packet_chunks.iter().map(|x| x.len()).sum(),
-                            );
-                            for chunk in self.c4_packet_chunks.iter() {
-                                packet.extend(chunk[10..].iter());
-                            }
-                            self.c4_packet_chunks.clear();
-                            let channel: u16 = u16::from_le_bytes([packet[0], packet[1]]);
-                            if channel == CHANNEL_ACK {
-                                let mut pointer: usize = PACKET_HEADER_SIZE_ACK / BYTE;
-                                while pointer + ((BYTES8 + BYTES2) / BYTE) <= packet.len() {
-                                    let packet_channel =
-                                        u16::from_le_bytes([packet[pointer], packet[pointer + 1]]);
-                                    pointer += BYTES2 / BYTE;
-                                    let packet_num = u64::from_le_bytes([
-                                        packet[pointer],
-                                        packet[pointer + 1],
-                                        packet[pointer + 2],
-                                        packet[pointer + 3],
-                                        packet[pointer + 4],
-                                        packet[pointer + 5],
-                                        packet[pointer + 6],
-                                        packet[pointer + 7],
-                                    ]);
-                                    pointer += 8;
-                                    self.reliable_packets.remove(&(packet_channel, packet_num));
-                                }
-                                continue;
-                            }
-                            let mut packet_bits: BitVec<u64, Lsb0> =
-                                BitVec::with_capacity(packet.len() * 8);
-                            for byte in packet {
-                                packet_bits.extend(byte.view_bits::<Lsb0>());
-                            }
-                            self.packet_buffer.push(packet_bits);
+        }
+        if let Some(replay) = self.replay.as_mut() {
+            match replay.next_tick() {
+                Some(payloads) => {
+                    for payload in payloads {
+                        if let Err(error) = self.receive_packet(payload) {
+                            self.record_decode_error(error);
                         }
                     }
                 }
-                continue;
+                None => self.set_state(
+                    ClientState::Disconnected,
+                    Some("replay finished".to_string()),
+                ),
             }
-
-            let mut packet_bits: BitVec<u64, Lsb0> = BitVec::with_capacity(packet.len() * BYTE);
-            for byte in packet {
-                packet_bits.extend(byte.view_bits::<Lsb0>());
+            return;
+        }
+        if let Err(error) = self
+            .client
+            .as_mut()
+            .unwrap()
+            .try_update(self.start_time.elapsed().as_secs_f64())
+        {
+            self.set_state(
+                ClientState::Disconnected,
+                Some(format!("network error: {}", error)),
+            );
+            return;
+        }
+        while let Some(packet) = self.client.as_mut().unwrap().recv() {
+            if let Some(capture) = self.capture.as_mut() {
+                capture.packet(Direction::Received, SERVER_SENDER, &packet);
+            }
+            if let Err(error) = self.receive_packet(packet) {
+                self.record_decode_error(error);
             }
-            self.packet_buffer.push(packet_bits);
         }
         if self.client.as_mut().unwrap().is_connected() {
             let buffer: Vec<(u16, BitVec<u64, Lsb0>)> = self.unsent_packets.drain(..).collect();
@@ -712,22 +1253,126 @@ impl ClientNetworker {
                 self.send(packet.1.as_bitslice(), packet.0);
             }
             let now = Instant::now();
-            for packet in self.reliable_packets.values() {
+            for (key, packet) in self.reliable_packets.iter_mut() {
+                if self.state == ClientState::Resuming && key.0 != CHANNEL_CONTROL {
+                    continue;
+                }
                 if now - packet.1 > (self.latency + Duration::from_millis(32)) * 3 {
                     ClientNetworker::resend(self.client.as_mut().unwrap(), &packet.0);
+                    if let Some(capture) = self.capture.as_mut() {
+                        capture.packet(Direction::Sent, SERVER_SENDER, &packet.0);
+                    }
+                    self.stats.record_resend(key.0, packet.0.len());
+                    packet.1 = now;
+                    packet.2 = true;
                 }
             }
         }
     }
+    // a replay has no connection but acts as if it does
+    fn is_connected(&self) -> bool {
+        self.replay.is_some() || self.client.as_ref().is_some_and(|x| x.is_connected())
+    }
+    // when replaying this is the time of the captured tick so latency works out as it did for the user
+    fn now_utc(&self) -> Duration {
+        match self.replay.as_ref() {
+            Some(replay) => replay.clock(),
+            None => SystemTime::now().duration_since(UNIX_EPOCH).unwrap(),
+        }
+    }
+    pub fn set_state(&mut self, state: ClientState, reason: Option<String>) {
+        if self.state == state {
+            return;
+        }
+        if state == ClientState::Disconnected {
+            log_warn!(
+                Transport,
+                "disconnected from server: {}",
+                reason.as_deref().unwrap_or("unknown reason")
+            );
+            if matches!(
+                self.state,
+                ClientState::InitialSync | ClientState::Connected
+            ) {
+                self.state_before_disconnect = self.state;
+            }
+        }
+        self.state_changes.push((self.state, state, reason));
+        self.state = state;
+        self.state_entered = Instant::now();
+    }
+    // follows netcode's connection state and enforces our own timeouts for each phase
+    fn update_connection_state(&mut self) {
+        let Some(client) = self.client.as_mut() else {
+            return;
+        };
+        if self.state == ClientState::Disconnected {
+            return;
+        }
+        if self.state == ClientState::Connecting && client.is_connected() {
+            self.set_state(ClientState::AwaitingID, None);
+            return;
+        }
+        if !client.is_connected() && !client.is_pending() {
+            let reason = netcode_state_reason(client.state());
+            self.set_state(ClientState::Disconnected, Some(reason.to_string()));
+            return;
+        }
+        let timeout = match self.state {
+            ClientState::Connecting | ClientState::Resuming => self.timeouts.connect,
+            ClientState::AwaitingID => self.timeouts.id,
+            ClientState::InitialSync => self.timeouts.sync,
+            _ => None,
+        };
+        if let Some(timeout) = timeout
+            && self.state_entered.elapsed() > timeout
+        {
+            let _ = client.disconnect();
+            let reason = format!("timed out in state {:#?}", self.state);
+            self.set_state(ClientState::Disconnected, Some(reason));
+        }
+    }
     fn resend(client: &mut Client<NetcodeSocket>, final_packet: &[u8]) {
         client.send(final_packet).unwrap();
     }
 }
-#[derive(Default, PartialEq, Debug)]
+// values are exposed to gdscript through NetNodeManager's CONNECTION_STATE constants
+#[derive(Default, PartialEq, Debug, Clone, Copy)]
 pub enum ClientState {
+    // netcode is still performing its handshake
     #[default]
-    AwaitingID,
-    InitialSync,
-    Connected,
-    Disconnected,
+    Connecting = 0,
+    AwaitingID = 1,
+    InitialSync = 2,
+    Connected = 3,
+    // reconnected on a new connection and waiting for the server to accept our resume ticket
+    Resuming = 4,
+    Disconnected = 5,
+}
+// how long each connection phase can take before we give up, None disables the timeout
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectionTimeouts {
+    pub connect: Option<Duration>,
+    pub id: Option<Duration>,
+    pub sync: Option<Duration>,
+}
+impl Default for ConnectionTimeouts {
+    fn default() -> Self {
+        ConnectionTimeouts {
+            connect: Some(Duration::from_secs(10)),
+            id: Some(Duration::from_secs(10)),
+            sync: Some(Duration::from_secs(60)),
+        }
+    }
+}
+fn netcode_state_reason(state: netcode::ClientState) -> &'static str {
+    match state {
+        netcode::ClientState::ConnectTokenExpired => "connect token expired",
+        netcode::ClientState::ConnectionTimedOut => "connection timed out",
+        netcode::ClientState::ConnectionRequestTimedOut => "connection request timed out",
+        netcode::ClientState::ChallengeResponseTimedOut => "challenge response timed out",
+        netcode::ClientState::ConnectionDenied => "connection denied by server",
+        netcode::ClientState::Disconnected => "disconnected by server",
+        _ => "unknown reason",
+    }
 }
@@ -1,17 +1,53 @@
 // wrapper for either a client or server
+mod capture;
 mod client;
+mod logging;
 mod messages;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod moderation;
 mod net_nodes;
+mod object_ids;
+mod players;
+mod rate_limit;
+mod registry;
+mod replication;
+mod schema;
 mod serializer;
 mod server;
+mod stats;
 mod voice;
+mod wire;
 
+// the parts of the network code that read untrusted data and dont need godot, used by the fuzz targets
+#[doc(hidden)]
+pub mod fuzzing {
+    pub use crate::messages::{IdSync, MessageTarget};
+    pub use crate::object_ids::ObjectId;
+    pub use crate::registry::decode_entry;
+    pub use crate::schema::{Field, FieldValue, Schema};
+    pub use crate::serializer::{BoundedRange, NetValue, NetworkedValueTypes, decode_value};
+    pub use crate::wire::*;
+}
+
+use crate::capture::{CaptureReader, FileCapture, Replay, Role};
 use crate::client::*;
-use crate::messages::MessageHandler;
+use crate::logging::{Category, Level, Output, log_debug, log_error, log_warn};
+use crate::messages::{Delivery, MessageHandler, MessageTarget, SERVER_SENDER};
 use crate::net_nodes::*;
+use crate::object_ids::ObjectId;
+use crate::players::PlayerInfo;
+use crate::rate_limit::{LimitPolicy, MessageLimits, PacketLimits};
+use crate::serializer::{BoundedRange, NetworkedValueTypes};
 use crate::server::*;
 use bitvec::prelude::*;
+use godot::classes::{EngineDebugger, ProjectSettings};
 use godot::prelude::*;
+use std::fs::OpenOptions;
+use std::time::Duration;
+
+// prefix for messages between a running game and the editor debugger plugin
+const DEBUGGER_CAPTURE: &str = "butterfly";
 
 struct MyExtension;
 
@@ -24,11 +60,121 @@ struct NetNodeManager {
     client: Option<Gd<NetNodeClient>>,
     server: Option<Gd<NetNodeServer>>,
     is_server: bool,
+    // how long the server holds a dropped player's session open for them to resume
+    #[export]
+    #[init(val = 30.0)]
+    reconnect_window_seconds: f64,
+    // how long a client can spend in each connection phase before giving up, 0 disables the timeout
+    #[export]
+    #[init(val = 10.0)]
+    connect_timeout_seconds: f64,
+    #[export]
+    #[init(val = 10.0)]
+    id_timeout_seconds: f64,
+    #[export]
+    #[init(val = 60.0)]
+    sync_timeout_seconds: f64,
+    // where the server keeps its bans between restarts, empty to not persist them
+    #[export]
+    #[init(val = GString::from("user://bans.txt"))]
+    ban_list_path: GString,
+    // default per client message limits on the server, message handlers can override these for their own type
+    // 0 disables a limit
+    #[export]
+    #[init(val = 30.0)]
+    message_rate_limit: f64,
+    #[export]
+    #[init(val = 60.0)]
+    message_rate_limit_burst: f64,
+    #[export]
+    #[init(val = 4096)]
+    max_message_bytes: i64,
+    #[export(enum = (Drop = 0, Warn = 1, Kick = 2))]
+    message_limit_policy: i64,
+    // per client limits on packets as a whole on the server, violations emit packet_limit_exceeded
+    // 0 disables a limit
+    #[export]
+    #[init(val = 600.0)]
+    packet_rate_limit: f64,
+    #[export]
+    #[init(val = 1200.0)]
+    packet_rate_limit_burst: f64,
+    #[export]
+    #[init(val = 1275)]
+    max_voice_packet_bytes: i64,
+    #[export]
+    #[init(val = 32)]
+    max_voice_buffer_length: i64,
+    // how far ahead of the next missing packet reliable packets are still buffered
+    #[export]
+    #[init(val = 1024)]
+    reliable_window: i64,
+    #[export(enum = (Drop = 0, Warn = 1, Kick = 2))]
+    packet_limit_policy: i64,
+    // malformed packets a client can send within 10 seconds before the server kicks it, 0 to never kick
+    #[export]
+    #[init(val = 20)]
+    malformed_packet_limit: i64,
+    // how long a client keeps updates for nodes it hasn't spawned yet so they can be applied once the node registers, 0 to drop them
+    #[export]
+    #[init(val = 1.0)]
+    early_update_seconds: f64,
+    // the most detailed messages logged in every category, set_category_log_level can change single categories
+    #[export(enum = (None = -1, Error = 0, Warn = 1, Info = 2, Debug = 3))]
+    #[var(get, set = apply_log_level)]
+    #[init(val = 1)]
+    log_level: i64,
+    // headless servers can log to stderr or a file instead of the godot console
+    #[export(enum = (Console = 0, Stderr = 1, File = 2))]
+    log_output: i64,
+    #[export]
+    #[init(val = GString::from("user://network.log"))]
+    log_file_path: GString,
+    // dedicated servers serve prometheus metrics on this port on localhost, 0 to not serve them
+    // needs the module to be built with the metrics feature
+    #[export]
+    metrics_port: i64,
+    // the client or server writes every packet it sends and receives here, empty to not capture
+    // client captures can be played back with start_replay to reproduce what a player saw
+    #[export]
+    capture_path: GString,
     base: Base<Node>,
 }
 
 #[godot_api]
 impl NetNodeManager {
+    #[constant]
+    const CONNECTION_STATE_CONNECTING: i64 = ClientState::Connecting as i64;
+    #[constant]
+    const CONNECTION_STATE_AWAITING_ID: i64 = ClientState::AwaitingID as i64;
+    #[constant]
+    const CONNECTION_STATE_INITIAL_SYNC: i64 = ClientState::InitialSync as i64;
+    #[constant]
+    const CONNECTION_STATE_CONNECTED: i64 = ClientState::Connected as i64;
+    #[constant]
+    const CONNECTION_STATE_RESUMING: i64 = ClientState::Resuming as i64;
+    #[constant]
+    const CONNECTION_STATE_DISCONNECTED: i64 = ClientState::Disconnected as i64;
+    #[constant]
+    const LOG_LEVEL_NONE: i64 = -1;
+    #[constant]
+    const LOG_LEVEL_ERROR: i64 = Level::Error as i64;
+    #[constant]
+    const LOG_LEVEL_WARN: i64 = Level::Warn as i64;
+    #[constant]
+    const LOG_LEVEL_INFO: i64 = Level::Info as i64;
+    #[constant]
+    const LOG_LEVEL_DEBUG: i64 = Level::Debug as i64;
+    #[constant]
+    const LOG_CATEGORY_TRANSPORT: i64 = Category::Transport as i64;
+    #[constant]
+    const LOG_CATEGORY_SYNC: i64 = Category::Sync as i64;
+    #[constant]
+    const LOG_CATEGORY_MESSAGES: i64 = Category::Messages as i64;
+    #[constant]
+    const LOG_CATEGORY_VOICE: i64 = Category::Voice as i64;
+    #[constant]
+    const LOG_CATEGORY_AUTH: i64 = Category::Auth as i64;
     fn register_node(&mut self, node_ref: Gd<NetworkedNode>, node: &mut NetworkedNode) {
         if self.server.is_some() {
             self.server
@@ -43,16 +189,19 @@ impl NetNodeManager {
                 .bind_mut()
                 .register_node(node_ref, node);
         } else {
-            godot_warn!("called register_node but no client or server is running");
+            log_warn!(
+                Sync,
+                "called register_node but no client or server is running"
+            );
         }
     }
-    fn unregister_node(&mut self, node_ref: Gd<NetworkedNode>) {
+    fn unregister_node(&mut self, node_ref: Gd<NetworkedNode>, node: &NetworkedNode) {
         if self.server.is_some() {
             self.server
                 .as_mut()
                 .unwrap()
                 .bind_mut()
-                .unregister_node(node_ref);
+                .unregister_node(node_ref, node.objectid);
         } else if self.client.is_some() {
             self.client
                 .as_mut()
@@ -68,11 +217,14 @@ impl NetNodeManager {
         } else if self.client.is_some() {
             self.client.as_mut().unwrap().bind_mut().unregister_all();
         } else {
-            godot_warn!("called unregister_all but no client or server is running");
+            log_warn!(
+                Sync,
+                "called unregister_all but no client or server is running"
+            );
         }
     }
     #[func]
-    fn get_next_object_id(&mut self) -> u16 {
+    fn get_next_object_id(&mut self) -> ObjectId {
         if self.server.is_some() {
             return self
                 .server
@@ -86,10 +238,150 @@ impl NetNodeManager {
     }
     #[func]
     fn start_client(&mut self, arr: PackedByteArray) {
+        self.create_client();
+        let timeout = |seconds: f64| (seconds > 0.0).then(|| Duration::from_secs_f64(seconds));
+        let timeouts = ConnectionTimeouts {
+            connect: timeout(self.connect_timeout_seconds),
+            id: timeout(self.id_timeout_seconds),
+            sync: timeout(self.sync_timeout_seconds),
+        };
+        let capture = self.create_capture(Role::Client);
+        self.client
+            .as_mut()
+            .unwrap()
+            .bind_mut()
+            .client_networker
+            .capture = capture;
+        self.client
+            .as_mut()
+            .unwrap()
+            .bind_mut()
+            .start_client(arr, timeouts);
+    }
+    // plays a client capture back as if it were coming from the server, nothing the client sends goes anywhere
+    // the scene needs the same networked nodes as the player who made the capture had for them to be updated
+    #[func]
+    fn start_replay(&mut self, path: GString) -> bool {
+        let path = ProjectSettings::singleton()
+            .globalize_path(&path)
+            .to_string();
+        let replay = match Replay::load(&path) {
+            Ok(replay) => replay,
+            Err(error) => {
+                log_error!(Transport, "failed to load capture {}: {}", path, error);
+                return false;
+            }
+        };
+        if replay.truncated() {
+            log_warn!(
+                Transport,
+                "capture {} ends part way through a packet, replaying up to there",
+                path
+            );
+        }
+        self.create_client();
+        self.client
+            .as_mut()
+            .unwrap()
+            .bind_mut()
+            .start_replay(replay);
+        true
+    }
+    // a paused replay stops the client until step_replay is called
+    #[func]
+    fn set_replay_paused(&mut self, paused: bool) {
+        if let Some(client) = self.client.as_mut()
+            && let Some(replay) = client.bind_mut().client_networker.replay.as_mut()
+        {
+            replay.paused = paused;
+        }
+    }
+    // runs this many captured ticks of a paused replay
+    #[func]
+    fn step_replay(&mut self, ticks: u32) {
+        if let Some(client) = self.client.as_mut()
+            && let Some(replay) = client.bind_mut().client_networker.replay.as_mut()
+        {
+            replay.step(ticks as u64);
+        }
+    }
+    // captured ticks replayed so far, -1 if we aren't replaying
+    #[func]
+    fn get_replay_tick(&self) -> i64 {
+        self.client
+            .as_ref()
+            .and_then(|x| {
+                x.bind()
+                    .client_networker
+                    .replay
+                    .as_ref()
+                    .map(|x| x.tick() as i64)
+            })
+            .unwrap_or(-1)
+    }
+    // every record in a client or server capture, kind is tick, received or sent
+    // packets also have the peer, channel, packet number and payload
+    #[func]
+    fn read_capture(&self, path: GString) -> Array<Dictionary> {
+        let path = ProjectSettings::singleton()
+            .globalize_path(&path)
+            .to_string();
+        match CaptureReader::open(&path).and_then(|mut x| x.read_to_end()) {
+            Ok((records, truncated)) => {
+                if truncated {
+                    log_warn!(Transport, "capture {} ends part way through a packet", path);
+                }
+                records.iter().map(|x| x.to_dictionary()).collect()
+            }
+            Err(error) => {
+                log_error!(Transport, "failed to read capture {}: {}", path, error);
+                Array::new()
+            }
+        }
+    }
+    fn create_client(&mut self) {
         let c = NetNodeClient::new_alloc();
         self.base_mut().add_child(&c);
+        let selfref = self.to_gd();
+        c.signals()
+            .player_info_changed()
+            .connect_other(&selfref, NetNodeManager::propogate_player_info_changed);
+        c.signals()
+            .player_info_removed()
+            .connect_other(&selfref, NetNodeManager::propogate_player_info_removed);
+        c.signals()
+            .connection_state_changed()
+            .connect_other(&selfref, NetNodeManager::propogate_connection_state_changed);
+        c.signals()
+            .connected()
+            .connect_other(&selfref, NetNodeManager::propogate_connected);
+        c.signals()
+            .connection_failed()
+            .connect_other(&selfref, NetNodeManager::propogate_connection_failed);
+        c.signals()
+            .disconnected()
+            .connect_other(&selfref, NetNodeManager::propogate_disconnected);
+        c.signals()
+            .moderation_event()
+            .connect_other(&selfref, NetNodeManager::propogate_moderation_event);
         self.client = Some(c);
-        self.client.as_mut().unwrap().bind_mut().start_client(arr);
+        self.client.as_mut().unwrap().bind_mut().pending_updates =
+            PendingUpdates::new(Duration::from_secs_f64(self.early_update_seconds.max(0.0)));
+    }
+    fn create_capture(&self, role: Role) -> Option<FileCapture> {
+        if self.capture_path.is_empty() {
+            return None;
+        }
+        let path = ProjectSettings::singleton()
+            .globalize_path(&self.capture_path)
+            .to_string();
+        match FileCapture::create(&path, role) {
+            Ok(capture) => Some(capture),
+            Err(error) => {
+                log_error!(Transport, "failed to create capture {}: {}", path, error);
+                None
+            }
+        }
     }
     #[func]
     fn start_server(&mut self, bind_addr: String, private_key: [u8; 32]) {
@@ -109,13 +401,115 @@ impl NetNodeManager {
             .signals()
             .player_left()
             .connect_other(&selfref, NetNodeManager::propogate_player_left);
+        self.server
+            .as_mut()
+            .unwrap()
+            .signals()
+            .player_reconnecting()
+            .connect_other(&selfref, NetNodeManager::propogate_player_reconnecting);
+        self.server
+            .as_mut()
+            .unwrap()
+            .signals()
+            .player_resumed()
+            .connect_other(&selfref, NetNodeManager::propogate_player_resumed);
+        let reconnect_window_seconds = self.reconnect_window_seconds;
         self.server
             .as_mut()
             .unwrap()
             .bind_mut()
-            .start_server(bind_addr, private_key);
+            .reconnect_window_seconds = reconnect_window_seconds;
+        self.server
+            .as_mut()
+            .unwrap()
+            .signals()
+            .player_info_changed()
+            .connect_other(&selfref, NetNodeManager::propogate_player_info_changed);
+        self.server
+            .as_mut()
+            .unwrap()
+            .signals()
+            .player_info_removed()
+            .connect_other(&selfref, NetNodeManager::propogate_player_info_removed);
+        self.server
+            .as_mut()
+            .unwrap()
+            .signals()
+            .moderation_event()
+            .connect_other(&selfref, NetNodeManager::propogate_moderation_event);
+        self.server
+            .as_mut()
+            .unwrap()
+            .signals()
+            .packet_limit_exceeded()
+            .connect_other(&selfref, NetNodeManager::propogate_packet_limit_exceeded);
+        let ban_list_path = if self.ban_list_path.is_empty() {
+            String::new()
+        } else {
+            ProjectSettings::singleton()
+                .globalize_path(&self.ban_list_path)
+                .to_string()
+        };
+        self.server
+            .as_mut()
+            .unwrap()
+            .bind_mut()
+            .load_bans(ban_list_path);
+        let message_limits = MessageLimits {
+            rate: self.message_rate_limit,
+            burst: self.message_rate_limit_burst,
+            max_bytes: self.max_message_bytes.max(0) as usize,
+            policy: LimitPolicy::try_from(self.message_limit_policy).unwrap_or_default(),
+        };
+        self.server.as_mut().unwrap().bind_mut().message_limits = message_limits;
+        let packet_limits = PacketLimits {
+            rate: self.packet_rate_limit,
+            burst: self.packet_rate_limit_burst,
+            max_voice_bytes: self.max_voice_packet_bytes.max(0) as usize,
+            max_voice_buffer: self.max_voice_buffer_length.max(0) as usize,
+            reliable_window: self.reliable_window.max(0) as u64,
+            policy: LimitPolicy::try_from(self.packet_limit_policy).unwrap_or_default(),
+        };
+        self.server.as_mut().unwrap().bind_mut().packet_limits = packet_limits;
+        self.server
+            .as_mut()
+            .unwrap()
+            .bind_mut()
+            .malformed_packet_limit = self.malformed_packet_limit.clamp(0, u32::MAX as i64) as u32;
+        if self.metrics_port != 0 {
+            match u16::try_from(self.metrics_port) {
+                Ok(port) => self.server.as_mut().unwrap().bind_mut().start_metrics(port),
+                Err(_) => log_error!(
+                    Transport,
+                    "{} is not a valid metrics port",
+                    self.metrics_port
+                ),
+            }
+        }
+        let capture = self.create_capture(Role::Server);
+        self.server
+            .as_mut()
+            .unwrap()
+            .bind_mut()
+            .start_server(bind_addr, private_key, capture);
         self.is_server = true;
     }
+    // resumes a dropped session on a new connection, the token must be a fresh one as netcode tokens are bound to the connection that used them
+    #[func]
+    fn resume_client(&mut self, token: PackedByteArray) -> bool {
+        if let Some(client) = self.client.as_mut() {
+            client.bind_mut().resume(token)
+        } else {
+            log_warn!(Transport, "tried to resume_client but we are not a client");
+            false
+        }
+    }
+    #[func]
+    fn can_resume(&self) -> bool {
+        self.client
+            .as_ref()
+            .is_some_and(|client| client.bind().can_resume())
+    }
     #[func]
     fn stop(&mut self) {
         self.is_server = false;
@@ -142,6 +536,243 @@ impl NetNodeManager {
             panic!("called get_next_client() but we are not a server");
         }
     }
+    // issues a connect token for a player with a known account, the profile is applied when the token is used to connect
+    #[func]
+    fn get_next_client_with_profile(
+        &mut self,
+        account_id: String,
+        display_name: String,
+        roles: PackedStringArray,
+    ) -> PackedByteArray {
+        if let Some(server) = self.server.as_mut() {
+            server
+                .bind_mut()
+                .get_next_client_with_profile(PlayerInfo::new(
+                    account_id,
+                    display_name,
+                    roles.as_slice().iter().map(|x| x.to_string()).collect(),
+                ))
+        } else {
+            panic!("called get_next_client_with_profile() but we are not a server");
+        }
+    }
+    // returns an empty dictionary if the player is unknown
+    #[func]
+    fn get_player_info(&self, player: u16) -> Dictionary {
+        let info = if let Some(server) = self.server.as_ref() {
+            server
+                .bind()
+                .players
+                .get(&player)
+                .map(|x| x.to_dictionary())
+        } else if let Some(client) = self.client.as_ref() {
+            client
+                .bind()
+                .players
+                .get(&player)
+                .map(|x| x.to_dictionary())
+        } else {
+            panic!("called get_player_info but no client or server is running");
+        };
+        info.unwrap_or_default()
+    }
+    #[func]
+    fn get_players(&self) -> Vec<u16> {
+        let mut players: Vec<u16> = if let Some(server) = self.server.as_ref() {
+            server.bind().players.keys().copied().collect()
+        } else if let Some(client) = self.client.as_ref() {
+            client.bind().players.keys().copied().collect()
+        } else {
+            panic!("called get_players but no client or server is running");
+        };
+        players.sort_unstable();
+        players
+    }
+    #[func]
+    fn set_player_info(&mut self, player: u16, display_name: String, roles: PackedStringArray) {
+        if let Some(server) = self.server.as_mut() {
+            server.bind_mut().set_player_info(
+                player,
+                display_name,
+                roles.as_slice().iter().map(|x| x.to_string()).collect(),
+            );
+        } else {
+            panic!("called set_player_info but we are not a server");
+        }
+    }
+    // disconnects the player, they can rejoin with a new token
+    #[func]
+    fn kick(&mut self, player: u16, reason: String) -> bool {
+        if let Some(server) = self.server.as_mut() {
+            server.bind_mut().kick(player, reason)
+        } else {
+            panic!("called kick but we are not a server");
+        }
+    }
+    // target is an account id or "token:" followed by the client id a token was issued with, a duration of 0 or less bans permanently
+    // token bans only last until the server restarts as token ids start again from 0, account bans are saved to ban_list_path
+    #[func]
+    fn ban(&mut self, target: String, duration_seconds: f64, reason: String) {
+        if let Some(server) = self.server.as_mut() {
+            server
+                .bind_mut()
+                .ban(target, ban_duration(duration_seconds), reason);
+        } else {
+            panic!("called ban but we are not a server");
+        }
+    }
+    // bans a connected player's account, or their token if they have no account
+    #[func]
+    fn ban_player(&mut self, player: u16, duration_seconds: f64, reason: String) -> bool {
+        if let Some(server) = self.server.as_mut() {
+            server
+                .bind_mut()
+                .ban_player(player, ban_duration(duration_seconds), reason)
+        } else {
+            panic!("called ban_player but we are not a server");
+        }
+    }
+    #[func]
+    fn unban(&mut self, target: String) -> bool {
+        if let Some(server) = self.server.as_mut() {
+            server.bind_mut().unban(&target)
+        } else {
+            panic!("called unban but we are not a server");
+        }
+    }
+    // each ban has a target, a reason and the unix time it expires at, 0 for permanent bans
+    #[func]
+    fn get_bans(&self) -> Array<Dictionary> {
+        if let Some(server) = self.server.as_ref() {
+            server
+                .bind()
+                .bans
+                .bans()
+                .iter()
+                .map(|x| x.to_dictionary())
+                .collect()
+        } else {
+            panic!("called get_bans but we are not a server");
+        }
+    }
+    // on the server this counts malformed packets from the given player, on a client it counts ones from the server
+    // -1 if the player isnt connected
+    #[func]
+    fn get_malformed_packet_count(&self, player: u16) -> i64 {
+        if let Some(client) = self.client.as_ref() {
+            client.bind().client_networker.decode_errors.total as i64
+        } else if let Some(server) = self.server.as_ref() {
+            server
+                .bind()
+                .malformed_packets(player)
+                .map_or(-1, |x| x as i64)
+        } else {
+            panic!("called get_malformed_packet_count but no client or server is running");
+        }
+    }
+    // connection quality to the server, rtt, latency and jitter are in milliseconds and packet_loss and late_packets are fractions of channel 1 packets
+    // late packets arrived but too late for the jitter buffer, bytes_sent and bytes_received are keyed by channel
+    #[func]
+    fn get_network_stats(&self) -> Dictionary {
+        if let Some(client) = self.client.as_ref() {
+            client.bind().network_stats()
+        } else {
+            panic!("called get_network_stats but we are not a client");
+        }
+    }
+    // the same stats as get_network_stats for the server's connection to a player, empty if the player isnt connected
+    #[func]
+    fn get_client_stats(&self, player: u16) -> Dictionary {
+        if let Some(server) = self.server.as_ref() {
+            server.bind().client_stats(player).unwrap_or_default()
+        } else {
+            panic!("called get_client_stats but we are not a server");
+        }
+    }
+    // setter for log_level, applies to every category
+    #[func]
+    fn apply_log_level(&mut self, level: i64) {
+        match Level::from_setting(level) {
+            Ok(parsed) => {
+                self.log_level = level;
+                logging::set_level(None, parsed);
+            }
+            Err(error) => log_error!(Sync, "{}", error),
+        }
+    }
+    // category is one of the LOG_CATEGORY constants
+    #[func]
+    fn set_category_log_level(&mut self, category: i64, level: i64) {
+        match (Category::try_from(category), Level::from_setting(level)) {
+            (Ok(category), Ok(level)) => logging::set_level(Some(category), level),
+            (Err(error), _) | (_, Err(error)) => log_error!(Sync, "{}", error),
+        }
+    }
+    // replication state for debugging, role is server, client or none
+    // both sides list their networked nodes, the server adds each client's priorities and buffers and a client adds its owned nodes and buffers
+    #[func]
+    fn get_debug_dump(&self) -> Dictionary {
+        if let Some(server) = self.server.as_ref() {
+            server.bind().debug_dump()
+        } else if let Some(client) = self.client.as_ref() {
+            client.bind().debug_dump()
+        } else {
+            let mut dump = Dictionary::new();
+            dump.set("role", "none");
+            dump
+        }
+    }
+    // messages from the editor debugger plugin, the prefix has already been stripped
+    #[func]
+    fn on_debugger_message(&mut self, message: GString, _data: VariantArray) -> bool {
+        if message == "request_dump".into() {
+            EngineDebugger::singleton().send_message(
+                &format!("{}:dump", DEBUGGER_CAPTURE),
+                &varray![self.get_debug_dump()],
+            );
+            return true;
+        }
+        false
+    }
+    // the message types given to named message handlers so far, keyed by name
+    #[func]
+    fn get_message_types(&self) -> Dictionary {
+        if let Some(client) = self.client.as_ref() {
+            client.bind().message_registry.to_dictionary()
+        } else if let Some(server) = self.server.as_ref() {
+            server.bind().message_registry.to_dictionary()
+        } else {
+            panic!("called get_message_types but no client or server is running");
+        }
+    }
+    // the value type for an integer between min and max inclusive, sent using only as many bits as the range needs
+    // returns -1 if the range is empty or wider than 30 bits
+    #[func]
+    fn bounded_type(min: i64, max: i64) -> i64 {
+        match BoundedRange::new(min, max) {
+            Ok(range) => NetworkedValueTypes::Bounded(range).id(),
+            Err(error) => {
+                log_error!(Sync, "{}", error);
+                -1
+            }
+        }
+    }
+    #[func]
+    fn set_muted(&mut self, player: u16, muted: bool) -> bool {
+        if let Some(server) = self.server.as_mut() {
+            server.bind_mut().set_muted(player, muted)
+        } else {
+            panic!("called set_muted but we are not a server");
+        }
+    }
+    #[func]
+    fn set_deafened(&mut self, player: u16, deafened: bool) -> bool {
+        if let Some(server) = self.server.as_mut() {
+            server.bind_mut().set_deafened(player, deafened)
+        } else {
+            panic!("called set_deafened but we are not a server");
+        }
+    }
     #[func]
     fn get_id(&self) -> u16 {
         if self.server.is_some() {
@@ -157,14 +788,22 @@ impl NetNodeManager {
         if self.client.is_none() && self.server.is_none() {
             return false;
         }
-        if self.client.is_some()
-            && self.client.as_ref().unwrap().bind().client_networker.state
-                == ClientState::AwaitingID
-        {
+        if self.client.is_some() && !self.client.as_ref().unwrap().bind().has_id() {
             return false;
         }
         return true;
     }
+    // one of the CONNECTION_STATE constants, servers are always connected
+    #[func]
+    fn get_connection_state(&self) -> i64 {
+        if let Some(client) = self.client.as_ref() {
+            client.bind().get_connection_state() as i64
+        } else if self.server.is_some() {
+            ClientState::Connected as i64
+        } else {
+            ClientState::Disconnected as i64
+        }
+    }
     #[func]
     pub fn is_server(&self) -> bool {
         self.is_server
@@ -188,7 +827,7 @@ impl NetNodeManager {
                 .bind_mut()
                 .transmit_audio(sample_buffer);
         } else {
-            godot_warn!("tried to transmit_audio but we are not a client")
+            log_warn!(Voice, "tried to transmit_audio but we are not a client")
         }
     }
     #[func]
@@ -212,70 +851,193 @@ impl NetNodeManager {
             panic!("tried to register_player_object but we are not a server");
         }
     }
-    fn register_message_handler(&mut self, handler: Gd<MessageHandler>, message_type: u16) {
+    // returns the type the handler was registered with, named handlers on clients get theirs once the server sends it
+    fn register_message_handler(
+        &mut self,
+        handler: Gd<MessageHandler>,
+        message_type: u16,
+        name: String,
+    ) -> Option<u16> {
         if self.client.is_some() {
-            return self
-                .client
-                .as_mut()
-                .unwrap()
-                .bind_mut()
-                .register_message(handler, message_type);
+            return self.client.as_mut().unwrap().bind_mut().register_message(
+                handler,
+                message_type,
+                name,
+            );
         } else if self.server.is_some() {
-            return self
-                .server
-                .as_mut()
-                .unwrap()
-                .bind_mut()
-                .register_message(handler, message_type);
+            return self.server.as_mut().unwrap().bind_mut().register_message(
+                handler,
+                message_type,
+                name,
+            );
         } else {
             panic!("tried to register_message_handler but no client or server is running");
         }
     }
-    fn unregister_message_handler(&mut self, message_type: u16) {
+    fn unregister_message_handler(&mut self, handler: Gd<MessageHandler>) {
         if self.client.is_some() {
             return self
                 .client
                 .as_mut()
                 .unwrap()
                 .bind_mut()
-                .unregister_message(message_type);
+                .unregister_message(handler);
         } else if self.server.is_some() {
             return self
                 .server
                 .as_mut()
                 .unwrap()
                 .bind_mut()
-                .unregister_message(message_type);
+                .unregister_message(handler);
         } else {
             panic!("tried to unregister_message_handler but no client or server is running");
         }
     }
-    fn queue_message(&mut self, message: BitVec<u64, Lsb0>) {
-        if self.client.is_some() {
-            self.client
-                .as_mut()
-                .unwrap()
+    fn queue_message_to(
+        &mut self,
+        message: BitVec<u64, Lsb0>,
+        target: MessageTarget,
+        delivery: Delivery,
+    ) {
+        if let Some(client) = self.client.as_mut() {
+            client
                 .bind_mut()
-                .queue_message(message);
-        } else if self.server.is_some() {
-            self.server
-                .as_mut()
-                .unwrap()
+                .queue_message_to(message, target, delivery);
+        } else if let Some(server) = self.server.as_mut() {
+            server
+                .bind_mut()
+                .queue_message_from(message, SERVER_SENDER, target, delivery);
+        } else {
+            log_warn!(
+                Messages,
+                "tried to queue_message but no client or server is running"
+            );
+        }
+    }
+    // the tick messages sent now will be stamped with, 0 if we are not a server
+    fn get_tick(&self) -> u64 {
+        self.server.as_ref().map(|x| x.bind().tick).unwrap_or(0)
+    }
+    fn queue_message_from(
+        &mut self,
+        message: BitVec<u64, Lsb0>,
+        sender: u16,
+        target: MessageTarget,
+        delivery: Delivery,
+    ) {
+        if let Some(server) = self.server.as_mut() {
+            server
                 .bind_mut()
-                .queue_message(message);
+                .queue_message_from(message, sender, target, delivery);
         } else {
-            godot_warn!("tried to queue_message but no client or server is running");
+            log_warn!(
+                Messages,
+                "tried to queue_message_from but we are not a server"
+            );
         }
     }
     fn propogate_player_joined(&mut self, player: u16) {
-        godot_warn!("new player propogated");
+        log_debug!(Auth, "new player propogated");
         self.signals().player_joined().emit(player);
     }
     fn propogate_player_left(&mut self, player: u16) {
         self.signals().player_left().emit(player);
     }
+    fn propogate_connection_state_changed(&mut self, state: i64) {
+        self.signals().connection_state_changed().emit(state);
+    }
+    fn propogate_connected(&mut self) {
+        self.signals().connected().emit();
+    }
+    fn propogate_connection_failed(&mut self, reason: GString) {
+        self.signals().connection_failed().emit(&reason);
+    }
+    fn propogate_disconnected(&mut self, reason: GString) {
+        self.signals().disconnected().emit(&reason);
+    }
+    fn propogate_player_reconnecting(&mut self, player: u16) {
+        self.signals().player_reconnecting().emit(player);
+    }
+    fn propogate_player_resumed(&mut self, player: u16) {
+        self.signals().player_resumed().emit(player);
+    }
+    fn propogate_player_info_changed(&mut self, player: u16) {
+        self.signals().player_info_changed().emit(player);
+    }
+    fn propogate_player_info_removed(&mut self, player: u16) {
+        self.signals().player_info_removed().emit(player);
+    }
+    fn propogate_moderation_event(&mut self, player: u16, action: GString, reason: GString) {
+        self.signals()
+            .moderation_event()
+            .emit(player, &action, &reason);
+    }
+    fn propogate_packet_limit_exceeded(&mut self, player: u16, violation: GString) {
+        self.signals()
+            .packet_limit_exceeded()
+            .emit(player, &violation);
+    }
     #[signal]
     pub fn player_joined(player: u16);
     #[signal]
     pub fn player_left(player: u16);
+    // only emitted on the server, clients see this through the reconnecting flag in the player's info
+    #[signal]
+    pub fn player_reconnecting(player: u16);
+    #[signal]
+    pub fn player_resumed(player: u16);
+    #[signal]
+    pub fn player_info_changed(player: u16);
+    #[signal]
+    pub fn player_info_removed(player: u16);
+    // client only, state is one of the CONNECTION_STATE constants
+    #[signal]
+    pub fn connection_state_changed(state: i64);
+    #[signal]
+    pub fn connected();
+    // the connection ended before we were fully connected, including failed session resumes
+    #[signal]
+    pub fn connection_failed(reason: GString);
+    #[signal]
+    pub fn disconnected(reason: GString);
+    // action is one of kicked, banned, muted, unmuted, deafened or undeafened
+    #[signal]
+    pub fn moderation_event(player: u16, action: GString, reason: GString);
+    // server only, a client went over one of the packet limits
+    #[signal]
+    pub fn packet_limit_exceeded(player: u16, violation: GString);
+}
+#[godot_api]
+impl INode for NetNodeManager {
+    fn ready(&mut self) {
+        self.apply_log_level(self.log_level);
+        let output = match self.log_output {
+            1 => Output::Stderr,
+            2 => {
+                let path = ProjectSettings::singleton()
+                    .globalize_path(&self.log_file_path)
+                    .to_string();
+                match OpenOptions::new().create(true).append(true).open(&path) {
+                    Ok(file) => Output::File(file),
+                    Err(error) => {
+                        log_error!(Sync, "failed to open log file {}: {}", path, error);
+                        Output::Console
+                    }
+                }
+            }
+            _ => Output::Console,
+        };
+        logging::set_output(output);
+        // only when running from the editor, lets the debugger plugin in addons/butterfly_debugger ask for dumps
+        let mut debugger = EngineDebugger::singleton();
+        if debugger.is_active() {
+            debugger.register_message_capture(
+                DEBUGGER_CAPTURE,
+                &Callable::from_object_method(&self.to_gd(), "on_debugger_message"),
+            );
+        }
+    }
+}
+fn ban_duration(seconds: f64) -> Option<u64> {
+    (seconds > 0.0).then(|| seconds.ceil() as u64)
 }
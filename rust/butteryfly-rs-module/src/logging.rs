@@ -0,0 +1,206 @@
+// logging for the networking code, every message has a category and a level so noisy ones can be filtered
+// messages logged over and over from the same place are rate limited
+use godot::prelude::*;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// each call site can log this many messages per window, the rest are counted and reported when the next window starts
+const RATE_LIMIT: u32 = 10;
+const RATE_WINDOW: Duration = Duration::from_secs(10);
+
+// values are exposed to gdscript through NetNodeManager's LOG_LEVEL constants
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 0,
+    Warn = 1,
+    Info = 2,
+    Debug = 3,
+}
+impl Level {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+        }
+    }
+    // -1 turns logging off
+    pub fn from_setting(value: i64) -> Result<Option<Self>, String> {
+        match value {
+            -1 => Ok(None),
+            0 => Ok(Some(Level::Error)),
+            1 => Ok(Some(Level::Warn)),
+            2 => Ok(Some(Level::Info)),
+            3 => Ok(Some(Level::Debug)),
+            _ => Err(format!("{} is not a log level", value)),
+        }
+    }
+}
+
+// values are exposed to gdscript through NetNodeManager's LOG_CATEGORY constants
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    // connections, packets and acks
+    Transport = 0,
+    // networked nodes and their values
+    Sync = 1,
+    Messages = 2,
+    Voice = 3,
+    // players joining and moderation
+    Auth = 4,
+}
+impl Category {
+    const COUNT: usize = 5;
+    fn as_str(&self) -> &'static str {
+        match self {
+            Category::Transport => "transport",
+            Category::Sync => "sync",
+            Category::Messages => "messages",
+            Category::Voice => "voice",
+            Category::Auth => "auth",
+        }
+    }
+}
+impl TryFrom<i64> for Category {
+    type Error = String;
+    fn try_from(value: i64) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Category::Transport),
+            1 => Ok(Category::Sync),
+            2 => Ok(Category::Messages),
+            3 => Ok(Category::Voice),
+            4 => Ok(Category::Auth),
+            _ => Err(format!("{} is not a log category", value)),
+        }
+    }
+}
+
+// headless servers usually want stderr or a file rather than the godot console
+pub enum Output {
+    Console,
+    Stderr,
+    File(File),
+}
+
+struct CallSite {
+    window_start: Instant,
+    count: u32,
+    suppressed: u32,
+}
+
+struct Logger {
+    // the most detailed level logged for each category, None if the category is off
+    levels: [Option<Level>; Category::COUNT],
+    output: Output,
+    call_sites: BTreeMap<(&'static str, u32), CallSite>,
+}
+impl Logger {
+    fn emit(&mut self, level: Level, category: Category, message: &str) {
+        match &mut self.output {
+            Output::Console => match level {
+                Level::Error => godot_error!("[{}] {}", category.as_str(), message),
+                Level::Warn => godot_warn!("[{}] {}", category.as_str(), message),
+                Level::Info | Level::Debug => godot_print!("[{}] {}", category.as_str(), message),
+            },
+            Output::Stderr => eprintln!("{}", line(level, category, message)),
+            Output::File(file) => {
+                let _ = writeln!(file, "{}", line(level, category, message));
+            }
+        }
+    }
+}
+
+static LOGGER: Mutex<Logger> = Mutex::new(Logger {
+    levels: [Some(Level::Warn); Category::COUNT],
+    output: Output::Console,
+    call_sites: BTreeMap::new(),
+});
+
+fn line(level: Level, category: Category, message: &str) -> String {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64();
+    format!(
+        "{:.3} {} {}: {}",
+        time,
+        level.as_str(),
+        category.as_str(),
+        message
+    )
+}
+
+// a category of None sets every category
+pub fn set_level(category: Option<Category>, level: Option<Level>) {
+    let mut logger = LOGGER.lock().unwrap_or_else(|x| x.into_inner());
+    match category {
+        Some(category) => logger.levels[category as usize] = level,
+        None => logger.levels = [level; Category::COUNT],
+    }
+}
+pub fn set_output(output: Output) {
+    LOGGER.lock().unwrap_or_else(|x| x.into_inner()).output = output;
+}
+
+// used by the log macros, the message is only formatted if it is going to be logged
+pub fn write(level: Level, category: Category, site: (&'static str, u32), message: fmt::Arguments) {
+    let mut logger = LOGGER.lock().unwrap_or_else(|x| x.into_inner());
+    if logger.levels[category as usize].is_none_or(|x| level > x) {
+        return;
+    }
+    let now = Instant::now();
+    let call_site = logger.call_sites.entry(site).or_insert(CallSite {
+        window_start: now,
+        count: 0,
+        suppressed: 0,
+    });
+    let mut suppressed = 0;
+    if now.duration_since(call_site.window_start) >= RATE_WINDOW {
+        suppressed = call_site.suppressed;
+        *call_site = CallSite {
+            window_start: now,
+            count: 0,
+            suppressed: 0,
+        };
+    }
+    if call_site.count >= RATE_LIMIT {
+        call_site.suppressed += 1;
+        return;
+    }
+    call_site.count += 1;
+    if suppressed > 0 {
+        let message = format!("{} (suppressed {} more like this)", message, suppressed);
+        logger.emit(level, category, &message);
+    } else {
+        logger.emit(level, category, &message.to_string());
+    }
+}
+
+macro_rules! log_at {
+    ($level:ident, $category:ident, $($arg:tt)*) => {
+        $crate::logging::write(
+            $crate::logging::Level::$level,
+            $crate::logging::Category::$category,
+            (file!(), line!()),
+            format_args!($($arg)*),
+        )
+    };
+}
+macro_rules! log_error {
+    ($category:ident, $($arg:tt)*) => { $crate::logging::log_at!(Error, $category, $($arg)*) };
+}
+macro_rules! log_warn {
+    ($category:ident, $($arg:tt)*) => { $crate::logging::log_at!(Warn, $category, $($arg)*) };
+}
+macro_rules! log_info {
+    ($category:ident, $($arg:tt)*) => { $crate::logging::log_at!(Info, $category, $($arg)*) };
+}
+macro_rules! log_debug {
+    ($category:ident, $($arg:tt)*) => { $crate::logging::log_at!(Debug, $category, $($arg)*) };
+}
+pub(crate) use {log_at, log_debug, log_error, log_info, log_warn};
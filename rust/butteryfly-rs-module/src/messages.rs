@@ -1,19 +1,224 @@
 use crate::{
     NetNodeManager,
+    logging::{log_error, log_warn},
+    moderation::ModerationEvent,
     net_nodes::NetworkedNode,
+    object_ids::ObjectId,
+    players::PlayerInfo,
+    rate_limit::{LimitPolicy, MessageLimits},
+    registry,
+    schema::{MessageField, Schema},
     serializer::{self, NetworkedValueTypes},
+    wire::{self, DecodeError},
 };
 use bitvec::prelude::*;
 use godot::prelude::*;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
 
 const BYTE: usize = 8;
 const BYTES2: usize = 16;
+// message types used internally, these are handled before any user message handlers
+pub const MESSAGE_TYPE_ID_SYNC: u16 = 0;
+pub const MESSAGE_TYPE_PLAYER_INFO: u16 = u16::MAX;
+pub const MESSAGE_TYPE_PLAYER_REMOVED: u16 = u16::MAX - 1;
+pub const MESSAGE_TYPE_MODERATION: u16 = u16::MAX - 2;
+pub const MESSAGE_TYPE_REGISTRY: u16 = u16::MAX - 3;
+// only the server can send these, clients sending them are ignored
+pub fn is_reserved_message_type(message_type: u16) -> bool {
+    matches!(
+        message_type,
+        MESSAGE_TYPE_ID_SYNC
+            | MESSAGE_TYPE_PLAYER_INFO
+            | MESSAGE_TYPE_PLAYER_REMOVED
+            | MESSAGE_TYPE_MODERATION
+            | MESSAGE_TYPE_REGISTRY
+    )
+}
+// the sender id used for messages that come from the server itself
+pub const SERVER_SENDER: u16 = 0;
+// reliable ordered messages use channel 3, these are for handlers that dont need ordering or reliability
+pub const CHANNEL_MESSAGES_UNORDERED: u16 = 6;
+pub const CHANNEL_MESSAGES_UNRELIABLE: u16 = 7;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum DeliveryMode {
+    // a lost packet holds back every later message from the same peer until it is resent
+    #[default]
+    ReliableOrdered = 0,
+    ReliableUnordered = 1,
+    Unreliable = 2,
+}
+impl DeliveryMode {
+    pub fn channel(&self) -> u16 {
+        match self {
+            DeliveryMode::ReliableOrdered => 3,
+            DeliveryMode::ReliableUnordered => CHANNEL_MESSAGES_UNORDERED,
+            DeliveryMode::Unreliable => CHANNEL_MESSAGES_UNRELIABLE,
+        }
+    }
+}
+impl TryFrom<i64> for DeliveryMode {
+    type Error = ();
+    fn try_from(value: i64) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(DeliveryMode::ReliableOrdered),
+            1 => Ok(DeliveryMode::ReliableUnordered),
+            2 => Ok(DeliveryMode::Unreliable),
+            _ => Err(()),
+        }
+    }
+}
+// how a message should be delivered, expiry only applies to unreliable messages
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Delivery {
+    pub mode: DeliveryMode,
+    // unreliable messages still waiting to be sent after this long are dropped
+    pub expiry: Option<Duration>,
+}
+impl Delivery {
+    pub fn deadline(&self) -> Option<Instant> {
+        if self.mode != DeliveryMode::Unreliable {
+            return None;
+        }
+        self.expiry.map(|x| Instant::now() + x)
+    }
+}
+
+// tracks which reliable unordered packets have been handled so resends are only handled once
+#[derive(Debug, Default)]
+pub struct ReceivedPackets {
+    highest: u64,
+    received: HashSet<u64>,
+}
+impl ReceivedPackets {
+    const WINDOW: u64 = 1024;
+    // false if the packet was already handled or is too old to tell
+    pub fn first_time(&mut self, packet_number: u64) -> bool {
+        if packet_number + Self::WINDOW <= self.highest || !self.received.insert(packet_number) {
+            return false;
+        }
+        if packet_number > self.highest {
+            self.highest = packet_number;
+        }
+        if self.received.len() as u64 > Self::WINDOW * 2 {
+            let highest = self.highest;
+            self.received.retain(|x| x + Self::WINDOW > highest);
+        }
+        true
+    }
+}
+
+// who a message gets delivered to, clients send this to the server right after the message type
+#[derive(Debug, Clone, PartialEq)]
+pub enum MessageTarget {
+    Everyone,
+    // only processed by the server, never relayed
+    Server,
+    Players(Vec<u16>),
+}
+impl MessageTarget {
+    pub fn includes(&self, player: u16) -> bool {
+        match self {
+            MessageTarget::Everyone => true,
+            MessageTarget::Server => false,
+            MessageTarget::Players(players) => players.contains(&player),
+        }
+    }
+    pub fn encode(&self) -> BitVec<u64, Lsb0> {
+        let mut data: BitVec<u64, Lsb0> = BitVec::new();
+        match self {
+            MessageTarget::Everyone => data.extend(0u8.view_bits::<Lsb0>()),
+            MessageTarget::Server => data.extend(1u8.view_bits::<Lsb0>()),
+            MessageTarget::Players(players) => {
+                data.extend(2u8.view_bits::<Lsb0>());
+                // players past u8::MAX are dropped, send to everyone instead at that point
+                let count = players.len().min(u8::MAX as usize) as u8;
+                data.extend(count.view_bits::<Lsb0>());
+                for player in players.iter().take(count as usize) {
+                    data.extend(player.view_bits::<Lsb0>());
+                }
+            }
+        }
+        data
+    }
+    pub fn decode(data: &BitSlice<u64>, pointer: &mut usize) -> Option<Self> {
+        let kind: u8 = data.get(*pointer..*pointer + BYTE)?.load_le();
+        *pointer += BYTE;
+        match kind {
+            0 => Some(MessageTarget::Everyone),
+            1 => Some(MessageTarget::Server),
+            2 => {
+                let count: u8 = data.get(*pointer..*pointer + BYTE)?.load_le();
+                *pointer += BYTE;
+                let mut players = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    players.push(data.get(*pointer..*pointer + BYTES2)?.load_le());
+                    *pointer += BYTES2;
+                }
+                Some(MessageTarget::Players(players))
+            }
+            _ => None,
+        }
+    }
+}
+
+// details about a received message that are not part of its values
+#[derive(GodotClass)]
+#[class(init, base=RefCounted)]
+pub struct MessageContext {
+    // the player that sent the message, 0 for the server
+    #[var]
+    pub sender: u16,
+    // the server tick the message was received on
+    #[var]
+    pub tick: i64,
+    #[var]
+    pub reliable: bool,
+}
+impl MessageContext {
+    pub fn create(sender: u16, tick: u64, reliable: bool) -> Gd<Self> {
+        Gd::from_object(MessageContext {
+            sender,
+            tick: tick as i64,
+            reliable,
+        })
+    }
+}
 
 #[derive(GodotClass)]
 #[class(init, base=Node)]
 pub struct MessageHandler {
+    // hand picked type, only used when message_name is empty
     #[export]
     pub message_type: u16,
+    // handlers with a name are given a message type by the server instead, the name must be the same on every peer
+    #[export]
+    pub message_name: GString,
+    // per client limits for this message type enforced by the server, negative values use the server's defaults
+    #[export]
+    #[init(val = -1.0)]
+    pub rate_limit: f64,
+    #[export]
+    #[init(val = -1.0)]
+    pub rate_limit_burst: f64,
+    #[export]
+    #[init(val = -1)]
+    pub max_message_bytes: i64,
+    #[export(enum = (Default = -1, Drop = 0, Warn = 1, Kick = 2))]
+    #[init(val = -1)]
+    pub limit_policy: i64,
+    // frequent transient messages like grab updates can use the unordered or unreliable modes so they dont hold up others
+    #[export(enum = (ReliableOrdered = 0, ReliableUnordered = 1, Unreliable = 2))]
+    pub delivery_mode: i64,
+    // unreliable messages not sent within this many seconds are dropped, 0 to never drop them
+    #[export]
+    pub expiry_seconds: f64,
+    // the fields of the message, when set values are encoded and checked from this instead of get_value_type
+    #[export]
+    pub schema: Array<Gd<MessageField>>,
+    // the schema is checked once when entering the tree, None if no schema is set
+    compiled_schema: Option<Result<Schema, String>>,
     network_manager: Option<Gd<NetNodeManager>>,
     base: Base<Node>,
 }
@@ -24,27 +229,146 @@ pub impl MessageHandler {
         unimplemented!()
     }
     #[func(virtual)]
-    fn process_message(&mut self, _values: VariantArray) {
+    fn process_message(&mut self, _values: VariantArray, _context: Gd<MessageContext>) {
         unimplemented!()
     }
+    // called on the server when a client goes over this message type's limits, can be overridden to handle repeat offenders
+    #[func(virtual)]
+    fn limit_exceeded(&mut self, _player: u16, _violation: GString) {}
+    // called on the server for every message a client sends before anything is relayed
+    // return the values to relay them to everyone, changed values to relay those instead, or null to drop the message
+    // a message can be relayed to only some players by calling send_message_to from here and returning null
+    #[func(virtual)]
+    fn authorize_message(&mut self, _sender: u16, values: VariantArray) -> Variant {
+        values.to_variant()
+    }
+    // values can be a dictionary keyed by field name when the handler has a schema, types are only needed without one
     #[func]
-    fn send_message_final(&mut self, values: VariantArray, types: Array<i64>) {
-        if values.len() != types.len() {
-            godot_warn!("invalid call to send_message_final");
-            return;
+    fn send_message_final(&mut self, values: Variant, types: Array<i64>) {
+        self.send_message_with_target(values, types, MessageTarget::Everyone);
+    }
+    // the values of a received message keyed by their field names, only works for handlers with a schema
+    #[func]
+    fn values_to_dictionary(&self, values: VariantArray) -> Dictionary {
+        match &self.compiled_schema {
+            Some(Ok(schema)) => schema.to_dictionary(&values),
+            _ => Dictionary::new(),
         }
-        if types
-            .iter_shared()
-            .any(|x| NetworkedValueTypes::try_from(x).is_err())
-        {
-            godot_warn!("invalid call to send_message_final");
+    }
+    // delivers the message to just the given players, messages from clients go through the server first
+    #[func]
+    fn send_message_to(
+        &mut self,
+        player_ids: PackedInt32Array,
+        values: Variant,
+        types: Array<i64>,
+    ) {
+        let players = player_ids
+            .as_slice()
+            .iter()
+            .filter_map(|x| {
+                u16::try_from(*x)
+                    .inspect_err(|_| log_warn!(Messages, "skipped invalid player id {}", x))
+                    .ok()
+            })
+            .collect();
+        self.send_message_with_target(values, types, MessageTarget::Players(players));
+    }
+    // the message is only processed by the server and never relayed to other players
+    #[func]
+    fn send_message_to_server(&mut self, values: Variant, types: Array<i64>) {
+        self.send_message_with_target(values, types, MessageTarget::Server);
+    }
+    fn send_message_with_target(
+        &mut self,
+        values: Variant,
+        types: Array<i64>,
+        target: MessageTarget,
+    ) {
+        if is_reserved_message_type(self.message_type) {
+            log_warn!(
+                Messages,
+                "message handler {} has no message type yet, the message was not sent",
+                self.message_name
+            );
             return;
         }
-
+        let packet = match self.encode_message(&values, &types) {
+            Ok(packet) => packet,
+            Err(error) => {
+                log_warn!(
+                    Messages,
+                    "invalid call to send a message with type {:#?}: {}",
+                    self.message_type,
+                    error
+                );
+                return;
+            }
+        };
+        let delivery = self.delivery();
+        let reliable = delivery.mode != DeliveryMode::Unreliable;
+        let mut manager = self.network_manager.clone().unwrap();
+        if manager.bind().is_server() {
+            // the server is not a player so only messages for everyone or the server are processed here
+            if matches!(target, MessageTarget::Everyone | MessageTarget::Server) {
+                let context =
+                    MessageContext::create(SERVER_SENDER, manager.bind().get_tick(), reliable);
+                if let Err(error) =
+                    self.handle_message(packet.as_bitslice(), &mut BYTES2.clone(), context)
+                {
+                    log_warn!(Messages, "failed to process our own message: {}", error);
+                }
+            }
+            if target == MessageTarget::Server {
+                return;
+            }
+        }
+        manager
+            .bind_mut()
+            .queue_message_to(packet, target, delivery);
+    }
+    fn encode_message(
+        &self,
+        values: &Variant,
+        types: &Array<i64>,
+    ) -> Result<BitVec<u64, Lsb0>, String> {
+        let schema = match &self.compiled_schema {
+            Some(Ok(schema)) => schema,
+            Some(Err(error)) => return Err(error.clone()),
+            None => {
+                let values = values
+                    .try_to::<VariantArray>()
+                    .map_err(|_| "values must be an array for handlers without a schema")?;
+                return self
+                    .encode_with_types(&values, types)
+                    .ok_or_else(|| "values dont match their types".to_string());
+            }
+        };
+        let body = if let Ok(values) = values.try_to::<Dictionary>() {
+            schema.encode_dictionary(&values)?
+        } else if let Ok(values) = values.try_to::<VariantArray>() {
+            schema.encode(&values.iter_shared().collect::<Vec<Variant>>())?
+        } else {
+            return Err("values must be an array or a dictionary".to_string());
+        };
+        let mut packet: BitVec<u64, Lsb0> = BitVec::new();
+        packet.extend(self.message_type.view_bits::<Lsb0>());
+        packet.extend(body);
+        Ok(packet)
+    }
+    fn encode_with_types(
+        &self,
+        values: &VariantArray,
+        types: &Array<i64>,
+    ) -> Option<BitVec<u64, Lsb0>> {
+        if values.len() != types.len() {
+            return None;
+        }
         let types = types
             .iter_shared()
-            .map(|x| NetworkedValueTypes::try_from(x).unwrap())
-            .collect::<Vec<NetworkedValueTypes>>();
+            .map(NetworkedValueTypes::try_from)
+            .collect::<Result<Vec<NetworkedValueTypes>, _>>()
+            .ok()?;
         let mut packet: BitVec<u64, Lsb0> = BitVec::new();
         packet.extend(self.message_type.view_bits::<Lsb0>());
         for value in values.iter_shared().enumerate() {
@@ -53,43 +377,155 @@ pub impl MessageHandler {
                 &types[value.0],
             ));
         }
-
-        if self
-            .network_manager
-            .as_mut()
-            .unwrap()
-            .bind_mut()
-            .is_server()
-        {
-            self.handle_message(packet.clone().as_bitslice(), &mut BYTES2.clone());
+        Some(packet)
+    }
+    // encodes values using the types the handler reports for them, used when the server relays a rewritten message
+    fn encode_values(&mut self, values: &VariantArray) -> Option<BitVec<u64, Lsb0>> {
+        let mut packet: BitVec<u64, Lsb0> = BitVec::new();
+        packet.extend(self.message_type.view_bits::<Lsb0>());
+        if let Some(schema) = &self.compiled_schema {
+            let body = schema
+                .as_ref()
+                .ok()?
+                .encode(&values.iter_shared().collect::<Vec<Variant>>());
+            match body {
+                Ok(body) => packet.extend(body),
+                Err(error) => {
+                    log_warn!(Messages, "{}", error);
+                    return None;
+                }
+            }
+            return Some(packet);
         }
-        self.network_manager
-            .as_mut()
-            .unwrap()
-            .bind_mut()
-            .queue_message(packet);
+        let mut last_value = Variant::nil();
+        for (idx, value) in values.iter_shared().enumerate() {
+            let value_type =
+                NetworkedValueTypes::try_from(self.get_value_type(last_value, idx as i64)).ok()?;
+            packet.extend(serializer::encode_with_known_type(&value, &value_type));
+            last_value = value;
+        }
+        Some(packet)
     }
-    pub fn handle_message(&mut self, packet: &BitSlice<u64, Lsb0>, pointer: &mut usize) {
+    fn decode_values(
+        &mut self,
+        packet: &BitSlice<u64, Lsb0>,
+        pointer: &mut usize,
+    ) -> Result<VariantArray, String> {
+        if let Some(schema) = &self.compiled_schema {
+            return schema
+                .as_ref()
+                .map_err(|x| x.clone())?
+                .decode(packet, pointer);
+        }
         let mut idx = 0;
         let mut last_value = Variant::nil();
         let mut values: VariantArray = VariantArray::new();
         while *pointer < packet.len() {
-            let value_type =
-                &NetworkedValueTypes::try_from(self.get_value_type(last_value, idx)).unwrap();
-            last_value = serializer::decode_with_known_type(packet, pointer, value_type).unwrap();
+            let value_type = NetworkedValueTypes::try_from(self.get_value_type(last_value, idx))?;
+            last_value = serializer::decode_with_known_type(packet, pointer, &value_type)
+                .ok_or_else(|| format!("value {} could not be decoded", idx))?;
             values.push(&last_value);
             idx += 1;
         }
-        self.apply_deferred(|this| this.process_message(values));
+        Ok(values)
+    }
+    pub fn handle_message(
+        &mut self,
+        packet: &BitSlice<u64, Lsb0>,
+        pointer: &mut usize,
+        context: Gd<MessageContext>,
+    ) -> Result<(), String> {
+        let values = self.decode_values(packet, pointer)?;
+        self.apply_deferred(|this| this.process_message(values, context));
+        Ok(())
+    }
+    // the server is bound while packets are handled so authorization happens deferred, like process_message
+    pub fn handle_client_message(
+        &mut self,
+        packet: &BitSlice<u64, Lsb0>,
+        pointer: &mut usize,
+        context: Gd<MessageContext>,
+        target: MessageTarget,
+    ) -> Result<(), String> {
+        let values = self.decode_values(packet, pointer)?;
+        self.apply_deferred(move |this| this.authorize_and_relay(values, context, target));
+        Ok(())
+    }
+    fn authorize_and_relay(
+        &mut self,
+        values: VariantArray,
+        context: Gd<MessageContext>,
+        target: MessageTarget,
+    ) {
+        let sender = context.bind().sender;
+        let decision = self.authorize_message(sender, values);
+        if decision.is_nil() {
+            return;
+        }
+        let Ok(values) = decision.try_to::<VariantArray>() else {
+            log_warn!(
+                Messages,
+                "authorize_message for message type {:#?} must return an array or null",
+                self.message_type
+            );
+            return;
+        };
+        let Some(packet) = self.encode_values(&values) else {
+            log_warn!(
+                Messages,
+                "failed to encode relayed message with type {:#?}",
+                self.message_type
+            );
+            return;
+        };
+        // messages for specific players are only routed through the server, not processed by it
+        let process_here = !matches!(target, MessageTarget::Players(_));
+        if target != MessageTarget::Server {
+            let delivery = self.delivery();
+            self.network_manager
+                .as_mut()
+                .unwrap()
+                .bind_mut()
+                .queue_message_from(packet, sender, target, delivery);
+        }
+        if process_here {
+            self.process_message(values, context);
+        }
+    }
+    pub fn limits(&self, defaults: &MessageLimits) -> MessageLimits {
+        MessageLimits {
+            rate: if self.rate_limit < 0.0 {
+                defaults.rate
+            } else {
+                self.rate_limit
+            },
+            burst: if self.rate_limit_burst < 0.0 {
+                defaults.burst
+            } else {
+                self.rate_limit_burst
+            },
+            max_bytes: usize::try_from(self.max_message_bytes).unwrap_or(defaults.max_bytes),
+            policy: LimitPolicy::try_from(self.limit_policy).unwrap_or(defaults.policy),
+        }
+    }
+    pub fn delivery(&self) -> Delivery {
+        Delivery {
+            mode: DeliveryMode::try_from(self.delivery_mode).unwrap_or_default(),
+            expiry: (self.expiry_seconds > 0.0)
+                .then(|| Duration::from_secs_f64(self.expiry_seconds)),
+        }
+    }
+    pub fn notify_limit_exceeded(&mut self, player: u16, violation: String) {
+        self.apply_deferred(move |this| this.limit_exceeded(player, GString::from(&violation)));
     }
     pub fn create_id_sync_message(
         object: Gd<Node>,
-        object_id: u16,
+        object_id: ObjectId,
         owner_id: Option<u16>,
     ) -> BitVec<u64, Lsb0> {
         let mut packet: BitVec<u64, Lsb0> = BitVec::new();
-        packet.extend(0u16.view_bits::<Lsb0>());
-        packet.extend(object_id.view_bits::<Lsb0>());
+        packet.extend(MESSAGE_TYPE_ID_SYNC.view_bits::<Lsb0>());
+        wire::push_object_id(&mut packet, object_id);
         packet.extend(owner_id.unwrap_or(0).view_bits::<Lsb0>());
         let mut index_path: Vec<u8> = Vec::with_capacity(8);
         index_path.push(object.get_index() as u8);
@@ -109,56 +545,113 @@ pub impl MessageHandler {
         }
         packet
     }
+    pub fn create_player_info_message(info: &PlayerInfo) -> BitVec<u64, Lsb0> {
+        let mut packet: BitVec<u64, Lsb0> = BitVec::new();
+        packet.extend(MESSAGE_TYPE_PLAYER_INFO.view_bits::<Lsb0>());
+        packet.extend(info.encode());
+        packet
+    }
+    pub fn create_player_removed_message(player: u16) -> BitVec<u64, Lsb0> {
+        let mut packet: BitVec<u64, Lsb0> = BitVec::new();
+        packet.extend(MESSAGE_TYPE_PLAYER_REMOVED.view_bits::<Lsb0>());
+        packet.extend(player.view_bits::<Lsb0>());
+        packet
+    }
+    pub fn create_registry_message(name: &str, message_type: u16) -> BitVec<u64, Lsb0> {
+        let mut packet: BitVec<u64, Lsb0> = BitVec::new();
+        packet.extend(MESSAGE_TYPE_REGISTRY.view_bits::<Lsb0>());
+        packet.extend(registry::encode_entry(name, message_type));
+        packet
+    }
+    pub fn create_moderation_message(event: &ModerationEvent) -> BitVec<u64, Lsb0> {
+        let mut packet: BitVec<u64, Lsb0> = BitVec::new();
+        packet.extend(MESSAGE_TYPE_MODERATION.view_bits::<Lsb0>());
+        packet.extend(event.encode());
+        packet
+    }
+    // returns the networked node that was given the id, if it was one
     pub fn handle_id_sync_message(
-        message: &BitSlice<u64, Lsb0>,
-        pointer: &mut usize,
+        sync: IdSync,
         root_object: Gd<Node>,
-    ) {
-        let mut object = Some(root_object);
-        let id: u16 = message[*pointer..*pointer + BYTES2].load_le();
-        *pointer += BYTES2;
-        let owner_id: u16 = message[*pointer..*pointer + BYTES2].load_le();
-        *pointer += BYTES2;
-        while let Some(index) = message.get(*pointer..*pointer + BYTE) {
-            let index: u8 = index.load_le();
-            object = object.unwrap().get_child(index as i32);
-            if object.is_none() {
-                godot_warn!("failed to apply id to object");
-                return;
-            }
-            *pointer += BYTE;
+    ) -> Option<Gd<NetworkedNode>> {
+        let mut object = root_object;
+        for index in sync.path {
+            let Some(child) = object.get_child(index as i32) else {
+                log_warn!(Sync, "failed to apply id to object");
+                return None;
+            };
+            object = child;
         }
-        let mut object = object.unwrap();
         let casted_object = object.try_cast::<NetworkedNode>();
         if let Ok(mut object) = casted_object {
-            object.bind_mut().objectid = id;
-            object.bind_mut().owner_id = owner_id;
-        } else {
-            object = casted_object.unwrap_err();
-            let casted_object = object.try_cast::<MessageHandler>();
-            if let Ok(mut object) = casted_object {
-                object.bind_mut().message_type = id;
-            }
+            object.bind_mut().objectid = sync.id;
+            object.bind_mut().owner_id = sync.owner_id;
+            return Some(object);
         }
+        object = casted_object.unwrap_err();
+        let casted_object = object.try_cast::<MessageHandler>();
+        if let Ok(mut object) = casted_object
+            && let Ok(message_type) = u16::try_from(sync.id.index())
+        {
+            object.bind_mut().message_type = message_type;
+        }
+        None
+    }
+}
+// assigns an id to a node, the node is found by following child indexes down from the root
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdSync {
+    pub id: ObjectId,
+    pub owner_id: u16,
+    pub path: Vec<u8>,
+}
+impl IdSync {
+    // the path takes up the rest of the message
+    pub fn decode(message: &BitSlice<u64>, pointer: &mut usize) -> Result<Self, DecodeError> {
+        let id = wire::read_object_id(message, pointer, "synced id")?;
+        let owner_id = wire::read_u16(message, pointer, "synced owner id")?;
+        let mut path = Vec::new();
+        while let Some(index) = message.get(*pointer..*pointer + BYTE) {
+            path.push(index.load_le());
+            *pointer += BYTE;
+        }
+        Ok(IdSync { id, owner_id, path })
     }
 }
 #[godot_api]
 impl INode for MessageHandler {
     fn enter_tree(&mut self) {
+        self.compiled_schema = (!self.schema.is_empty()).then(|| {
+            Schema::from_fields(&self.schema).inspect_err(|error| {
+                log_error!(
+                    Messages,
+                    "invalid schema for message handler {}: {}",
+                    self.base().get_name(),
+                    error
+                )
+            })
+        });
         self.network_manager = Some(
             self.base()
                 .get_node_as::<NetNodeManager>("/root/NetworkManager"),
         );
-        self.base()
+        let name = self.message_name.to_string();
+        let registered_type = self
+            .base()
             .get_node_as::<NetNodeManager>("/root/NetworkManager")
             .bind_mut()
-            .register_message_handler(self.to_gd(), self.message_type);
+            .register_message_handler(self.to_gd(), self.message_type, name);
+        if !self.message_name.is_empty() {
+            // 0 until the server tells us which type to use
+            self.message_type = registered_type.unwrap_or(MESSAGE_TYPE_ID_SYNC);
+        }
     }
     fn exit_tree(&mut self) {
+        let handler = self.to_gd();
         self.network_manager
             .as_mut()
             .unwrap()
             .bind_mut()
-            .unregister_message_handler(self.message_type);
+            .unregister_message_handler(handler);
     }
 }
@@ -0,0 +1,341 @@
+// prometheus metrics for dedicated servers, only built with the metrics feature
+// the server records into ServerMetrics every tick and renders the text once a second, a background thread serves the last render
+use crate::stats::PeerStats;
+use std::fmt::Write;
+use std::io::{self, Read, Write as _};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// prometheus scrapes every 15 seconds by default so there is no point rendering every tick
+const RENDER_INTERVAL: Duration = Duration::from_secs(1);
+// upper bounds in seconds, a whole tick at 60hz is about 16ms
+const DURATION_BUCKETS: [f64; 10] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.016, 0.025, 0.05,
+];
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+const MAX_REQUEST_BYTES: usize = 1024;
+
+#[derive(Debug, Default)]
+pub struct Histogram {
+    // observations in each bucket, the last one is everything above the largest bound
+    counts: [u64; DURATION_BUCKETS.len() + 1],
+    sum: f64,
+}
+impl Histogram {
+    pub fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let bucket = DURATION_BUCKETS
+            .iter()
+            .position(|x| seconds <= *x)
+            .unwrap_or(DURATION_BUCKETS.len());
+        self.counts[bucket] += 1;
+        self.sum += seconds;
+    }
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        header(out, name, help, "histogram");
+        let mut cumulative = 0;
+        for (bound, count) in DURATION_BUCKETS.iter().zip(self.counts) {
+            cumulative += count;
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+        }
+        cumulative += self.counts[DURATION_BUCKETS.len()];
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, cumulative);
+        let _ = writeln!(out, "{}_sum {}", name, self.sum);
+        let _ = writeln!(out, "{}_count {}", name, cumulative);
+    }
+}
+
+// values that are read off the server when rendering rather than recorded every tick
+pub struct ServerGauges {
+    pub tick: u64,
+    pub players: usize,
+    pub reconnecting_players: usize,
+    pub networked_nodes: usize,
+}
+
+// shared with the exporter thread
+#[derive(Default)]
+struct Exported {
+    text: Mutex<String>,
+    stopped: AtomicBool,
+}
+
+pub struct ServerMetrics {
+    pub tick_server: Histogram,
+    pub send_packets: Histogram,
+    pub voice_mixing: Histogram,
+    last_render: Option<Instant>,
+    exported: Arc<Exported>,
+    address: SocketAddr,
+}
+impl ServerMetrics {
+    // metrics are only served on localhost, anything else should go through a proxy or the scraper's own agent
+    pub fn start(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        let address = listener.local_addr()?;
+        let exported = Arc::new(Exported::default());
+        let thread_exported = exported.clone();
+        thread::Builder::new()
+            .name("butterfly metrics".to_string())
+            .spawn(move || serve(listener, thread_exported))?;
+        Ok(ServerMetrics {
+            tick_server: Histogram::default(),
+            send_packets: Histogram::default(),
+            voice_mixing: Histogram::default(),
+            last_render: None,
+            exported,
+            address,
+        })
+    }
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+    pub fn render_due(&self, now: Instant) -> bool {
+        self.last_render
+            .is_none_or(|x| now.duration_since(x) >= RENDER_INTERVAL)
+    }
+    pub fn render<'a>(
+        &mut self,
+        now: Instant,
+        gauges: ServerGauges,
+        clients: impl Iterator<Item = (u16, &'a PeerStats)>,
+    ) {
+        self.last_render = Some(now);
+        let mut out = String::new();
+        header(
+            &mut out,
+            "butterfly_ticks_total",
+            "physics ticks since the server started",
+            "counter",
+        );
+        let _ = writeln!(out, "butterfly_ticks_total {}", gauges.tick);
+        header(&mut out, "butterfly_players", "connected players", "gauge");
+        let _ = writeln!(out, "butterfly_players {}", gauges.players);
+        header(
+            &mut out,
+            "butterfly_reconnecting_players",
+            "dropped players whose session can still be resumed",
+            "gauge",
+        );
+        let _ = writeln!(
+            out,
+            "butterfly_reconnecting_players {}",
+            gauges.reconnecting_players
+        );
+        header(
+            &mut out,
+            "butterfly_networked_nodes",
+            "registered networked nodes",
+            "gauge",
+        );
+        let _ = writeln!(out, "butterfly_networked_nodes {}", gauges.networked_nodes);
+        self.tick_server.render(
+            &mut out,
+            "butterfly_tick_server_seconds",
+            "time spent receiving and handling packets each tick",
+        );
+        self.send_packets.render(
+            &mut out,
+            "butterfly_send_packets_seconds",
+            "time spent building and sending packets each tick",
+        );
+        self.voice_mixing.render(
+            &mut out,
+            "butterfly_voice_mixing_seconds",
+            "time spent decoding, mixing and encoding voice each tick",
+        );
+        render_clients(&mut out, clients.collect());
+        *self.exported.text.lock().unwrap_or_else(|x| x.into_inner()) = out;
+    }
+}
+impl Drop for ServerMetrics {
+    fn drop(&mut self) {
+        // the exporter thread is blocked waiting for a connection, give it one so it sees it has been stopped
+        self.exported.stopped.store(true, Ordering::Relaxed);
+        let _ = TcpStream::connect_timeout(&self.address, REQUEST_TIMEOUT);
+    }
+}
+
+fn render_clients(out: &mut String, clients: Vec<(u16, &PeerStats)>) {
+    type ChannelCounts = fn(&PeerStats) -> &std::collections::HashMap<u16, u64>;
+    let by_channel: [(&str, &str, ChannelCounts); 2] = [
+        (
+            "butterfly_client_bytes_sent_total",
+            "bytes sent to each player by channel including headers",
+            PeerStats::bytes_sent,
+        ),
+        (
+            "butterfly_client_bytes_received_total",
+            "bytes received from each player by channel including headers",
+            PeerStats::bytes_received,
+        ),
+    ];
+    for (name, help, counts) in by_channel {
+        header(out, name, help, "counter");
+        for (player, stats) in &clients {
+            let mut channels: Vec<_> = counts(stats).iter().collect();
+            channels.sort();
+            for (channel, bytes) in channels {
+                let _ = writeln!(
+                    out,
+                    "{}{{player=\"{}\",channel=\"{}\"}} {}",
+                    name, player, channel, bytes
+                );
+            }
+        }
+    }
+    type Value = fn(&PeerStats) -> f64;
+    let per_client: [(&str, &str, &str, Value); 6] = [
+        (
+            "butterfly_client_packets_sent_total",
+            "packets sent to each player",
+            "counter",
+            |x| x.packets_sent() as f64,
+        ),
+        (
+            "butterfly_client_packets_received_total",
+            "packets received from each player",
+            "counter",
+            |x| x.packets_received() as f64,
+        ),
+        (
+            "butterfly_client_resends_total",
+            "reliable packets resent to each player",
+            "counter",
+            |x| x.resends() as f64,
+        ),
+        (
+            "butterfly_client_rtt_seconds",
+            "smoothed round trip time of reliable packets, NaN until one is acked",
+            "gauge",
+            |x| x.rtt().map_or(f64::NAN, |x| x.as_secs_f64()),
+        ),
+        (
+            "butterfly_client_jitter_seconds",
+            "variation in channel 1 latency",
+            "gauge",
+            |x| x.jitter().as_secs_f64(),
+        ),
+        (
+            "butterfly_client_packet_loss",
+            "fraction of channel 1 packets lost in the last full window",
+            "gauge",
+            PeerStats::loss,
+        ),
+    ];
+    for (name, help, kind, value) in per_client {
+        header(out, name, help, kind);
+        for (player, stats) in &clients {
+            let _ = writeln!(out, "{}{{player=\"{}\"}} {}", name, player, value(stats));
+        }
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn serve(listener: TcpListener, exported: Arc<Exported>) {
+    for stream in listener.incoming() {
+        if exported.stopped.load(Ordering::Relaxed) {
+            return;
+        }
+        // a scraper that misbehaves only costs it its own request
+        if let Ok(mut stream) = stream {
+            let _ = respond(&mut stream, &exported);
+        }
+    }
+}
+
+fn respond(stream: &mut TcpStream, exported: &Exported) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    // only the request line matters, it can arrive over several reads
+    let mut request = [0; MAX_REQUEST_BYTES];
+    let mut length = 0;
+    while length < MAX_REQUEST_BYTES && !request[..length].windows(2).any(|x| x == b"\r\n") {
+        match stream.read(&mut request[length..])? {
+            0 => break,
+            read => length += read,
+        }
+    }
+    let request = &request[..length];
+    let is_metrics = request.starts_with(b"GET /metrics ") || request.starts_with(b"GET /metrics?");
+    let text = if is_metrics {
+        exported
+            .text
+            .lock()
+            .unwrap_or_else(|x| x.into_inner())
+            .clone()
+    } else {
+        String::new()
+    };
+    let status = if is_metrics {
+        "200 OK"
+    } else {
+        "404 Not Found"
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        text.len(),
+        text
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(address: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let mut histogram = Histogram::default();
+        histogram.observe(Duration::from_micros(50));
+        histogram.observe(Duration::from_micros(800));
+        histogram.observe(Duration::from_secs(1));
+        let mut out = String::new();
+        histogram.render(&mut out, "tick", "help");
+        assert!(out.contains("tick_bucket{le=\"0.0001\"} 1\n"));
+        assert!(out.contains("tick_bucket{le=\"0.001\"} 2\n"));
+        assert!(out.contains("tick_bucket{le=\"0.05\"} 2\n"));
+        assert!(out.contains("tick_bucket{le=\"+Inf\"} 3\n"));
+        assert!(out.contains("tick_count 3\n"));
+    }
+
+    #[test]
+    fn serves_the_last_render() {
+        let mut metrics = ServerMetrics::start(0).unwrap();
+        let mut stats = PeerStats::default();
+        stats.record_sent(1, 100);
+        let gauges = ServerGauges {
+            tick: 42,
+            players: 1,
+            reconnecting_players: 0,
+            networked_nodes: 3,
+        };
+        metrics.render(Instant::now(), gauges, [(7, &stats)].into_iter());
+        let response = get(metrics.address(), "/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("butterfly_ticks_total 42\n"));
+        assert!(
+            response
+                .contains("butterfly_client_bytes_sent_total{player=\"7\",channel=\"1\"} 100\n")
+        );
+        assert!(get(metrics.address(), "/").starts_with("HTTP/1.1 404"));
+        assert!(!metrics.render_due(Instant::now()));
+    }
+}
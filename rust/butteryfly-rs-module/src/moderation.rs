@@ -0,0 +1,247 @@
+// server side moderation, bans are kept in a plain text file so they survive restarts
+use crate::logging::log_warn;
+use crate::serializer::{self, NetworkedValueTypes};
+use bitvec::prelude::*;
+use godot::prelude::*;
+use std::fs;
+use std::io::ErrorKind;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ModerationAction {
+    Kicked = 0,
+    Banned = 1,
+    Muted = 2,
+    Unmuted = 3,
+    Deafened = 4,
+    Undeafened = 5,
+}
+impl ModerationAction {
+    // the name gdscript sees in the moderation_event signal
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModerationAction::Kicked => "kicked",
+            ModerationAction::Banned => "banned",
+            ModerationAction::Muted => "muted",
+            ModerationAction::Unmuted => "unmuted",
+            ModerationAction::Deafened => "deafened",
+            ModerationAction::Undeafened => "undeafened",
+        }
+    }
+}
+impl TryFrom<u8> for ModerationAction {
+    type Error = ();
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ModerationAction::Kicked),
+            1 => Ok(ModerationAction::Banned),
+            2 => Ok(ModerationAction::Muted),
+            3 => Ok(ModerationAction::Unmuted),
+            4 => Ok(ModerationAction::Deafened),
+            5 => Ok(ModerationAction::Undeafened),
+            _ => Err(()),
+        }
+    }
+}
+
+// replicated to clients so they can show what happened to a player
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModerationEvent {
+    pub player: u16,
+    pub action: ModerationAction,
+    pub reason: String,
+}
+impl ModerationEvent {
+    pub fn encode(&self) -> BitVec<u64, Lsb0> {
+        let mut data: BitVec<u64, Lsb0> = BitVec::new();
+        data.extend(self.player.view_bits::<Lsb0>());
+        data.extend((self.action as u8).view_bits::<Lsb0>());
+        data.extend(serializer::encode_with_known_type(
+            &self.reason.to_variant(),
+            &NetworkedValueTypes::String,
+        ));
+        data
+    }
+    pub fn decode(data: &BitSlice<u64>, pointer: &mut usize) -> Option<Self> {
+        let player = u16::from_variant(&serializer::decode_with_known_type(
+            data,
+            pointer,
+            &NetworkedValueTypes::Unsigned16,
+        )?);
+        let action = u8::from_variant(&serializer::decode_with_known_type(
+            data,
+            pointer,
+            &NetworkedValueTypes::Unsigned8,
+        )?);
+        let reason = String::from_variant(&serializer::decode_with_known_type(
+            data,
+            pointer,
+            &NetworkedValueTypes::String,
+        )?);
+        Some(ModerationEvent {
+            player,
+            action: ModerationAction::try_from(action).ok()?,
+            reason,
+        })
+    }
+}
+
+// token client ids start again from 0 every time the server starts so bans on them are only kept in memory
+// the prefix keeps them from being mistaken for an account id that happens to be a number
+const TOKEN_TARGET_PREFIX: &str = "token:";
+pub fn token_target(token_id: u64) -> String {
+    format!("{}{}", TOKEN_TARGET_PREFIX, token_id)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ban {
+    // an account id, or the client id of a connect token as made by token_target
+    pub target: String,
+    // unix time in seconds, None for permanent bans
+    pub expires: Option<u64>,
+    pub reason: String,
+}
+impl Ban {
+    fn expired(&self, now: u64) -> bool {
+        self.expires.is_some_and(|x| x <= now)
+    }
+    fn persistent(&self) -> bool {
+        !self.target.starts_with(TOKEN_TARGET_PREFIX)
+    }
+    pub fn to_dictionary(&self) -> Dictionary {
+        let mut dictionary = Dictionary::new();
+        dictionary.set("target", self.target.clone());
+        dictionary.set("expires", self.expires.unwrap_or(0) as i64);
+        dictionary.set("reason", self.reason.clone());
+        dictionary
+    }
+}
+
+// one account ban per line as target, expiry and reason separated by tabs, an expiry of 0 means permanent
+#[derive(Debug, Default)]
+pub struct BanList {
+    // empty if bans should only last as long as the server is running
+    path: String,
+    bans: Vec<Ban>,
+}
+impl BanList {
+    pub fn load(path: String) -> Self {
+        let mut list = BanList {
+            path,
+            bans: Vec::new(),
+        };
+        if list.path.is_empty() {
+            return list;
+        }
+        let contents = match fs::read_to_string(&list.path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == ErrorKind::NotFound => return list,
+            Err(error) => {
+                log_warn!(Auth, "failed to read ban list {}: {}", list.path, error);
+                return list;
+            }
+        };
+        let now = unix_time();
+        for line in contents.lines().filter(|x| !x.trim().is_empty()) {
+            let mut fields = line.splitn(3, '\t');
+            let (Some(target), Some(expires), reason) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                log_warn!(Auth, "skipping malformed ban list entry: {}", line);
+                continue;
+            };
+            let Ok(expires) = expires.parse::<u64>() else {
+                log_warn!(Auth, "skipping malformed ban list entry: {}", line);
+                continue;
+            };
+            let ban = Ban {
+                target: target.to_string(),
+                expires: (expires != 0).then_some(expires),
+                reason: reason.unwrap_or_default().to_string(),
+            };
+            if !ban.expired(now) && ban.persistent() {
+                list.bans.push(ban);
+            }
+        }
+        list
+    }
+    pub fn save(&mut self) {
+        let now = unix_time();
+        self.bans.retain(|x| !x.expired(now));
+        if self.path.is_empty() {
+            return;
+        }
+        let mut contents = String::new();
+        for ban in self.bans.iter().filter(|x| x.persistent()) {
+            contents.push_str(&format!(
+                "{}\t{}\t{}\n",
+                ban.target,
+                ban.expires.unwrap_or(0),
+                ban.reason
+            ));
+        }
+        if let Err(error) = fs::write(&self.path, contents) {
+            log_warn!(Auth, "failed to save ban list {}: {}", self.path, error);
+        }
+    }
+    // a duration of None bans permanently, replaces any existing ban on the same target
+    pub fn add(&mut self, target: String, duration_seconds: Option<u64>, reason: String) {
+        // tabs and newlines would break the file format
+        let clean = |x: String| x.replace(['\t', '\n', '\r'], " ");
+        let target = clean(target);
+        self.bans.retain(|x| x.target != target);
+        self.bans.push(Ban {
+            target,
+            expires: duration_seconds.map(|x| unix_time() + x),
+            reason: clean(reason),
+        });
+        self.save();
+    }
+    pub fn remove(&mut self, target: &str) -> bool {
+        let count = self.bans.len();
+        self.bans.retain(|x| x.target != target);
+        let removed = self.bans.len() != count;
+        if removed {
+            self.save();
+        }
+        removed
+    }
+    // finds an active ban on any of the given targets, empty targets never match
+    pub fn find(&self, targets: &[&str]) -> Option<&Ban> {
+        let now = unix_time();
+        self.bans.iter().find(|ban| {
+            !ban.expired(now) && targets.iter().any(|x| !x.is_empty() && *x == ban.target)
+        })
+    }
+    pub fn bans(&self) -> &[Ban] {
+        &self.bans
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_bans_are_not_saved() {
+        let path = std::env::temp_dir().join(format!("butterfly_bans_{}.txt", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        let mut list = BanList::load(path.clone());
+        list.add("5".to_string(), None, "account".to_string());
+        list.add(token_target(5), None, "token".to_string());
+        assert_eq!(list.find(&[&token_target(5)]).unwrap().reason, "token");
+        assert_eq!(list.find(&["5"]).unwrap().reason, "account");
+        let reloaded = BanList::load(path.clone());
+        let _ = fs::remove_file(&path);
+        assert_eq!(reloaded.bans().len(), 1);
+        assert_eq!(reloaded.bans()[0].target, "5");
+        assert!(reloaded.find(&[&token_target(5)]).is_none());
+    }
+}
@@ -1,18 +1,31 @@
 // methods and functionality for NetworkedNode
 use crate::{
     NetNodeManager,
+    logging::{log_error, log_warn},
+    object_ids::ObjectId,
+    replication::{ReplicatedProperty, Replication},
     serializer::{self, NetworkedValueTypes},
+    wire,
 };
 
 use bitvec::prelude::*;
 use godot::prelude::*;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
 #[derive(GodotClass)]
 #[class(init, base=Node)]
 pub struct NetworkedNode {
     #[var]
-    pub objectid: u16,
+    pub objectid: ObjectId,
     #[var]
     pub owner_id: u16,
+    // when set these properties are sent instead of using get_networked_values, set_networked_values and get_networked_value_type
+    #[export]
+    pub replicated_properties: Array<Gd<ReplicatedProperty>>,
+    replication: Option<Result<Replication, String>>,
+    // from get_networked_value_type, read once when the node enters the tree and again when replication_changed is emitted
+    value_types: Vec<NetworkedValueTypes>,
+    replication_cached: bool,
     base: Base<Node>,
 }
 
@@ -37,44 +50,109 @@ pub impl NetworkedNode {
     pub fn on_owner_dc(&mut self) {
         return;
     }
+    // emit after changing replicated_properties or what get_networked_value_type returns, the node reads them again
+    // both sides must agree on the layout so this has to happen on every peer
+    #[signal]
+    pub fn replication_changed();
+    fn cache_replication(&mut self) {
+        self.replication = None;
+        self.value_types.clear();
+        if self.replicated_properties.is_empty() {
+            self.value_types = self.get_networked_values_types();
+        } else {
+            self.replication = Some(
+                Replication::from_properties(&self.replicated_properties).inspect_err(|error| {
+                    log_error!(
+                        Sync,
+                        "invalid replicated properties for {}: {}",
+                        self.base().get_name(),
+                        error
+                    )
+                }),
+            );
+        }
+    }
 
-    // generates a packet chunk containing the values from get_networked_values, encodes each value using the network value types
-    pub fn get_byte_data(&self, types: &[NetworkedValueTypes]) -> BitVec {
+    // generates a packet chunk containing the values of the node, empty if they couldn't be encoded
+    pub fn get_byte_data(&self) -> BitVec<u64, Lsb0> {
         const AVERAGE_OBJECT_SIZE: usize = 128; // estimated average size, prefers to overallocate than underallocate, probably a better way to do this
-        let data: VariantArray = self.get_networked_values();
-        let mut byte_data: BitVec = BitVec::with_capacity(data.len() * AVERAGE_OBJECT_SIZE);
-
-        byte_data.extend(self.objectid.view_bits::<Lsb0>());
-        for (index, inner_value) in data.iter_shared().enumerate() {
-            // network values and network value types must match
-            byte_data.extend(serializer::encode_with_known_type(
-                &inner_value,
-                &types[index],
-            ));
+        let mut byte_data: BitVec<u64, Lsb0> =
+            BitVec::with_capacity(self.value_types.len() * AVERAGE_OBJECT_SIZE);
+        match &self.replication {
+            Some(Ok(replication)) => match replication.encode(&self.base()) {
+                Ok(data) => byte_data = data,
+                Err(error) => {
+                    log_warn!(
+                        Sync,
+                        "failed to replicate {}: {}",
+                        self.base().get_name(),
+                        error
+                    );
+                    return BitVec::new();
+                }
+            },
+            // already reported when the node entered the tree
+            Some(Err(_)) => return BitVec::new(),
+            None => {
+                let data: VariantArray = self.get_networked_values();
+                // network values and network value types must match
+                if data.len() != self.value_types.len() {
+                    log_warn!(
+                        Sync,
+                        "{} has {} networked values but {} value types",
+                        self.base().get_name(),
+                        data.len(),
+                        self.value_types.len()
+                    );
+                    return BitVec::new();
+                }
+                for (inner_value, value_type) in data.iter_shared().zip(self.value_types.iter()) {
+                    byte_data.extend(serializer::encode_with_known_type(&inner_value, value_type));
+                }
+            }
         }
-        byte_data
+        let mut entry: BitVec<u64, Lsb0> = BitVec::with_capacity(byte_data.len() + 64);
+        wire::push_node_entry(&mut entry, self.objectid, &byte_data);
+        entry
     }
 
-    // decodes a packet chunk into the variant values used in set_networked_values
-    pub fn update_networked_values(
-        &self,
-        pointer: &mut usize,
-        data: &BitSlice<u64>,
-        types: &[NetworkedValueTypes],
-    ) -> bool {
-        let mut values: VariantArray = VariantArray::new();
-        while values.len() < types.len() {
-            if let Some(value) =
-                serializer::decode_with_known_type(data, pointer, &types[values.len()])
-            {
-                values.push(&value);
-            } else {
-                godot_warn!("failed to decode {:#?}", types[values.len()]);
-                return false;
+    // decodes the values from a packet entry and applies them to the node, they must use up the whole entry
+    pub fn update_networked_values(&self, data: &BitSlice<u64>) -> Result<(), UpdateError> {
+        let mut pointer: usize = 0;
+        match &self.replication {
+            Some(Ok(replication)) => replication
+                .decode(&self.base(), data, &mut pointer)
+                .inspect_err(|error| log_warn!(Sync, "{}", error))?,
+            // already reported when the node entered the tree
+            Some(Err(error)) => return Err(UpdateError::Local(error.clone())),
+            None => {
+                let mut values: VariantArray = VariantArray::new();
+                for value_type in self.value_types.iter() {
+                    if let Some(value) =
+                        serializer::decode_with_known_type(data, &mut pointer, value_type)
+                    {
+                        values.push(&value);
+                    } else {
+                        let error =
+                            UpdateError::Malformed(format!("failed to decode {:#?}", value_type));
+                        log_warn!(Sync, "{}", error);
+                        return Err(error);
+                    }
+                }
+                self.set_networked_values(values);
             }
         }
-        self.set_networked_values(values);
-        true
+        if pointer != data.len() {
+            let error = UpdateError::Malformed(format!(
+                "{} decoded {} bits of a {} bit update",
+                self.base().get_name(),
+                pointer,
+                data.len()
+            ));
+            log_warn!(Sync, "{}", error);
+            return Err(error);
+        }
+        Ok(())
     }
     // intended to be overriden, determines how values from get_networked_values are encoded in the packet, types are provided using the enum values. encoding must be valid for the variant type
     // called in loop with incrementing idx until -1 is returned
@@ -83,14 +161,26 @@ pub impl NetworkedNode {
         panic!("node has no impl for get_networked_values_type")
     }
     // collects network value types into a vec by calling get_networked_value_type until -1 is returned
-    pub fn get_networked_values_types(&self) -> Vec<NetworkedValueTypes> {
+    fn get_networked_values_types(&self) -> Vec<NetworkedValueTypes> {
         let mut values: Vec<NetworkedValueTypes> = Vec::new();
         for i in 0..1000 {
             let tmp = self.get_networked_value_type(i);
             if tmp == -1 {
                 break;
             }
-            values.push(NetworkedValueTypes::try_from(tmp).unwrap());
+            match NetworkedValueTypes::try_from(tmp) {
+                Ok(value_type) => values.push(value_type),
+                Err(error) => {
+                    log_error!(
+                        Sync,
+                        "{} returned an invalid value type {}: {}",
+                        self.base().get_name(),
+                        tmp,
+                        error
+                    );
+                    break;
+                }
+            }
         }
         values
     }
@@ -98,6 +188,13 @@ pub impl NetworkedNode {
 #[godot_api]
 impl INode for NetworkedNode {
     fn enter_tree(&mut self) {
+        if !self.replication_cached {
+            self.replication_cached = true;
+            self.cache_replication();
+            self.signals()
+                .replication_changed()
+                .connect_self(Self::cache_replication);
+        }
         if self
             .base()
             .get_node_as::<NetNodeManager>("/root/NetworkManager")
@@ -124,6 +221,138 @@ impl INode for NetworkedNode {
         self.base()
             .get_node_as::<NetNodeManager>("/root/NetworkManager")
             .bind_mut()
-            .unregister_node(self.to_gd());
+            .unregister_node(self.to_gd(), self);
+    }
+}
+
+// why an update couldn't be applied to a node, only malformed updates are the sender's fault
+#[derive(Debug, Clone, PartialEq)]
+pub enum UpdateError {
+    // the node is misconfigured on this side, like invalid replicated properties or a property on a missing node
+    Local(String),
+    // the values don't match what the node expects
+    Malformed(String),
+}
+impl std::fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UpdateError::Local(error) | UpdateError::Malformed(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+// one entry per node for NetNodeManager::get_debug_dump
+pub fn debug_dump_nodes(nodes: &[Gd<NetworkedNode>]) -> Array<Dictionary> {
+    let mut dump: Array<Dictionary> = Array::new();
+    for node in nodes.iter().filter(|x| x.is_instance_valid()) {
+        let node = node.bind();
+        let mut entry = Dictionary::new();
+        entry.set("objectid", node.objectid);
+        entry.set("owner_id", node.owner_id);
+        entry.set("path", node.base().get_path());
+        let replication = match &node.replication {
+            Some(Ok(_)) => GString::from("properties"),
+            Some(Err(error)) => GString::from(error),
+            None => GString::from("script"),
+        };
+        entry.set("replication", replication);
+        dump.push(&entry);
+    }
+    dump
+}
+
+// finds networked nodes by objectid without scanning every node
+// nodes are added when they register and again when a client is told their id, a miss means the id is stale or bogus
+#[derive(Default)]
+pub struct NodeIndex {
+    by_id: HashMap<ObjectId, Gd<NetworkedNode>>,
+}
+impl NodeIndex {
+    pub fn get(&self, objectid: ObjectId) -> Option<Gd<NetworkedNode>> {
+        self.by_id
+            .get(&objectid)
+            .filter(|x| x.is_instance_valid() && x.bind().objectid == objectid)
+            .cloned()
+    }
+    // takes the id separately since nodes are still bound while they register
+    pub fn insert(&mut self, objectid: ObjectId, node: &Gd<NetworkedNode>) {
+        if !objectid.is_none() {
+            self.by_id.insert(objectid, node.clone());
+        }
+    }
+    pub fn remove(&mut self, node: &Gd<NetworkedNode>) {
+        self.by_id.retain(|_, x| x != node);
+    }
+    pub fn clear(&mut self) {
+        self.by_id.clear();
+    }
+}
+
+// the encoded state of each node for one tick, shared by every client so scripts are only asked for a node's values once per tick
+#[derive(Default)]
+pub struct SnapshotCache {
+    tick: u64,
+    encoded: HashMap<InstanceId, BitVec<u64, Lsb0>>,
+}
+impl SnapshotCache {
+    pub fn get(&mut self, tick: u64, node: &Gd<NetworkedNode>) -> &BitVec<u64, Lsb0> {
+        if tick != self.tick {
+            self.encoded.clear();
+            self.tick = tick;
+        }
+        self.encoded
+            .entry(node.instance_id())
+            .or_insert_with(|| node.bind().get_byte_data())
+    }
+}
+
+// updates for nodes that haven't registered yet, a spawn and the first updates for it can arrive in either order
+// only the newest update for each node is kept and they are dropped once they are older than the lifetime
+#[derive(Default)]
+pub struct PendingUpdates {
+    lifetime: Duration,
+    updates: HashMap<ObjectId, (BitVec<u64, Lsb0>, Instant)>,
+}
+impl PendingUpdates {
+    const MAX_PENDING: usize = 256;
+    // a lifetime of 0 drops updates for unknown nodes straight away
+    pub fn new(lifetime: Duration) -> Self {
+        PendingUpdates {
+            lifetime,
+            updates: HashMap::new(),
+        }
+    }
+    pub fn hold(&mut self, objectid: ObjectId, values: &BitSlice<u64>) {
+        if self.lifetime.is_zero()
+            || (self.updates.len() >= Self::MAX_PENDING && !self.updates.contains_key(&objectid))
+        {
+            return;
+        }
+        self.updates
+            .insert(objectid, (values.to_bitvec(), Instant::now()));
+    }
+    // removes and returns the updates whose nodes have registered since they arrived
+    pub fn take_ready(&mut self, index: &NodeIndex) -> Vec<(Gd<NetworkedNode>, BitVec<u64, Lsb0>)> {
+        let now = Instant::now();
+        self.updates
+            .retain(|_, x| now.duration_since(x.1) < self.lifetime);
+        if self.updates.is_empty() {
+            return Vec::new();
+        }
+        let ready: Vec<(ObjectId, Gd<NetworkedNode>)> = self
+            .updates
+            .keys()
+            .filter_map(|x| Some((*x, index.get(*x)?)))
+            .collect();
+        ready
+            .into_iter()
+            .filter_map(|(id, node)| Some((node, self.updates.remove(&id)?.0)))
+            .collect()
+    }
+    pub fn len(&self) -> usize {
+        self.updates.len()
+    }
+    pub fn clear(&mut self) {
+        self.updates.clear();
     }
 }
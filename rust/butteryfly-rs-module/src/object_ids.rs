@@ -0,0 +1,142 @@
+// ids for networked nodes, an index that is reused once it has been free for a while and a generation that changes every time it is
+use godot::prelude::*;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// how long a freed index waits before it is given out again, packets still in flight for the old node are dropped instead of hitting the new one
+const QUARANTINE: Duration = Duration::from_secs(10);
+const INDEX_BITS: u32 = 32;
+
+// the index is in the low 32 bits and the generation in the 16 above it so scripts see a plain positive int
+// index 0 is a node that hasn't been given an id yet
+#[derive(GodotConvert, Var, Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[godot(transparent)]
+pub struct ObjectId(i64);
+impl ObjectId {
+    pub const NONE: ObjectId = ObjectId(0);
+    pub fn new(index: u32, generation: u16) -> Self {
+        ObjectId(((generation as i64) << INDEX_BITS) | index as i64)
+    }
+    pub fn index(&self) -> u32 {
+        self.0 as u32
+    }
+    pub fn generation(&self) -> u16 {
+        (self.0 >> INDEX_BITS) as u16
+    }
+    pub fn is_none(&self) -> bool {
+        self.index() == 0
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Slot {
+    generation: u16,
+    live: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct ObjectIdAllocator {
+    // the generation given out last at each index, index 0 is never used
+    slots: Vec<Slot>,
+    free: VecDeque<u32>,
+    // released indexes in the order they were freed
+    quarantine: VecDeque<(u32, Instant)>,
+}
+impl ObjectIdAllocator {
+    pub fn allocate(&mut self, now: Instant) -> ObjectId {
+        while let Some(&(index, freed)) = self.quarantine.front()
+            && now.duration_since(freed) >= QUARANTINE
+        {
+            self.quarantine.pop_front();
+            self.free.push_back(index);
+        }
+        let index = match self.free.pop_front() {
+            Some(index) => index,
+            None => {
+                if self.slots.is_empty() {
+                    self.slots.push(Slot::default());
+                }
+                self.slots.push(Slot::default());
+                u32::try_from(self.slots.len() - 1).expect("ran out of object ids")
+            }
+        };
+        let slot = &mut self.slots[index as usize];
+        slot.live = true;
+        ObjectId::new(index, slot.generation)
+    }
+    // returns false if the id isn't one that is currently given out
+    pub fn release(&mut self, id: ObjectId, now: Instant) -> bool {
+        let Some(slot) = self.slots.get_mut(id.index() as usize) else {
+            return false;
+        };
+        if id.is_none() || !slot.live || slot.generation != id.generation() {
+            return false;
+        }
+        slot.live = false;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.quarantine.push_back((id.index(), now));
+        true
+    }
+    // clients may still hold every id so they all go through quarantine rather than starting again from 1
+    pub fn release_all(&mut self, now: Instant) {
+        for index in 1..self.slots.len() {
+            let slot = self.slots[index];
+            if slot.live {
+                self.release(ObjectId::new(index as u32, slot.generation), now);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_start_at_one() {
+        let mut allocator = ObjectIdAllocator::default();
+        let now = Instant::now();
+        assert_eq!(allocator.allocate(now), ObjectId::new(1, 0));
+        assert_eq!(allocator.allocate(now), ObjectId::new(2, 0));
+    }
+
+    #[test]
+    fn released_ids_wait_out_the_quarantine() {
+        let mut allocator = ObjectIdAllocator::default();
+        let now = Instant::now();
+        let id = allocator.allocate(now);
+        assert!(allocator.release(id, now));
+        assert_eq!(allocator.allocate(now), ObjectId::new(2, 0));
+        let reused = allocator.allocate(now + QUARANTINE);
+        assert_eq!(reused.index(), id.index());
+        assert_eq!(reused.generation(), 1);
+        assert_ne!(reused, id);
+    }
+
+    #[test]
+    fn stale_ids_are_not_released() {
+        let mut allocator = ObjectIdAllocator::default();
+        let now = Instant::now();
+        let id = allocator.allocate(now);
+        assert!(allocator.release(id, now));
+        assert!(!allocator.release(id, now));
+        let reused = allocator.allocate(now + QUARANTINE);
+        assert!(!allocator.release(id, now + QUARANTINE));
+        assert!(allocator.release(reused, now + QUARANTINE));
+        assert!(!allocator.release(ObjectId::NONE, now));
+    }
+
+    #[test]
+    fn release_all_quarantines_every_id() {
+        let mut allocator = ObjectIdAllocator::default();
+        let now = Instant::now();
+        let first = allocator.allocate(now);
+        allocator.allocate(now);
+        allocator.release_all(now);
+        assert_eq!(allocator.allocate(now).index(), 3);
+        assert_eq!(
+            allocator.allocate(now + QUARANTINE),
+            ObjectId::new(first.index(), 1)
+        );
+    }
+}
@@ -0,0 +1,134 @@
+// player identity and profile information, owned by the server and replicated to all clients
+use crate::serializer::{self, NetworkedValueTypes};
+use bitvec::prelude::*;
+use godot::prelude::*;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PlayerInfo {
+    // the network id of the player, only stable for the lifetime of a connection
+    pub id: u16,
+    // stable id for the account the player logged in with, empty for anonymous players
+    pub account_id: String,
+    pub display_name: String,
+    pub roles: Vec<String>,
+    // set while the player's connection has dropped and the server is holding their session for them
+    pub reconnecting: bool,
+    // set by moderators, the server drops voice from muted players and sends silence to deafened ones
+    pub muted: bool,
+    pub deafened: bool,
+}
+
+impl PlayerInfo {
+    pub fn new(account_id: String, display_name: String, roles: Vec<String>) -> Self {
+        PlayerInfo {
+            id: 0,
+            account_id,
+            display_name,
+            roles,
+            reconnecting: false,
+            muted: false,
+            deafened: false,
+        }
+    }
+    // used when a player connects with a token that was not issued with a profile
+    pub fn anonymous(id: u16) -> Self {
+        PlayerInfo {
+            id,
+            display_name: format!("Player {}", id),
+            ..Default::default()
+        }
+    }
+    pub fn to_dictionary(&self) -> Dictionary {
+        let mut dictionary = Dictionary::new();
+        dictionary.set("id", self.id);
+        dictionary.set("account_id", self.account_id.clone());
+        dictionary.set("display_name", self.display_name.clone());
+        dictionary.set(
+            "roles",
+            PackedStringArray::from_iter(self.roles.iter().map(GString::from)),
+        );
+        dictionary.set("reconnecting", self.reconnecting);
+        dictionary.set("muted", self.muted);
+        dictionary.set("deafened", self.deafened);
+        dictionary
+    }
+    pub fn encode(&self) -> BitVec<u64, Lsb0> {
+        let mut data: BitVec<u64, Lsb0> = BitVec::new();
+        data.extend(self.id.view_bits::<Lsb0>());
+        data.extend(serializer::encode_with_known_type(
+            &self.account_id.to_variant(),
+            &NetworkedValueTypes::String,
+        ));
+        data.extend(serializer::encode_with_known_type(
+            &self.display_name.to_variant(),
+            &NetworkedValueTypes::String,
+        ));
+        // roles past u8::MAX are dropped, nobody should need that many
+        let role_count = self.roles.len().min(u8::MAX as usize) as u8;
+        data.extend(role_count.view_bits::<Lsb0>());
+        for role in self.roles.iter().take(role_count as usize) {
+            data.extend(serializer::encode_with_known_type(
+                &role.to_variant(),
+                &NetworkedValueTypes::String,
+            ));
+        }
+        data.push(self.reconnecting);
+        data.push(self.muted);
+        data.push(self.deafened);
+        data
+    }
+    pub fn decode(data: &BitSlice<u64>, pointer: &mut usize) -> Option<Self> {
+        let id = u16::from_variant(&serializer::decode_with_known_type(
+            data,
+            pointer,
+            &NetworkedValueTypes::Unsigned16,
+        )?);
+        let account_id = String::from_variant(&serializer::decode_with_known_type(
+            data,
+            pointer,
+            &NetworkedValueTypes::String,
+        )?);
+        let display_name = String::from_variant(&serializer::decode_with_known_type(
+            data,
+            pointer,
+            &NetworkedValueTypes::String,
+        )?);
+        let role_count = u8::from_variant(&serializer::decode_with_known_type(
+            data,
+            pointer,
+            &NetworkedValueTypes::Unsigned8,
+        )?);
+        let mut roles = Vec::with_capacity(role_count as usize);
+        for _ in 0..role_count {
+            roles.push(String::from_variant(&serializer::decode_with_known_type(
+                data,
+                pointer,
+                &NetworkedValueTypes::String,
+            )?));
+        }
+        let reconnecting = bool::from_variant(&serializer::decode_with_known_type(
+            data,
+            pointer,
+            &NetworkedValueTypes::Bool,
+        )?);
+        let muted = bool::from_variant(&serializer::decode_with_known_type(
+            data,
+            pointer,
+            &NetworkedValueTypes::Bool,
+        )?);
+        let deafened = bool::from_variant(&serializer::decode_with_known_type(
+            data,
+            pointer,
+            &NetworkedValueTypes::Bool,
+        )?);
+        Some(PlayerInfo {
+            id,
+            account_id,
+            display_name,
+            roles,
+            reconnecting,
+            muted,
+            deafened,
+        })
+    }
+}
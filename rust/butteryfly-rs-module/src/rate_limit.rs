@@ -0,0 +1,238 @@
+// limits on how much a single client can send, used by the server to stop one client from flooding everyone else
+use std::time::Instant;
+
+// what the server does when a client goes over a limit
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum LimitPolicy {
+    // the message is thrown away
+    #[default]
+    Drop = 0,
+    // the message is still let through but a warning is logged, useful for tuning limits before enforcing them
+    Warn = 1,
+    // the message is thrown away and the client is kicked
+    Kick = 2,
+}
+impl TryFrom<i64> for LimitPolicy {
+    type Error = ();
+    fn try_from(value: i64) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(LimitPolicy::Drop),
+            1 => Ok(LimitPolicy::Warn),
+            2 => Ok(LimitPolicy::Kick),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MessageLimits {
+    // messages per second, 0 for no limit
+    pub rate: f64,
+    // how many messages can be sent at once before the rate applies
+    pub burst: f64,
+    // largest payload in bytes, 0 for no limit
+    pub max_bytes: usize,
+    pub policy: LimitPolicy,
+}
+impl Default for MessageLimits {
+    fn default() -> Self {
+        MessageLimits {
+            rate: 30.0,
+            burst: 60.0,
+            max_bytes: 4096,
+            policy: LimitPolicy::Drop,
+        }
+    }
+}
+impl MessageLimits {
+    // returns a description of the violation if a message of this size goes over the limits
+    pub fn check(&self, bucket: &mut TokenBucket, size: usize) -> Option<String> {
+        if self.max_bytes != 0 && size > self.max_bytes {
+            return Some(format!(
+                "message of {} bytes is over the limit of {}",
+                size, self.max_bytes
+            ));
+        }
+        if self.rate > 0.0 && !bucket.take(self.rate, self.burst.max(1.0)) {
+            return Some(format!("sent more than {} messages per second", self.rate));
+        }
+        None
+    }
+}
+
+// limits on a client's packets as a whole rather than on single message types, 0 disables a limit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PacketLimits {
+    // packets per second on every channel
+    pub rate: f64,
+    pub burst: f64,
+    // largest voice payload in bytes, 1275 is the most a single opus frame can be
+    pub max_voice_bytes: usize,
+    // voice is consumed one packet a tick so anything past a small backlog is just a flood
+    pub max_voice_buffer: usize,
+    // reliable packets further ahead than this are not buffered so a client cant make us buffer without limit
+    pub reliable_window: u64,
+    pub policy: LimitPolicy,
+}
+impl Default for PacketLimits {
+    fn default() -> Self {
+        PacketLimits {
+            rate: 600.0,
+            burst: 1200.0,
+            max_voice_bytes: 1275,
+            max_voice_buffer: 32,
+            reliable_window: 1024,
+            policy: LimitPolicy::Drop,
+        }
+    }
+}
+impl PacketLimits {
+    // each check returns a description of the violation if the packet goes over a limit
+    pub fn check_rate(&self, bucket: &mut TokenBucket) -> Option<String> {
+        if self.rate > 0.0 && !bucket.take(self.rate, self.burst.max(1.0)) {
+            return Some(format!("sent more than {} packets per second", self.rate));
+        }
+        None
+    }
+    pub fn check_voice(&self, size: usize, buffered: usize) -> Option<String> {
+        if self.max_voice_bytes != 0 && size > self.max_voice_bytes {
+            return Some(format!(
+                "voice packet of {} bytes is over the limit of {}",
+                size, self.max_voice_bytes
+            ));
+        }
+        if self.max_voice_buffer != 0 && buffered >= self.max_voice_buffer {
+            return Some(format!(
+                "sent voice faster than it is played, {} packets are already waiting",
+                buffered
+            ));
+        }
+        None
+    }
+    // next is the packet number the channel is waiting for
+    pub fn check_reliable(&self, channel: u16, packet_number: u64, next: u64) -> Option<String> {
+        if self.reliable_window != 0 && packet_number >= next.saturating_add(self.reliable_window) {
+            return Some(format!(
+                "sent packet {} on channel {} while {} was still missing",
+                packet_number, channel, next
+            ));
+        }
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+impl Default for TokenBucket {
+    fn default() -> Self {
+        TokenBucket {
+            tokens: f64::INFINITY,
+            last_refill: Instant::now(),
+        }
+    }
+}
+impl TokenBucket {
+    // refills at rate tokens per second up to capacity, returns false if there was no token to take
+    pub fn take(&mut self, rate: f64, capacity: f64) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(capacity);
+        self.last_refill = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn messages_over_the_size_limit_are_rejected() {
+        let limits = MessageLimits {
+            max_bytes: 10,
+            ..Default::default()
+        };
+        let mut bucket = TokenBucket::default();
+        assert_eq!(limits.check(&mut bucket, 10), None);
+        assert!(limits.check(&mut bucket, 11).is_some());
+    }
+
+    #[test]
+    fn messages_over_the_rate_limit_are_rejected() {
+        let limits = MessageLimits {
+            rate: 1.0,
+            burst: 2.0,
+            ..Default::default()
+        };
+        let mut bucket = TokenBucket::default();
+        assert_eq!(limits.check(&mut bucket, 1), None);
+        assert_eq!(limits.check(&mut bucket, 1), None);
+        assert!(limits.check(&mut bucket, 1).is_some());
+    }
+
+    #[test]
+    fn zero_disables_message_limits() {
+        let limits = MessageLimits {
+            rate: 0.0,
+            burst: 0.0,
+            max_bytes: 0,
+            policy: LimitPolicy::Kick,
+        };
+        let mut bucket = TokenBucket::default();
+        for _ in 0..1000 {
+            assert_eq!(limits.check(&mut bucket, usize::MAX), None);
+        }
+    }
+
+    #[test]
+    fn buckets_start_at_capacity() {
+        let mut bucket = TokenBucket::default();
+        for _ in 0..3 {
+            assert!(bucket.take(0.001, 3.0));
+        }
+        assert!(!bucket.take(0.001, 3.0));
+    }
+
+    #[test]
+    fn buckets_refill_over_time() {
+        let mut bucket = TokenBucket {
+            tokens: 0.0,
+            last_refill: Instant::now() - Duration::from_secs(1),
+        };
+        assert!(bucket.take(2.0, 10.0));
+        assert!(bucket.take(2.0, 10.0));
+        assert!(!bucket.take(2.0, 10.0));
+        // refilling never goes past the capacity
+        bucket.last_refill -= Duration::from_secs(100);
+        for _ in 0..5 {
+            assert!(bucket.take(2.0, 5.0));
+        }
+        assert!(!bucket.take(2.0, 5.0));
+    }
+
+    #[test]
+    fn packet_limits_check_voice_and_reliable_packets() {
+        let limits = PacketLimits::default();
+        assert_eq!(limits.check_voice(1275, 31), None);
+        assert!(limits.check_voice(1276, 0).is_some());
+        assert!(limits.check_voice(100, 32).is_some());
+        assert_eq!(limits.check_reliable(3, 1023, 0), None);
+        assert!(limits.check_reliable(3, 1024, 0).is_some());
+        let unlimited = PacketLimits {
+            max_voice_bytes: 0,
+            max_voice_buffer: 0,
+            reliable_window: 0,
+            ..Default::default()
+        };
+        assert_eq!(unlimited.check_voice(usize::MAX, usize::MAX), None);
+        assert_eq!(unlimited.check_reliable(3, u64::MAX, 0), None);
+    }
+}
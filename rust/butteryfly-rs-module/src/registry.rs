@@ -0,0 +1,241 @@
+// maps message handler names to message types so handlers dont need hand picked numbers
+// the server allocates the ids and replicates the table to clients through the message buffer, so new clients get it while catching up
+use crate::messages::is_reserved_message_type;
+use crate::serializer::{self, NetValue, NetworkedValueTypes};
+use bitvec::prelude::*;
+use godot::prelude::*;
+use std::collections::HashMap;
+
+// types below this are for handlers with a hand picked message_type, named handlers are given ids from here up
+pub const FIRST_NAMED_MESSAGE_TYPE: u16 = 0x8000;
+// leaves room for the reserved types at the top of the range
+pub const LAST_NAMED_MESSAGE_TYPE: u16 = u16::MAX - 256;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegistryError {
+    // the type is used internally or falls in the range kept for named handlers
+    ReservedType(u16),
+    // another handler is already registered for this type
+    TypeInUse(u16),
+    // another handler is already registered with this name, the type is none when a client hasnt been sent it yet
+    NameInUse(String, Option<u16>),
+    // the server sent an id for a name that doesnt match the one we already have
+    Mismatch(String, u16, u16),
+    Full,
+}
+impl std::fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegistryError::ReservedType(message_type) => write!(
+                f,
+                "message type {} is reserved, hand picked types must be between 1 and {}",
+                message_type,
+                FIRST_NAMED_MESSAGE_TYPE - 1
+            ),
+            RegistryError::TypeInUse(message_type) => write!(
+                f,
+                "a handler is already registered for message type {}",
+                message_type
+            ),
+            RegistryError::NameInUse(name, Some(message_type)) => write!(
+                f,
+                "a handler named \"{}\" is already registered with message type {}",
+                name, message_type
+            ),
+            RegistryError::NameInUse(name, None) => {
+                write!(f, "a handler named \"{}\" is already registered", name)
+            }
+            RegistryError::Mismatch(name, ours, theirs) => write!(
+                f,
+                "server assigned message type {} to \"{}\" but it already has type {}",
+                theirs, name, ours
+            ),
+            RegistryError::Full => write!(f, "no message types left for named handlers"),
+        }
+    }
+}
+
+// checks a hand picked type can be used
+pub fn check_manual_type(message_type: u16) -> Result<(), RegistryError> {
+    if is_reserved_message_type(message_type) || message_type >= FIRST_NAMED_MESSAGE_TYPE {
+        return Err(RegistryError::ReservedType(message_type));
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct MessageRegistry {
+    names: HashMap<String, u16>,
+    next_type: u16,
+}
+impl Default for MessageRegistry {
+    fn default() -> Self {
+        MessageRegistry {
+            names: HashMap::new(),
+            next_type: FIRST_NAMED_MESSAGE_TYPE,
+        }
+    }
+}
+impl MessageRegistry {
+    pub fn get(&self, name: &str) -> Option<u16> {
+        self.names.get(name).copied()
+    }
+    // returns the type for a name and whether it was newly allocated, ids are never reused so clients tables stay valid
+    pub fn allocate(&mut self, name: &str) -> Result<(u16, bool), RegistryError> {
+        if let Some(message_type) = self.get(name) {
+            return Ok((message_type, false));
+        }
+        if self.next_type > LAST_NAMED_MESSAGE_TYPE {
+            return Err(RegistryError::Full);
+        }
+        let message_type = self.next_type;
+        self.next_type += 1;
+        self.names.insert(name.to_string(), message_type);
+        Ok((message_type, true))
+    }
+    // used by clients when the server tells them about a name
+    pub fn insert(&mut self, name: &str, message_type: u16) -> Result<(), RegistryError> {
+        match self.get(name) {
+            Some(existing) if existing != message_type => Err(RegistryError::Mismatch(
+                name.to_string(),
+                existing,
+                message_type,
+            )),
+            _ => {
+                if let Some(other) = self
+                    .names
+                    .iter()
+                    .find(|x| *x.1 == message_type && x.0 != name)
+                {
+                    return Err(RegistryError::NameInUse(
+                        other.0.clone(),
+                        Some(message_type),
+                    ));
+                }
+                self.names.insert(name.to_string(), message_type);
+                Ok(())
+            }
+        }
+    }
+    pub fn to_dictionary(&self) -> Dictionary {
+        let mut dictionary = Dictionary::new();
+        for (name, message_type) in self.names.iter() {
+            dictionary.set(name.clone(), *message_type as i64);
+        }
+        dictionary
+    }
+}
+
+pub fn encode_entry(name: &str, message_type: u16) -> BitVec<u64, Lsb0> {
+    let mut data: BitVec<u64, Lsb0> = BitVec::new();
+    data.extend(message_type.view_bits::<Lsb0>());
+    data.extend(NetValue::String(name.to_string()).encode());
+    data
+}
+pub fn decode_entry(data: &BitSlice<u64>, pointer: &mut usize) -> Option<(String, u16)> {
+    let message_type: u16 = data.get(*pointer..*pointer + 16)?.load_le();
+    *pointer += 16;
+    let NetValue::String(name) =
+        serializer::decode_value(data, pointer, &NetworkedValueTypes::String)?
+    else {
+        return None;
+    };
+    Some((name, message_type))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{MESSAGE_TYPE_ID_SYNC, MESSAGE_TYPE_REGISTRY};
+
+    #[test]
+    fn allocate_reuses_names() {
+        let mut registry = MessageRegistry::default();
+        assert_eq!(
+            registry.allocate("chat"),
+            Ok((FIRST_NAMED_MESSAGE_TYPE, true))
+        );
+        assert_eq!(
+            registry.allocate("emote"),
+            Ok((FIRST_NAMED_MESSAGE_TYPE + 1, true))
+        );
+        assert_eq!(
+            registry.allocate("chat"),
+            Ok((FIRST_NAMED_MESSAGE_TYPE, false))
+        );
+    }
+
+    #[test]
+    fn allocate_fails_when_full() {
+        let mut registry = MessageRegistry {
+            next_type: LAST_NAMED_MESSAGE_TYPE,
+            ..Default::default()
+        };
+        assert_eq!(
+            registry.allocate("last"),
+            Ok((LAST_NAMED_MESSAGE_TYPE, true))
+        );
+        assert_eq!(registry.allocate("one more"), Err(RegistryError::Full));
+        assert_eq!(
+            registry.allocate("last"),
+            Ok((LAST_NAMED_MESSAGE_TYPE, false))
+        );
+    }
+
+    #[test]
+    fn manual_types_cant_be_reserved() {
+        assert_eq!(check_manual_type(1), Ok(()));
+        assert_eq!(check_manual_type(FIRST_NAMED_MESSAGE_TYPE - 1), Ok(()));
+        for message_type in [
+            MESSAGE_TYPE_ID_SYNC,
+            MESSAGE_TYPE_REGISTRY,
+            FIRST_NAMED_MESSAGE_TYPE,
+            LAST_NAMED_MESSAGE_TYPE,
+        ] {
+            assert_eq!(
+                check_manual_type(message_type),
+                Err(RegistryError::ReservedType(message_type))
+            );
+        }
+    }
+
+    #[test]
+    fn insert_rejects_conflicts() {
+        let mut registry = MessageRegistry::default();
+        assert_eq!(registry.insert("chat", FIRST_NAMED_MESSAGE_TYPE), Ok(()));
+        assert_eq!(registry.insert("chat", FIRST_NAMED_MESSAGE_TYPE), Ok(()));
+        assert_eq!(
+            registry.insert("chat", FIRST_NAMED_MESSAGE_TYPE + 1),
+            Err(RegistryError::Mismatch(
+                "chat".to_string(),
+                FIRST_NAMED_MESSAGE_TYPE,
+                FIRST_NAMED_MESSAGE_TYPE + 1
+            ))
+        );
+        assert_eq!(
+            registry.insert("emote", FIRST_NAMED_MESSAGE_TYPE),
+            Err(RegistryError::NameInUse(
+                "chat".to_string(),
+                Some(FIRST_NAMED_MESSAGE_TYPE)
+            ))
+        );
+        assert_eq!(registry.get("emote"), None);
+    }
+
+    #[test]
+    fn entries_round_trip() {
+        let mut data = encode_entry("chat", FIRST_NAMED_MESSAGE_TYPE + 7);
+        data.extend(encode_entry("", FIRST_NAMED_MESSAGE_TYPE));
+        let mut pointer = 0;
+        assert_eq!(
+            decode_entry(&data, &mut pointer),
+            Some(("chat".to_string(), FIRST_NAMED_MESSAGE_TYPE + 7))
+        );
+        assert_eq!(
+            decode_entry(&data, &mut pointer),
+            Some((String::new(), FIRST_NAMED_MESSAGE_TYPE))
+        );
+        assert_eq!(pointer, data.len());
+        assert_eq!(decode_entry(&data[..20], &mut 0), None);
+    }
+}
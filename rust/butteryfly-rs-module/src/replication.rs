@@ -0,0 +1,236 @@
+// lets a NetworkedNode replicate properties directly instead of implementing the networked value virtuals
+use crate::net_nodes::UpdateError;
+use crate::schema::{self, BOUNDED_FIELD_TYPE, Field};
+use crate::serializer::{self, BoundedRange, NetValue, NetworkedValueTypes};
+use bitvec::prelude::*;
+use godot::prelude::*;
+
+#[derive(GodotClass)]
+#[class(init, base=Resource)]
+pub struct ReplicatedProperty {
+    // the node and property relative to the NetworkedNode, like ..:position or Head:rotation:y
+    #[export]
+    pub path: NodePath,
+    #[export(enum = (Nil = -2, Bool = 0, Unsigned8 = 1, Unsigned16 = 2, Signed64 = 3, Float32 = 4, Vector3 = 5, String = 6, ByteArray = 7, Signed32 = 8, Float64 = 9, Vector2 = 10, Quaternion = 11, Basis = 12, Transform3D = 13, Color = 14, StringName = 15, Float32Array = 16, Array = 17, Dictionary = 18, VarUInt = 19, VarInt = 20, Bounded = 21))]
+    pub value_type: i64,
+    // the range of bounded and quantized values, inclusive
+    #[export]
+    pub min: f64,
+    #[export]
+    pub max: f64,
+    // when above 0 floats and vectors are rounded to a multiple of this above min and sent as bounded integers
+    #[export]
+    pub step: f64,
+    base: Base<Resource>,
+}
+
+enum Encoding {
+    Value,
+    // each component is sent as the number of steps above min
+    Quantized {
+        components: usize,
+        min: f64,
+        step: f64,
+        range: BoundedRange,
+    },
+}
+
+struct Property {
+    // empty for properties on the NetworkedNode itself
+    node: NodePath,
+    property: NodePath,
+    field: Field,
+    encoding: Encoding,
+}
+
+pub struct Replication {
+    properties: Vec<Property>,
+}
+impl Replication {
+    pub fn from_properties(properties: &Array<Gd<ReplicatedProperty>>) -> Result<Self, String> {
+        let mut replication = Replication {
+            properties: Vec::new(),
+        };
+        for property in properties.iter_shared() {
+            let property = property.bind();
+            let name = property.path.to_string();
+            let subnames = property.path.get_concatenated_subnames().to_string();
+            if subnames.is_empty() {
+                return Err(format!(
+                    "\"{}\" doesn't name a property, use node:property",
+                    name
+                ));
+            }
+            let mut node = property.path.get_concatenated_names().to_string();
+            if property.path.is_absolute() {
+                node.insert(0, '/');
+            }
+            let value_type = if property.value_type == BOUNDED_FIELD_TYPE {
+                BoundedRange::new(property.min as i64, property.max as i64)
+                    .map(NetworkedValueTypes::Bounded)
+                    .map_err(|x| format!("\"{}\" has an invalid range: {}", name, x))?
+            } else {
+                NetworkedValueTypes::try_from(property.value_type)
+                    .map_err(|x| format!("\"{}\" has an invalid type: {}", name, x))?
+            };
+            let encoding = if property.step > 0.0 {
+                let components = match value_type {
+                    NetworkedValueTypes::Float32 | NetworkedValueTypes::Float64 => 1,
+                    NetworkedValueTypes::Vector2 => 2,
+                    NetworkedValueTypes::Vector3 => 3,
+                    _ => {
+                        return Err(format!(
+                            "\"{}\" can't be quantized, only floats and vectors can",
+                            name
+                        ));
+                    }
+                };
+                let span = property.max - property.min;
+                if !span.is_finite() || span <= 0.0 || !property.step.is_finite() {
+                    return Err(format!(
+                        "\"{}\" needs a max above min to be quantized",
+                        name
+                    ));
+                }
+                let steps = (span / property.step).ceil() as i64;
+                Encoding::Quantized {
+                    components,
+                    min: property.min,
+                    step: property.step,
+                    range: BoundedRange::new(0, steps)
+                        .map_err(|x| format!("\"{}\" has too many steps: {}", name, x))?,
+                }
+            } else {
+                Encoding::Value
+            };
+            replication.properties.push(Property {
+                node: NodePath::from(node.as_str()),
+                property: NodePath::from(subnames.as_str()),
+                field: Field {
+                    name,
+                    value_type,
+                    optional: false,
+                    array: false,
+                },
+                encoding,
+            });
+        }
+        Ok(replication)
+    }
+    // reads every property, fails if any node is missing or a property has the wrong type
+    pub fn encode(&self, owner: &Gd<Node>) -> Result<BitVec<u64, Lsb0>, String> {
+        let mut data: BitVec<u64, Lsb0> = BitVec::new();
+        for property in self.properties.iter() {
+            let node = property
+                .target(owner)
+                .ok_or_else(|| format!("couldn't find the node for \"{}\"", property.field.name))?;
+            let value =
+                schema::check_value(&property.field, &node.get_indexed(&property.property))?;
+            match property.encoding {
+                Encoding::Value => data.extend(serializer::encode_with_known_type(
+                    &value,
+                    &property.field.value_type,
+                )),
+                Encoding::Quantized {
+                    components,
+                    min,
+                    step,
+                    range,
+                } => {
+                    for component in components_of(&value, components) {
+                        let steps = ((component - min) / step)
+                            .round()
+                            .clamp(range.min() as f64, range.max() as f64);
+                        data.extend(NetValue::Bounded(steps as i64, range).encode());
+                    }
+                }
+            }
+        }
+        Ok(data)
+    }
+    // values are always read so the pointer ends up after this node even if a target is missing
+    // a missing target is our own scene's fault so it is reported separately from values that couldn't be decoded
+    pub fn decode(
+        &self,
+        owner: &Gd<Node>,
+        data: &BitSlice<u64>,
+        pointer: &mut usize,
+    ) -> Result<(), UpdateError> {
+        let mut missing: Option<UpdateError> = None;
+        for property in self.properties.iter() {
+            let value = match property.encoding {
+                Encoding::Value => {
+                    serializer::decode_with_known_type(data, pointer, &property.field.value_type)
+                }
+                Encoding::Quantized {
+                    components,
+                    min,
+                    step,
+                    range,
+                } => (0..components)
+                    .map(|_| {
+                        match serializer::decode_value(
+                            data,
+                            pointer,
+                            &NetworkedValueTypes::Bounded(range),
+                        )? {
+                            NetValue::Bounded(steps, _) => Some(min + steps as f64 * step),
+                            _ => None,
+                        }
+                    })
+                    .collect::<Option<Vec<f64>>>()
+                    .map(|x| from_components(&x, &property.field.value_type)),
+            }
+            .ok_or_else(|| {
+                UpdateError::Malformed(format!("\"{}\" could not be decoded", property.field.name))
+            })?;
+            match property.target(owner) {
+                Some(mut node) => node.set_indexed(&property.property, &value),
+                None => {
+                    missing.get_or_insert(UpdateError::Local(format!(
+                        "couldn't find the node for \"{}\"",
+                        property.field.name
+                    )));
+                }
+            }
+        }
+        missing.map_or(Ok(()), Err)
+    }
+}
+impl Property {
+    fn target(&self, owner: &Gd<Node>) -> Option<Gd<Node>> {
+        if self.node.is_empty() {
+            return Some(owner.clone());
+        }
+        owner.get_node_or_null(&self.node)
+    }
+}
+
+fn components_of(value: &Variant, components: usize) -> Vec<f64> {
+    match components {
+        1 => vec![value.to::<f64>()],
+        2 => {
+            let vector = value.to::<Vector2>();
+            vec![vector.x as f64, vector.y as f64]
+        }
+        _ => {
+            let vector = value.to::<Vector3>();
+            vec![vector.x as f64, vector.y as f64, vector.z as f64]
+        }
+    }
+}
+fn from_components(components: &[f64], value_type: &NetworkedValueTypes) -> Variant {
+    match value_type {
+        NetworkedValueTypes::Float32 => (components[0] as f32).to_variant(),
+        NetworkedValueTypes::Vector2 => {
+            Vector2::new(components[0] as f32, components[1] as f32).to_variant()
+        }
+        NetworkedValueTypes::Vector3 => Vector3::new(
+            components[0] as f32,
+            components[1] as f32,
+            components[2] as f32,
+        )
+        .to_variant(),
+        _ => components[0].to_variant(),
+    }
+}
@@ -0,0 +1,560 @@
+// describes the fields of a message so handlers dont need to implement get_value_type
+use crate::serializer::{self, BoundedRange, NetValue, NetworkedValueTypes};
+use bitvec::prelude::*;
+use godot::prelude::*;
+use std::collections::HashSet;
+
+const BYTE: usize = 8;
+const BYTES2: usize = 16;
+// bounded fields use this in the inspector and take their range from min and max
+pub const BOUNDED_FIELD_TYPE: i64 = 21;
+
+#[derive(GodotClass)]
+#[class(init, base=Resource)]
+pub struct MessageField {
+    // the key used for this field when sending a dictionary
+    #[export]
+    pub name: GString,
+    #[export(enum = (Nil = -2, Bool = 0, Unsigned8 = 1, Unsigned16 = 2, Signed64 = 3, Float32 = 4, Vector3 = 5, String = 6, ByteArray = 7, Signed32 = 8, Float64 = 9, Vector2 = 10, Quaternion = 11, Basis = 12, Transform3D = 13, Color = 14, StringName = 15, Float32Array = 16, Array = 17, Dictionary = 18, VarUInt = 19, VarInt = 20, Bounded = 21))]
+    pub value_type: i64,
+    // optional fields take a single bit when left out and are null when received
+    #[export]
+    pub optional: bool,
+    // the value is an array of value_type, sent with a 16 bit length
+    #[export]
+    pub array: bool,
+    // the range of a bounded field, inclusive
+    #[export]
+    pub min: i64,
+    #[export]
+    pub max: i64,
+    base: Base<Resource>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub name: String,
+    pub value_type: NetworkedValueTypes,
+    pub optional: bool,
+    pub array: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    // an optional field that was left out
+    Absent,
+    Single(NetValue),
+    Array(Vec<NetValue>),
+}
+impl FieldValue {
+    pub fn to_variant(&self) -> Variant {
+        match self {
+            FieldValue::Absent => Variant::nil(),
+            FieldValue::Single(value) => value.to_variant(),
+            FieldValue::Array(values) => values
+                .iter()
+                .map(|x| x.to_variant())
+                .collect::<VariantArray>()
+                .to_variant(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schema {
+    pub fields: Vec<Field>,
+}
+impl Schema {
+    pub fn from_fields(fields: &Array<Gd<MessageField>>) -> Result<Self, String> {
+        let mut names: HashSet<String> = HashSet::new();
+        let mut schema = Schema { fields: Vec::new() };
+        for (idx, field) in fields.iter_shared().enumerate() {
+            let field = field.bind();
+            let name = field.name.to_string();
+            if name.is_empty() {
+                return Err(format!("field {} has no name", idx));
+            }
+            if !names.insert(name.clone()) {
+                return Err(format!("field \"{}\" is declared more than once", name));
+            }
+            let value_type = if field.value_type == BOUNDED_FIELD_TYPE {
+                BoundedRange::new(field.min, field.max)
+                    .map(NetworkedValueTypes::Bounded)
+                    .map_err(|x| format!("field \"{}\" has an invalid range: {}", name, x))?
+            } else {
+                NetworkedValueTypes::try_from(field.value_type)
+                    .map_err(|x| format!("field \"{}\" has an invalid type: {}", name, x))?
+            };
+            schema.fields.push(Field {
+                name,
+                value_type,
+                optional: field.optional,
+                array: field.array,
+            });
+        }
+        Ok(schema)
+    }
+    // values keyed by field name, optional fields can be left out or set to null
+    pub fn encode_dictionary(&self, values: &Dictionary) -> Result<BitVec<u64, Lsb0>, String> {
+        for key in values.keys_array().iter_shared() {
+            let key = key.to_string();
+            if !self.fields.iter().any(|x| x.name == key) {
+                return Err(format!("message has no field named \"{}\"", key));
+            }
+        }
+        let values: Vec<Variant> = self
+            .fields
+            .iter()
+            .map(|x| values.get(x.name.as_str()).unwrap_or_default())
+            .collect();
+        self.encode(&values)
+    }
+    // values in the order the fields are declared
+    pub fn encode(&self, values: &[Variant]) -> Result<BitVec<u64, Lsb0>, String> {
+        if values.len() > self.fields.len() {
+            return Err(format!(
+                "got {} values but the message only has {} fields",
+                values.len(),
+                self.fields.len()
+            ));
+        }
+        let mut fields: Vec<FieldValue> = Vec::with_capacity(self.fields.len());
+        for (idx, field) in self.fields.iter().enumerate() {
+            let value = values.get(idx).cloned().unwrap_or_default();
+            if field.optional && value.is_nil() {
+                fields.push(FieldValue::Absent);
+                continue;
+            }
+            if field.array {
+                let Ok(items) = value.try_to::<VariantArray>() else {
+                    return Err(format!("field \"{}\" must be an array", field.name));
+                };
+                fields.push(FieldValue::Array(
+                    items
+                        .iter_shared()
+                        .map(|x| to_net_value(field, &x))
+                        .collect::<Result<_, _>>()?,
+                ));
+            } else {
+                fields.push(FieldValue::Single(to_net_value(field, &value)?));
+            }
+        }
+        self.encode_fields(&fields)
+    }
+    // the wire format of values that have already been checked against their fields
+    pub fn encode_fields(&self, values: &[FieldValue]) -> Result<BitVec<u64, Lsb0>, String> {
+        if values.len() != self.fields.len() {
+            return Err(format!(
+                "got {} values but the message has {} fields",
+                values.len(),
+                self.fields.len()
+            ));
+        }
+        let mut data: BitVec<u64, Lsb0> = BitVec::new();
+        for (field, value) in self.fields.iter().zip(values) {
+            if field.optional {
+                data.push(*value != FieldValue::Absent);
+            }
+            match (value, field.array) {
+                (FieldValue::Absent, _) if field.optional => {}
+                (FieldValue::Absent, _) => {
+                    return Err(format!("missing value for field \"{}\"", field.name));
+                }
+                (FieldValue::Array(items), true) => {
+                    let length = u16::try_from(items.len())
+                        .map_err(|_| format!("field \"{}\" has too many items", field.name))?;
+                    data.extend(length.view_bits::<Lsb0>());
+                    for item in items {
+                        encode_value(&mut data, field, item)?;
+                    }
+                }
+                (FieldValue::Single(value), false) => encode_value(&mut data, field, value)?,
+                (_, true) => return Err(format!("field \"{}\" must be an array", field.name)),
+                (_, false) => {
+                    return Err(format!("field \"{}\" must not be an array", field.name));
+                }
+            }
+        }
+        Ok(data)
+    }
+    // reads every field, fails on truncated packets or leftover data instead of guessing
+    pub fn decode_fields(
+        &self,
+        data: &BitSlice<u64>,
+        pointer: &mut usize,
+    ) -> Result<Vec<FieldValue>, String> {
+        let mut values = Vec::with_capacity(self.fields.len());
+        for field in self.fields.iter() {
+            if field.optional {
+                let present = *data
+                    .get(*pointer)
+                    .ok_or_else(|| format!("message ended before field \"{}\"", field.name))?;
+                *pointer += 1;
+                if !present {
+                    values.push(FieldValue::Absent);
+                    continue;
+                }
+            }
+            if field.array {
+                let length: u16 = data
+                    .get(*pointer..*pointer + BYTES2)
+                    .ok_or_else(|| format!("message ended before field \"{}\"", field.name))?
+                    .load_le();
+                *pointer += BYTES2;
+                let mut items = Vec::new();
+                for _ in 0..length {
+                    items.push(decode_value(data, pointer, field)?);
+                }
+                values.push(FieldValue::Array(items));
+            } else {
+                values.push(FieldValue::Single(decode_value(data, pointer, field)?));
+            }
+        }
+        // packets are sent as whole bytes so up to a byte of padding is expected
+        if data.len().saturating_sub(*pointer) >= BYTE {
+            return Err(format!(
+                "message has {} bits left over after its last field",
+                data.len() - *pointer
+            ));
+        }
+        Ok(values)
+    }
+    pub fn decode(
+        &self,
+        data: &BitSlice<u64>,
+        pointer: &mut usize,
+    ) -> Result<VariantArray, String> {
+        Ok(self
+            .decode_fields(data, pointer)?
+            .iter()
+            .map(|x| x.to_variant())
+            .collect())
+    }
+    pub fn to_dictionary(&self, values: &VariantArray) -> Dictionary {
+        let mut dictionary = Dictionary::new();
+        for (field, value) in self.fields.iter().zip(values.iter_shared()) {
+            dictionary.set(field.name.as_str(), value);
+        }
+        dictionary
+    }
+}
+
+fn to_net_value(field: &Field, value: &Variant) -> Result<NetValue, String> {
+    if value.is_nil() && field.value_type != NetworkedValueTypes::Nil {
+        return Err(format!("missing value for field \"{}\"", field.name));
+    }
+    let value = check_value(field, value)?;
+    Ok(NetValue::from_variant(&value, &field.value_type))
+}
+fn encode_value(
+    data: &mut BitVec<u64, Lsb0>,
+    field: &Field,
+    value: &NetValue,
+) -> Result<(), String> {
+    if value.value_type() != field.value_type {
+        return Err(format!(
+            "field \"{}\" expects {:?} but got {:?}",
+            field.name,
+            field.value_type,
+            value.value_type()
+        ));
+    }
+    data.extend(value.encode());
+    Ok(())
+}
+fn decode_value(
+    data: &BitSlice<u64>,
+    pointer: &mut usize,
+    field: &Field,
+) -> Result<NetValue, String> {
+    serializer::decode_value(data, pointer, &field.value_type)
+        .ok_or_else(|| format!("field \"{}\" could not be decoded", field.name))
+}
+// makes sure a value can be encoded as the field's type, the serializer panics on values of the wrong type
+pub fn check_value(field: &Field, value: &Variant) -> Result<Variant, String> {
+    let wrong_type = || {
+        format!(
+            "field \"{}\" expects {:?} but got {:?}",
+            field.name,
+            field.value_type,
+            value.get_type()
+        )
+    };
+    let out_of_range = || format!("value for field \"{}\" is out of range", field.name);
+    match field.value_type {
+        NetworkedValueTypes::Nil => Ok(Variant::nil()),
+        NetworkedValueTypes::Bool => value
+            .try_to::<bool>()
+            .map(|x| x.to_variant())
+            .map_err(|_| wrong_type()),
+        NetworkedValueTypes::Unsigned8 => {
+            let value = value.try_to::<i64>().map_err(|_| wrong_type())?;
+            u8::try_from(value)
+                .map(|x| x.to_variant())
+                .map_err(|_| out_of_range())
+        }
+        NetworkedValueTypes::Unsigned16 => {
+            let value = value.try_to::<i64>().map_err(|_| wrong_type())?;
+            u16::try_from(value)
+                .map(|x| x.to_variant())
+                .map_err(|_| out_of_range())
+        }
+        NetworkedValueTypes::Signed64 => value
+            .try_to::<i64>()
+            .map(|x| x.to_variant())
+            .map_err(|_| wrong_type()),
+        // gdext only converts a variant to its own type so ints are converted by hand
+        NetworkedValueTypes::Float32 => match value.get_type() {
+            VariantType::FLOAT => Ok(value.to::<f64>().to_variant()),
+            VariantType::INT => Ok((value.to::<i64>() as f64).to_variant()),
+            _ => Err(wrong_type()),
+        },
+        NetworkedValueTypes::Vector3 => value
+            .try_to::<Vector3>()
+            .map(|x| x.to_variant())
+            .map_err(|_| wrong_type()),
+        NetworkedValueTypes::String => match value.get_type() {
+            VariantType::STRING | VariantType::STRING_NAME | VariantType::NODE_PATH => {
+                Ok(value.stringify().to_variant())
+            }
+            _ => Err(wrong_type()),
+        },
+        NetworkedValueTypes::ByteArray => match value.get_type() {
+            VariantType::PACKED_BYTE_ARRAY => Ok(value.clone()),
+            VariantType::ARRAY => value
+                .to::<VariantArray>()
+                .iter_shared()
+                .map(|x| x.try_to::<u8>().ok())
+                .collect::<Option<Vec<u8>>>()
+                .map(|x| PackedByteArray::from(x.as_slice()).to_variant())
+                .ok_or_else(|| format!("field \"{}\" must only contain bytes", field.name)),
+            _ => Err(wrong_type()),
+        },
+        NetworkedValueTypes::Signed32 => {
+            let value = value.try_to::<i64>().map_err(|_| wrong_type())?;
+            i32::try_from(value)
+                .map(|x| x.to_variant())
+                .map_err(|_| out_of_range())
+        }
+        NetworkedValueTypes::Float64 => match value.get_type() {
+            VariantType::FLOAT => Ok(value.to::<f64>().to_variant()),
+            VariantType::INT => Ok((value.to::<i64>() as f64).to_variant()),
+            _ => Err(wrong_type()),
+        },
+        NetworkedValueTypes::Vector2 => value
+            .try_to::<Vector2>()
+            .map(|x| x.to_variant())
+            .map_err(|_| wrong_type()),
+        NetworkedValueTypes::Quaternion => value
+            .try_to::<Quaternion>()
+            .map(|x| x.to_variant())
+            .map_err(|_| wrong_type()),
+        NetworkedValueTypes::Basis => value
+            .try_to::<Basis>()
+            .map(|x| x.to_variant())
+            .map_err(|_| wrong_type()),
+        NetworkedValueTypes::Transform3D => value
+            .try_to::<Transform3D>()
+            .map(|x| x.to_variant())
+            .map_err(|_| wrong_type()),
+        NetworkedValueTypes::Color => value
+            .try_to::<Color>()
+            .map(|x| x.to_variant())
+            .map_err(|_| wrong_type()),
+        NetworkedValueTypes::StringName => match value.get_type() {
+            VariantType::STRING | VariantType::STRING_NAME => {
+                Ok(StringName::from(&value.stringify()).to_variant())
+            }
+            _ => Err(wrong_type()),
+        },
+        NetworkedValueTypes::Float32Array => match value.get_type() {
+            VariantType::PACKED_FLOAT32_ARRAY => Ok(value.clone()),
+            VariantType::ARRAY => value
+                .to::<VariantArray>()
+                .iter_shared()
+                .map(|x| match x.get_type() {
+                    VariantType::FLOAT => Some(x.to::<f64>() as f32),
+                    VariantType::INT => Some(x.to::<i64>() as f32),
+                    _ => None,
+                })
+                .collect::<Option<Vec<f32>>>()
+                .map(|x| PackedFloat32Array::from(x.as_slice()).to_variant())
+                .ok_or_else(|| format!("field \"{}\" must only contain numbers", field.name)),
+            _ => Err(wrong_type()),
+        },
+        NetworkedValueTypes::VarUInt => {
+            let value = value.try_to::<i64>().map_err(|_| wrong_type())?;
+            if value < 0 {
+                return Err(out_of_range());
+            }
+            Ok(value.to_variant())
+        }
+        NetworkedValueTypes::VarInt => value
+            .try_to::<i64>()
+            .map(|x| x.to_variant())
+            .map_err(|_| wrong_type()),
+        NetworkedValueTypes::Bounded(range) => {
+            let value = value.try_to::<i64>().map_err(|_| wrong_type())?;
+            if !range.contains(value) {
+                return Err(format!(
+                    "value {} for field \"{}\" is outside of {}..={}",
+                    value,
+                    field.name,
+                    range.min(),
+                    range.max()
+                ));
+            }
+            Ok(value.to_variant())
+        }
+        NetworkedValueTypes::Array | NetworkedValueTypes::Dictionary => {
+            if NetworkedValueTypes::of_variant(value.get_type()) != Some(field.value_type) {
+                return Err(wrong_type());
+            }
+            match NetValue::from_untyped(value) {
+                Some(_) => Ok(value.clone()),
+                None => Err(format!(
+                    "field \"{}\" contains values that can't be sent or is nested too deeply",
+                    field.name
+                )),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(name: &str, value_type: NetworkedValueTypes, optional: bool, array: bool) -> Field {
+        Field {
+            name: name.to_string(),
+            value_type,
+            optional,
+            array,
+        }
+    }
+    fn round_trip(schema: &Schema, values: &[FieldValue]) -> Vec<FieldValue> {
+        let data = schema.encode_fields(values).unwrap();
+        let mut pointer = 0;
+        let decoded = schema.decode_fields(&data, &mut pointer).unwrap();
+        assert_eq!(pointer, data.len());
+        decoded
+    }
+
+    #[test]
+    fn optional_fields_can_be_left_out() {
+        let schema = Schema {
+            fields: vec![
+                field("emote", NetworkedValueTypes::Unsigned8, true, false),
+                field("target", NetworkedValueTypes::Unsigned16, false, false),
+            ],
+        };
+        let absent = [
+            FieldValue::Absent,
+            FieldValue::Single(NetValue::Unsigned16(7)),
+        ];
+        assert_eq!(schema.encode_fields(&absent).unwrap().len(), 1 + 16);
+        assert_eq!(round_trip(&schema, &absent), absent);
+        let present = [
+            FieldValue::Single(NetValue::Unsigned8(3)),
+            FieldValue::Single(NetValue::Unsigned16(7)),
+        ];
+        assert_eq!(round_trip(&schema, &present), present);
+        assert!(
+            schema
+                .encode_fields(&[
+                    FieldValue::Single(NetValue::Unsigned8(3)),
+                    FieldValue::Absent
+                ])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn arrays_are_prefixed_with_their_length() {
+        let schema = Schema {
+            fields: vec![field("bytes", NetworkedValueTypes::Unsigned8, false, true)],
+        };
+        let items: Vec<NetValue> = [1, 2, 3].map(NetValue::Unsigned8).to_vec();
+        let data = schema
+            .encode_fields(&[FieldValue::Array(items.clone())])
+            .unwrap();
+        assert_eq!(data[..16].load_le::<u16>(), 3);
+        assert_eq!(data.len(), 16 + 3 * 8);
+        assert_eq!(
+            schema.decode_fields(&data, &mut 0),
+            Ok(vec![FieldValue::Array(items)])
+        );
+        assert!(schema.decode_fields(&data[..8], &mut 0).is_err());
+    }
+
+    #[test]
+    fn array_lengths_cant_overrun_the_message() {
+        let schema = Schema {
+            fields: vec![field("bytes", NetworkedValueTypes::Unsigned8, false, true)],
+        };
+        let mut data: BitVec<u64, Lsb0> = BitVec::new();
+        data.extend(200u16.view_bits::<Lsb0>());
+        data.extend([1u8, 2, 3].view_bits::<Lsb0>());
+        assert!(schema.decode_fields(&data, &mut 0).is_err());
+    }
+
+    #[test]
+    fn leftover_bytes_are_rejected() {
+        let schema = Schema {
+            fields: vec![field("flag", NetworkedValueTypes::Bool, false, false)],
+        };
+        let mut data: BitVec<u64, Lsb0> = BitVec::repeat(true, 1 + 7);
+        assert_eq!(
+            schema.decode_fields(&data, &mut 0),
+            Ok(vec![FieldValue::Single(NetValue::Bool(true))])
+        );
+        data.push(false);
+        assert!(schema.decode_fields(&data, &mut 0).is_err());
+    }
+
+    #[test]
+    fn fields_round_trip() {
+        let range = BoundedRange::new(-10, 10).unwrap();
+        let schema = Schema {
+            fields: vec![
+                field("speed", NetworkedValueTypes::Float32, false, false),
+                field("time", NetworkedValueTypes::Float64, false, false),
+                field("name", NetworkedValueTypes::String, false, false),
+                field("offset", NetworkedValueTypes::Bounded(range), false, false),
+                field("path", NetworkedValueTypes::Vector3, true, true),
+                field("note", NetworkedValueTypes::String, true, false),
+            ],
+        };
+        let values = [
+            FieldValue::Single(NetValue::Float32(2.5)),
+            FieldValue::Single(NetValue::Float64(-1e9)),
+            FieldValue::Single(NetValue::String("player".to_string())),
+            FieldValue::Single(NetValue::Bounded(-4, range)),
+            FieldValue::Array(vec![
+                NetValue::Vector3([1.0, 2.0, 3.0]),
+                NetValue::Vector3([0.0, -0.5, 8.0]),
+            ]),
+            FieldValue::Absent,
+        ];
+        assert_eq!(round_trip(&schema, &values), values);
+    }
+
+    #[test]
+    fn values_must_match_their_field() {
+        let schema = Schema {
+            fields: vec![field("speed", NetworkedValueTypes::Float32, false, false)],
+        };
+        assert!(
+            schema
+                .encode_fields(&[FieldValue::Single(NetValue::Float64(1.0))])
+                .is_err()
+        );
+        assert!(
+            schema
+                .encode_fields(&[FieldValue::Array(vec![NetValue::Float32(1.0)])])
+                .is_err()
+        );
+        assert!(schema.encode_fields(&[]).is_err());
+    }
+}
@@ -1,4 +1,5 @@
 // serialization functions for networkednode values
+use crate::logging::log_warn;
 use bitvec::prelude::*;
 use godot::prelude::*;
 use std::{borrow::Cow, i64};
@@ -7,7 +8,16 @@ const BYTE: usize = 8;
 const BYTES2: usize = 16;
 const BYTES4: usize = 32;
 const BYTES8: usize = 64;
+// bounded type ids have this bit set, the span is stored above bit 32 and the minimum in the low 32 bits
+pub const BOUNDED_TYPE_FLAG: i64 = 1 << 62;
+// keeps the span clear of the flag, larger ranges should use VarInt
+pub const MAX_BOUNDED_BITS: u32 = 30;
+// a varint holds 7 bits per byte so a u64 takes at most 10
+const MAX_VARINT_BYTES: usize = 10;
+// how deep arrays and dictionaries can be nested, also stops arrays that contain themselves
+pub const MAX_NESTING: usize = 16;
 // all possible ways a value can be encoded for the network
+// the ids are sent over the network and used by scripts so they must never change
 #[derive(PartialEq, Debug, Copy, Clone)]
 pub enum NetworkedValueTypes {
     Nil,
@@ -19,224 +29,1098 @@ pub enum NetworkedValueTypes {
     Vector3,
     String,
     ByteArray,
+    Signed32,
+    Float64,
+    Vector2,
+    Quaternion,
+    Basis,
+    Transform3D,
+    Color,
+    StringName,
+    Float32Array,
+    // each element is sent with its type
+    Array,
+    // each key and value is sent with its type
+    Dictionary,
+    // 7 bits per byte with the top bit set on every byte but the last, small values take a single byte
+    VarUInt,
+    // zigzag encoded so small negative values stay small
+    VarInt,
+    // an integer between min and max inclusive, sent with just enough bits for the range
+    Bounded(BoundedRange),
+}
+impl NetworkedValueTypes {
+    // every type with a fixed id, bounded types get their id from their range
+    pub const ALL: [NetworkedValueTypes; 22] = [
+        NetworkedValueTypes::Nil,
+        NetworkedValueTypes::Bool,
+        NetworkedValueTypes::Unsigned8,
+        NetworkedValueTypes::Unsigned16,
+        NetworkedValueTypes::Signed64,
+        NetworkedValueTypes::Float32,
+        NetworkedValueTypes::Vector3,
+        NetworkedValueTypes::String,
+        NetworkedValueTypes::ByteArray,
+        NetworkedValueTypes::Signed32,
+        NetworkedValueTypes::Float64,
+        NetworkedValueTypes::Vector2,
+        NetworkedValueTypes::Quaternion,
+        NetworkedValueTypes::Basis,
+        NetworkedValueTypes::Transform3D,
+        NetworkedValueTypes::Color,
+        NetworkedValueTypes::StringName,
+        NetworkedValueTypes::Float32Array,
+        NetworkedValueTypes::Array,
+        NetworkedValueTypes::Dictionary,
+        NetworkedValueTypes::VarUInt,
+        NetworkedValueTypes::VarInt,
+    ];
+    pub fn id(&self) -> i64 {
+        match self {
+            NetworkedValueTypes::Nil => -2,
+            NetworkedValueTypes::Bool => 0,
+            NetworkedValueTypes::Unsigned8 => 1,
+            NetworkedValueTypes::Unsigned16 => 2,
+            NetworkedValueTypes::Signed64 => 3,
+            NetworkedValueTypes::Float32 => 4,
+            NetworkedValueTypes::Vector3 => 5,
+            NetworkedValueTypes::String => 6,
+            NetworkedValueTypes::ByteArray => 7,
+            NetworkedValueTypes::Signed32 => 8,
+            NetworkedValueTypes::Float64 => 9,
+            NetworkedValueTypes::Vector2 => 10,
+            NetworkedValueTypes::Quaternion => 11,
+            NetworkedValueTypes::Basis => 12,
+            NetworkedValueTypes::Transform3D => 13,
+            NetworkedValueTypes::Color => 14,
+            NetworkedValueTypes::StringName => 15,
+            NetworkedValueTypes::Float32Array => 16,
+            NetworkedValueTypes::Array => 17,
+            NetworkedValueTypes::Dictionary => 18,
+            NetworkedValueTypes::VarUInt => 19,
+            NetworkedValueTypes::VarInt => 20,
+            NetworkedValueTypes::Bounded(range) => range.id(),
+        }
+    }
+    // the type used when a value is sent without a known type, like inside an array
+    pub fn of_variant(variant_type: VariantType) -> Option<Self> {
+        Some(match variant_type {
+            VariantType::NIL => NetworkedValueTypes::Nil,
+            VariantType::BOOL => NetworkedValueTypes::Bool,
+            VariantType::INT => NetworkedValueTypes::Signed64,
+            VariantType::FLOAT => NetworkedValueTypes::Float64,
+            VariantType::STRING => NetworkedValueTypes::String,
+            VariantType::VECTOR2 => NetworkedValueTypes::Vector2,
+            VariantType::VECTOR3 => NetworkedValueTypes::Vector3,
+            VariantType::QUATERNION => NetworkedValueTypes::Quaternion,
+            VariantType::BASIS => NetworkedValueTypes::Basis,
+            VariantType::TRANSFORM3D => NetworkedValueTypes::Transform3D,
+            VariantType::COLOR => NetworkedValueTypes::Color,
+            VariantType::STRING_NAME => NetworkedValueTypes::StringName,
+            VariantType::PACKED_BYTE_ARRAY => NetworkedValueTypes::ByteArray,
+            VariantType::PACKED_FLOAT32_ARRAY => NetworkedValueTypes::Float32Array,
+            VariantType::ARRAY => NetworkedValueTypes::Array,
+            VariantType::DICTIONARY => NetworkedValueTypes::Dictionary,
+            _ => return None,
+        })
+    }
 }
 impl TryFrom<i64> for NetworkedValueTypes {
     type Error = Cow<'static, str>;
     fn try_from(value: i64) -> Result<Self, Self::Error> {
-        match value {
-            -2 => Ok(NetworkedValueTypes::Nil),
-            -1 => Err(Cow::Borrowed("invalid type")),
-            0 => Ok(NetworkedValueTypes::Bool),
-            1 => Ok(NetworkedValueTypes::Unsigned8),
-            2 => Ok(NetworkedValueTypes::Unsigned16),
-            3 => Ok(NetworkedValueTypes::Signed64),
-            4 => Ok(NetworkedValueTypes::Float32),
-            5 => Ok(NetworkedValueTypes::Vector3),
-            6 => Ok(NetworkedValueTypes::String),
-            7 => Ok(NetworkedValueTypes::ByteArray),
-            _ => Err(Cow::Owned(format!(
-                "tried to parse nonexistent type {:#?}",
-                value
-            ))),
+        if value == -1 {
+            return Err(Cow::Borrowed("invalid type"));
+        }
+        if value > 0 && value & BOUNDED_TYPE_FLAG != 0 {
+            return BoundedRange::from_id(value)
+                .map(NetworkedValueTypes::Bounded)
+                .ok_or(Cow::Borrowed("invalid bounded type"));
         }
+        NetworkedValueTypes::ALL
+            .into_iter()
+            .find(|x| x.id() == value)
+            .ok_or_else(|| Cow::Owned(format!("tried to parse nonexistent type {:#?}", value)))
     }
 }
-pub fn decode_with_known_type(
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub struct BoundedRange {
+    min: i32,
+    // max - min
+    span: u32,
+}
+impl BoundedRange {
+    pub fn new(min: i64, max: i64) -> Result<Self, String> {
+        if min > max {
+            return Err(format!("bounded range {}..={} is empty", min, max));
+        }
+        let min32 = i32::try_from(min)
+            .map_err(|_| format!("bounded minimum {} doesn't fit in 32 bits", min))?;
+        let span = max
+            .checked_sub(min)
+            .and_then(|x| u32::try_from(x).ok())
+            .filter(|x| *x < 1 << MAX_BOUNDED_BITS)
+            .ok_or_else(|| {
+                format!(
+                    "bounded range {}..={} is larger than {} bits, use VarInt instead",
+                    min, max, MAX_BOUNDED_BITS
+                )
+            })?;
+        Ok(BoundedRange { min: min32, span })
+    }
+    pub fn min(&self) -> i64 {
+        self.min as i64
+    }
+    pub fn max(&self) -> i64 {
+        self.min as i64 + self.span as i64
+    }
+    pub fn contains(&self, value: i64) -> bool {
+        value >= self.min() && value <= self.max()
+    }
+    // ceil(log2(max - min + 1)), a range with one value takes no bits
+    pub fn bits(&self) -> usize {
+        (u32::BITS - self.span.leading_zeros()) as usize
+    }
+    pub fn id(&self) -> i64 {
+        BOUNDED_TYPE_FLAG | (self.span as i64) << 32 | self.min as u32 as i64
+    }
+    pub fn from_id(id: i64) -> Option<Self> {
+        if id & BOUNDED_TYPE_FLAG == 0 || id < 0 {
+            return None;
+        }
+        let span = ((id & !BOUNDED_TYPE_FLAG) >> 32) as u32;
+        let min = id as u32 as i32;
+        Self::new(min as i64, min as i64 + span as i64).ok()
+    }
+}
+// a decoded value without any godot types, so decoding can be used and tested without the engine
+#[derive(PartialEq, Debug, Clone)]
+pub enum NetValue {
+    Nil,
+    Bool(bool),
+    Unsigned8(u8),
+    Unsigned16(u16),
+    Signed64(i64),
+    Float32(f32),
+    Vector3([f32; 3]),
+    String(String),
+    ByteArray(Vec<u8>),
+    Signed32(i32),
+    Float64(f64),
+    Vector2([f32; 2]),
+    // x, y, z, w
+    Quaternion([f32; 4]),
+    // rows one after another
+    Basis([f32; 9]),
+    // the basis rows followed by the origin
+    Transform3D([f32; 12]),
+    // r, g, b, a
+    Color([f32; 4]),
+    StringName(String),
+    Float32Array(Vec<f32>),
+    Array(Vec<NetValue>),
+    Dictionary(Vec<(NetValue, NetValue)>),
+    VarUInt(u64),
+    VarInt(i64),
+    // always inside the range
+    Bounded(i64, BoundedRange),
+}
+impl NetValue {
+    pub fn value_type(&self) -> NetworkedValueTypes {
+        match self {
+            NetValue::Nil => NetworkedValueTypes::Nil,
+            NetValue::Bool(_) => NetworkedValueTypes::Bool,
+            NetValue::Unsigned8(_) => NetworkedValueTypes::Unsigned8,
+            NetValue::Unsigned16(_) => NetworkedValueTypes::Unsigned16,
+            NetValue::Signed64(_) => NetworkedValueTypes::Signed64,
+            NetValue::Float32(_) => NetworkedValueTypes::Float32,
+            NetValue::Vector3(_) => NetworkedValueTypes::Vector3,
+            NetValue::String(_) => NetworkedValueTypes::String,
+            NetValue::ByteArray(_) => NetworkedValueTypes::ByteArray,
+            NetValue::Signed32(_) => NetworkedValueTypes::Signed32,
+            NetValue::Float64(_) => NetworkedValueTypes::Float64,
+            NetValue::Vector2(_) => NetworkedValueTypes::Vector2,
+            NetValue::Quaternion(_) => NetworkedValueTypes::Quaternion,
+            NetValue::Basis(_) => NetworkedValueTypes::Basis,
+            NetValue::Transform3D(_) => NetworkedValueTypes::Transform3D,
+            NetValue::Color(_) => NetworkedValueTypes::Color,
+            NetValue::StringName(_) => NetworkedValueTypes::StringName,
+            NetValue::Float32Array(_) => NetworkedValueTypes::Float32Array,
+            NetValue::Array(_) => NetworkedValueTypes::Array,
+            NetValue::Dictionary(_) => NetworkedValueTypes::Dictionary,
+            NetValue::VarUInt(_) => NetworkedValueTypes::VarUInt,
+            NetValue::VarInt(_) => NetworkedValueTypes::VarInt,
+            NetValue::Bounded(_, range) => NetworkedValueTypes::Bounded(*range),
+        }
+    }
+    pub fn to_variant(&self) -> Variant {
+        match self {
+            NetValue::Nil => Variant::nil(),
+            NetValue::Bool(x) => x.to_variant(),
+            NetValue::Unsigned8(x) => x.to_variant(),
+            NetValue::Unsigned16(x) => x.to_variant(),
+            NetValue::Signed64(x) => x.to_variant(),
+            NetValue::Float32(x) => x.to_variant(),
+            NetValue::Vector3(x) => Vector3::new(x[0], x[1], x[2]).to_variant(),
+            NetValue::String(x) => x.to_variant(),
+            NetValue::ByteArray(x) => PackedByteArray::from(x.as_slice()).to_variant(),
+            NetValue::Signed32(x) => x.to_variant(),
+            NetValue::Float64(x) => x.to_variant(),
+            NetValue::Vector2(x) => Vector2::new(x[0], x[1]).to_variant(),
+            NetValue::Quaternion(x) => Quaternion::new(x[0], x[1], x[2], x[3]).to_variant(),
+            NetValue::Basis(x) => basis_from_floats(&x[..9]).to_variant(),
+            NetValue::Transform3D(x) => {
+                Transform3D::new(basis_from_floats(&x[..9]), Vector3::new(x[9], x[10], x[11]))
+                    .to_variant()
+            }
+            NetValue::Color(x) => Color::from_rgba(x[0], x[1], x[2], x[3]).to_variant(),
+            NetValue::StringName(x) => StringName::from(x.as_str()).to_variant(),
+            NetValue::Float32Array(x) => PackedFloat32Array::from(x.as_slice()).to_variant(),
+            NetValue::Array(x) => x
+                .iter()
+                .map(|x| x.to_variant())
+                .collect::<VariantArray>()
+                .to_variant(),
+            NetValue::Dictionary(x) => {
+                let mut dictionary = Dictionary::new();
+                for (key, value) in x {
+                    dictionary.set(key.to_variant(), value.to_variant());
+                }
+                dictionary.to_variant()
+            }
+            // godot ints are signed so the largest values are clamped
+            NetValue::VarUInt(x) => i64::try_from(*x).unwrap_or(i64::MAX).to_variant(),
+            NetValue::VarInt(x) | NetValue::Bounded(x, _) => x.to_variant(),
+        }
+    }
+    // the variant must already hold a value of the right type
+    pub fn from_variant(object: &Variant, object_type: &NetworkedValueTypes) -> Self {
+        match object_type {
+            NetworkedValueTypes::Nil => NetValue::Nil,
+            NetworkedValueTypes::Bool => NetValue::Bool(bool::from_variant(object)),
+            NetworkedValueTypes::Unsigned8 => NetValue::Unsigned8(u8::from_variant(object)),
+            NetworkedValueTypes::Unsigned16 => NetValue::Unsigned16(u16::from_variant(object)),
+            NetworkedValueTypes::Signed64 => NetValue::Signed64(i64::from_variant(object)),
+            NetworkedValueTypes::Float32 => NetValue::Float32(f32::from_variant(object)),
+            NetworkedValueTypes::Vector3 => {
+                let vector = Vector3::from_variant(object);
+                NetValue::Vector3([vector.x, vector.y, vector.z])
+            }
+            NetworkedValueTypes::String => NetValue::String(String::from_variant(object)),
+            // arrays of ints are still accepted for code written before packed arrays were used
+            NetworkedValueTypes::ByteArray => match object.get_type() {
+                VariantType::ARRAY => NetValue::ByteArray(
+                    Array::<i64>::from_variant(object)
+                        .iter_shared()
+                        .map(|x| x.try_into().unwrap_or(0u8))
+                        .collect(),
+                ),
+                _ => NetValue::ByteArray(PackedByteArray::from_variant(object).to_vec()),
+            },
+            NetworkedValueTypes::Signed32 => NetValue::Signed32(i32::from_variant(object)),
+            NetworkedValueTypes::Float64 => NetValue::Float64(f64::from_variant(object)),
+            NetworkedValueTypes::Vector2 => {
+                let vector = Vector2::from_variant(object);
+                NetValue::Vector2([vector.x, vector.y])
+            }
+            NetworkedValueTypes::Quaternion => {
+                let quaternion = Quaternion::from_variant(object);
+                NetValue::Quaternion([quaternion.x, quaternion.y, quaternion.z, quaternion.w])
+            }
+            NetworkedValueTypes::Basis => {
+                NetValue::Basis(basis_to_floats(&Basis::from_variant(object)))
+            }
+            NetworkedValueTypes::Transform3D => {
+                let transform = Transform3D::from_variant(object);
+                let mut floats = [0.0; 12];
+                floats[..9].copy_from_slice(&basis_to_floats(&transform.basis));
+                floats[9..].copy_from_slice(&[
+                    transform.origin.x,
+                    transform.origin.y,
+                    transform.origin.z,
+                ]);
+                NetValue::Transform3D(floats)
+            }
+            NetworkedValueTypes::Color => {
+                let color = Color::from_variant(object);
+                NetValue::Color([color.r, color.g, color.b, color.a])
+            }
+            NetworkedValueTypes::StringName => NetValue::StringName(object.stringify().to_string()),
+            NetworkedValueTypes::Float32Array => {
+                NetValue::Float32Array(PackedFloat32Array::from_variant(object).to_vec())
+            }
+            NetworkedValueTypes::VarUInt => {
+                let value = i64::from_variant(object);
+                if value < 0 {
+                    log_warn!(
+                        Sync,
+                        "tried to send {} as an unsigned varint, sending 0",
+                        value
+                    );
+                }
+                NetValue::VarUInt(value.max(0) as u64)
+            }
+            NetworkedValueTypes::VarInt => NetValue::VarInt(i64::from_variant(object)),
+            NetworkedValueTypes::Bounded(range) => {
+                let value = i64::from_variant(object);
+                if !range.contains(value) {
+                    log_warn!(
+                        Sync,
+                        "{} is outside of {}..={}, sending the closest value",
+                        value,
+                        range.min(),
+                        range.max()
+                    );
+                }
+                NetValue::Bounded(value.clamp(range.min(), range.max()), *range)
+            }
+            NetworkedValueTypes::Array | NetworkedValueTypes::Dictionary => {
+                match NetValue::from_untyped(object) {
+                    Some(value) if value.value_type() == *object_type => value,
+                    _ => {
+                        log_warn!(
+                            Sync,
+                            "{:?} contains values that can't be sent, sending it empty",
+                            object_type
+                        );
+                        if *object_type == NetworkedValueTypes::Array {
+                            NetValue::Array(Vec::new())
+                        } else {
+                            NetValue::Dictionary(Vec::new())
+                        }
+                    }
+                }
+            }
+        }
+    }
+    // picks the type from the variant itself, none if it or anything inside it can't be sent
+    pub fn from_untyped(object: &Variant) -> Option<Self> {
+        Self::from_untyped_at_depth(object, 0)
+    }
+    fn from_untyped_at_depth(object: &Variant, depth: usize) -> Option<Self> {
+        let object_type = NetworkedValueTypes::of_variant(object.get_type())?;
+        match object_type {
+            NetworkedValueTypes::Array => {
+                if depth >= MAX_NESTING {
+                    return None;
+                }
+                VariantArray::from_variant(object)
+                    .iter_shared()
+                    .map(|x| Self::from_untyped_at_depth(&x, depth + 1))
+                    .collect::<Option<Vec<NetValue>>>()
+                    .map(NetValue::Array)
+            }
+            NetworkedValueTypes::Dictionary => {
+                if depth >= MAX_NESTING {
+                    return None;
+                }
+                Dictionary::from_variant(object)
+                    .iter_shared()
+                    .map(|(key, value)| {
+                        Some((
+                            Self::from_untyped_at_depth(&key, depth + 1)?,
+                            Self::from_untyped_at_depth(&value, depth + 1)?,
+                        ))
+                    })
+                    .collect::<Option<Vec<(NetValue, NetValue)>>>()
+                    .map(NetValue::Dictionary)
+            }
+            _ => Some(Self::from_variant(object, &object_type)),
+        }
+    }
+    pub fn encode(&self) -> BitVec<u64, Lsb0> {
+        let mut bitvec = BitVec::new();
+        self.encode_into(&mut bitvec);
+        bitvec
+    }
+    fn encode_into(&self, bitvec: &mut BitVec<u64, Lsb0>) {
+        match self {
+            NetValue::Nil => {}
+            NetValue::Bool(value) => bitvec.push(*value),
+            NetValue::Unsigned8(value) => bitvec.extend(value.view_bits::<Lsb0>()),
+            NetValue::Unsigned16(value) => bitvec.extend(value.view_bits::<Lsb0>()),
+            NetValue::Signed64(value) => bitvec.extend((*value as u64).view_bits::<Lsb0>()),
+            NetValue::Float32(value) => push_f32s(bitvec, &[*value]),
+            NetValue::Vector3(vector) => push_f32s(bitvec, vector),
+            NetValue::String(string) | NetValue::StringName(string) => {
+                push_bytes(bitvec, string.as_bytes())
+            }
+            NetValue::ByteArray(bytes) => push_bytes(bitvec, bytes),
+            NetValue::Signed32(value) => bitvec.extend((*value as u32).view_bits::<Lsb0>()),
+            NetValue::Float64(value) => bitvec.extend(value.to_bits().view_bits::<Lsb0>()),
+            NetValue::Vector2(floats) => push_f32s(bitvec, floats),
+            NetValue::Quaternion(floats) | NetValue::Color(floats) => push_f32s(bitvec, floats),
+            NetValue::Basis(floats) => push_f32s(bitvec, floats),
+            NetValue::Transform3D(floats) => push_f32s(bitvec, floats),
+            NetValue::Float32Array(floats) => {
+                bitvec.extend((floats.len() as u32).view_bits::<Lsb0>());
+                push_f32s(bitvec, floats);
+            }
+            NetValue::Array(values) => {
+                bitvec.extend((values.len() as u32).view_bits::<Lsb0>());
+                for value in values {
+                    value.encode_tagged(bitvec);
+                }
+            }
+            NetValue::Dictionary(entries) => {
+                bitvec.extend((entries.len() as u32).view_bits::<Lsb0>());
+                for (key, value) in entries {
+                    key.encode_tagged(bitvec);
+                    value.encode_tagged(bitvec);
+                }
+            }
+            NetValue::VarUInt(value) => push_varint(bitvec, *value),
+            NetValue::VarInt(value) => push_varint(bitvec, ((value << 1) ^ (value >> 63)) as u64),
+            NetValue::Bounded(value, range) => {
+                let offset = (*value).clamp(range.min(), range.max()) - range.min();
+                bitvec.extend(&(offset as u32).view_bits::<Lsb0>()[..range.bits()]);
+            }
+        }
+    }
+    // values inside arrays and dictionaries are prefixed by their type id
+    fn encode_tagged(&self, bitvec: &mut BitVec<u64, Lsb0>) {
+        // tags are a single byte so bounded values are sent as plain ints
+        if let NetValue::Bounded(value, _) = self {
+            return NetValue::Signed64(*value).encode_tagged(bitvec);
+        }
+        bitvec.extend((self.value_type().id() as i8 as u8).view_bits::<Lsb0>());
+        self.encode_into(bitvec);
+    }
+}
+fn basis_to_floats(basis: &Basis) -> [f32; 9] {
+    let mut floats = [0.0; 9];
+    for (idx, row) in basis.rows.iter().enumerate() {
+        floats[idx * 3..idx * 3 + 3].copy_from_slice(&[row.x, row.y, row.z]);
+    }
+    floats
+}
+fn basis_from_floats(floats: &[f32]) -> Basis {
+    Basis::from_rows(
+        Vector3::new(floats[0], floats[1], floats[2]),
+        Vector3::new(floats[3], floats[4], floats[5]),
+        Vector3::new(floats[6], floats[7], floats[8]),
+    )
+}
+fn push_f32s(bitvec: &mut BitVec<u64, Lsb0>, floats: &[f32]) {
+    for float in floats {
+        bitvec.extend(float.to_bits().view_bits::<Lsb0>());
+    }
+}
+// strings and byte arrays are sent as a 32 bit length followed by the bytes
+fn push_bytes(bitvec: &mut BitVec<u64, Lsb0>, bytes: &[u8]) {
+    bitvec.extend((bytes.len() as u32).view_bits::<Lsb0>());
+    bitvec.extend_from_bitslice(bytes.view_bits::<Lsb0>());
+}
+pub fn push_varint(bitvec: &mut BitVec<u64, Lsb0>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bitvec.extend(byte.view_bits::<Lsb0>());
+            return;
+        }
+        bitvec.extend((byte | 0x80).view_bits::<Lsb0>());
+    }
+}
+// overlong encodings are rejected so every value has exactly one encoding
+pub fn read_varint(data: &BitSlice<u64>, pointer: &mut usize) -> Option<u64> {
+    let mut value: u64 = 0;
+    for idx in 0..MAX_VARINT_BYTES {
+        let byte = data.get(*pointer..*pointer + BYTE)?.load_le::<u8>();
+        *pointer += BYTE;
+        let bits = (byte & 0x7f) as u64;
+        // the last byte only has room for the top bit of a u64
+        if idx == MAX_VARINT_BYTES - 1 && bits > 1 {
+            return None;
+        }
+        value |= bits << (idx * 7);
+        if byte & 0x80 == 0 {
+            if idx > 0 && bits == 0 {
+                return None;
+            }
+            return Some(value);
+        }
+    }
+    None
+}
+fn read_f32(data: &BitSlice<u64>, pointer: &mut usize) -> Option<f32> {
+    let value = f32::from_bits(data.get(*pointer..*pointer + BYTES4)?.load_le::<u32>());
+    *pointer += BYTES4;
+    Some(value)
+}
+fn read_f32s<const N: usize>(data: &BitSlice<u64>, pointer: &mut usize) -> Option<[f32; N]> {
+    let mut floats = [0.0; N];
+    for float in floats.iter_mut() {
+        *float = read_f32(data, pointer)?;
+    }
+    Some(floats)
+}
+// reads a 32 bit length and checks that many items of item_bits could fit in what is left
+fn read_length(data: &BitSlice<u64>, pointer: &mut usize, item_bits: usize) -> Option<usize> {
+    let length = data.get(*pointer..*pointer + BYTES4)?.load_le::<u32>() as usize;
+    *pointer += BYTES4;
+    if length.checked_mul(item_bits)? > data.len().saturating_sub(*pointer) {
+        return None;
+    }
+    Some(length)
+}
+// reads the length and bytes of a string or byte array
+fn read_bytes(data: &BitSlice<u64>, pointer: &mut usize) -> Option<Vec<u8>> {
+    let length = read_length(data, pointer, BYTE)?;
+    let bits = &data[*pointer..*pointer + length * BYTE];
+    let mut bytes: Vec<u8> = Vec::with_capacity(length);
+    // loads a whole word at a time, much faster than going byte by byte for large arrays
+    let words = bits.chunks_exact(BYTES8);
+    let remainder = words.remainder();
+    for word in words {
+        bytes.extend(word.load_le::<u64>().to_le_bytes());
+    }
+    bytes.extend(remainder.chunks_exact(BYTE).map(|x| x.load_le::<u8>()));
+    *pointer += length * BYTE;
+    Some(bytes)
+}
+fn read_tagged(data: &BitSlice<u64>, pointer: &mut usize, depth: usize) -> Option<NetValue> {
+    let tag = data.get(*pointer..*pointer + BYTE)?.load_le::<u8>() as i8;
+    *pointer += BYTE;
+    let object_type = NetworkedValueTypes::try_from(tag as i64).ok()?;
+    read_value(data, pointer, &object_type, depth)
+}
+// the pointer is only moved if the whole value could be read
+pub fn decode_value(
     data: &BitSlice<u64>,
     pointer: &mut usize,
     object_type: &NetworkedValueTypes,
-) -> Option<Variant> {
-    match object_type {
-        NetworkedValueTypes::Nil => Some(Variant::nil()),
+) -> Option<NetValue> {
+    let mut end = *pointer;
+    let value = read_value(data, &mut end, object_type, 0)?;
+    *pointer = end;
+    Some(value)
+}
+fn read_value(
+    data: &BitSlice<u64>,
+    end: &mut usize,
+    object_type: &NetworkedValueTypes,
+    depth: usize,
+) -> Option<NetValue> {
+    let value = match object_type {
+        NetworkedValueTypes::Nil => NetValue::Nil,
         NetworkedValueTypes::Bool => {
-            if *pointer + 1 > data.len() {
-                return None;
-            }
-            let data = Some(data[*pointer].to_variant());
-            *pointer += 1;
-            data
+            let value = *data.get(*end)?;
+            *end += 1;
+            NetValue::Bool(value)
         }
         NetworkedValueTypes::Unsigned8 => {
-            if *pointer + BYTE > data.len() {
-                return None;
-            }
-            let data = Some(data[*pointer..*pointer + BYTE].load_le::<u8>().to_variant());
-            *pointer += BYTE;
-            data
+            let value = data.get(*end..*end + BYTE)?.load_le::<u8>();
+            *end += BYTE;
+            NetValue::Unsigned8(value)
         }
         NetworkedValueTypes::Unsigned16 => {
-            if *pointer + BYTES2 > data.len() {
-                return None;
-            }
-            let data = Some(
-                data[*pointer..*pointer + BYTES2]
-                    .load_le::<u16>()
-                    .to_variant(),
-            );
-            *pointer += BYTES2;
-            data
+            let value = data.get(*end..*end + BYTES2)?.load_le::<u16>();
+            *end += BYTES2;
+            NetValue::Unsigned16(value)
         }
         NetworkedValueTypes::Signed64 => {
-            if *pointer + BYTES8 > data.len() {
-                return None;
-            }
-            let data = Some(
-                data[*pointer..*pointer + BYTES8]
-                    .load_le::<i64>()
-                    .to_variant(),
-            );
-            *pointer += BYTES8;
-            data
+            let value = data.get(*end..*end + BYTES8)?.load_le::<i64>();
+            *end += BYTES8;
+            NetValue::Signed64(value)
         }
-        NetworkedValueTypes::Float32 => {
-            if *pointer + BYTES4 > data.len() {
-                return None;
+        NetworkedValueTypes::Float32 => NetValue::Float32(read_f32(data, end)?),
+        NetworkedValueTypes::Vector3 => NetValue::Vector3(read_f32s(data, end)?),
+        NetworkedValueTypes::String => {
+            // invalid text fails the whole decode rather than silently becoming an empty string
+            NetValue::String(String::from_utf8(read_bytes(data, end)?).ok()?)
+        }
+        NetworkedValueTypes::ByteArray => NetValue::ByteArray(read_bytes(data, end)?),
+        NetworkedValueTypes::Signed32 => {
+            let value = data.get(*end..*end + BYTES4)?.load_le::<i32>();
+            *end += BYTES4;
+            NetValue::Signed32(value)
+        }
+        NetworkedValueTypes::Float64 => {
+            let value = f64::from_bits(data.get(*end..*end + BYTES8)?.load_le::<u64>());
+            *end += BYTES8;
+            NetValue::Float64(value)
+        }
+        NetworkedValueTypes::Vector2 => NetValue::Vector2(read_f32s(data, end)?),
+        NetworkedValueTypes::Quaternion => NetValue::Quaternion(read_f32s(data, end)?),
+        NetworkedValueTypes::Basis => NetValue::Basis(read_f32s(data, end)?),
+        NetworkedValueTypes::Transform3D => NetValue::Transform3D(read_f32s(data, end)?),
+        NetworkedValueTypes::Color => NetValue::Color(read_f32s(data, end)?),
+        NetworkedValueTypes::StringName => {
+            NetValue::StringName(String::from_utf8(read_bytes(data, end)?).ok()?)
+        }
+        NetworkedValueTypes::Float32Array => {
+            let length = read_length(data, end, BYTES4)?;
+            let mut floats = Vec::with_capacity(length);
+            for _ in 0..length {
+                floats.push(read_f32(data, end)?);
             }
-            let data = Some(
-                f32::from_ne_bytes(
-                    data[*pointer..*pointer + BYTES4]
-                        .load_le::<u32>()
-                        .to_ne_bytes(),
-                )
-                .to_variant(),
-            );
-            *pointer += BYTES4;
-            data
+            NetValue::Float32Array(floats)
         }
-        NetworkedValueTypes::Vector3 => {
-            if *pointer + (BYTES4 * 3) > data.len() {
+        NetworkedValueTypes::Array => {
+            if depth >= MAX_NESTING {
                 return None;
             }
-            let x = f32::from_ne_bytes(
-                data[*pointer..*pointer + BYTES4]
-                    .load_le::<u32>()
-                    .to_ne_bytes(),
-            );
-            *pointer += BYTES4;
-            let y = f32::from_ne_bytes(
-                data[*pointer..*pointer + BYTES4]
-                    .load_le::<u32>()
-                    .to_ne_bytes(),
-            );
-            *pointer += BYTES4;
-            let z = f32::from_ne_bytes(
-                data[*pointer..*pointer + BYTES4]
-                    .load_le::<u32>()
-                    .to_ne_bytes(),
-            );
-            *pointer += BYTES4;
-            Some(Vector3::new(x, y, z).to_variant())
+            // every element has at least its type tag
+            let length = read_length(data, end, BYTE)?;
+            let mut values = Vec::with_capacity(length);
+            for _ in 0..length {
+                values.push(read_tagged(data, end, depth + 1)?);
+            }
+            NetValue::Array(values)
         }
-        NetworkedValueTypes::String => {
-            if *pointer + BYTES4 > data.len() {
+        NetworkedValueTypes::Dictionary => {
+            if depth >= MAX_NESTING {
                 return None;
             }
-            let length = data[*pointer..*pointer + BYTES4].load_le::<u32>() as usize;
-            *pointer += BYTES4;
-            if *pointer + (length * BYTE) <= data.len() {
-                let result = Some(
-                    str::from_utf8(
-                        &data[*pointer..*pointer + (length * BYTE)]
-                            .chunks_exact(BYTE)
-                            .map(|x| x.load_le::<u8>())
-                            .collect::<Vec<u8>>(),
-                    )
-                    .unwrap_or("")
-                    .to_variant(),
-                );
-                *pointer += length * BYTE;
-                return result;
+            let length = read_length(data, end, BYTE * 2)?;
+            let mut entries = Vec::with_capacity(length);
+            for _ in 0..length {
+                let key = read_tagged(data, end, depth + 1)?;
+                entries.push((key, read_tagged(data, end, depth + 1)?));
             }
-            return None;
+            NetValue::Dictionary(entries)
         }
-
-        NetworkedValueTypes::ByteArray => {
-            if *pointer + BYTES4 > data.len() {
+        NetworkedValueTypes::VarUInt => NetValue::VarUInt(read_varint(data, end)?),
+        NetworkedValueTypes::VarInt => {
+            let value = read_varint(data, end)?;
+            NetValue::VarInt((value >> 1) as i64 ^ -((value & 1) as i64))
+        }
+        NetworkedValueTypes::Bounded(range) => {
+            let bits = range.bits();
+            let offset = match bits {
+                0 => 0,
+                _ => data.get(*end..*end + bits)?.load_le::<u32>(),
+            };
+            *end += bits;
+            let value = range.min() + offset as i64;
+            // ranges that arent a power of two leave some offsets unused
+            if !range.contains(value) {
                 return None;
             }
-            let length = data[*pointer..*pointer + BYTES4].load_le::<u32>() as usize;
-            *pointer += BYTES4;
-            if *pointer + (length * BYTE) <= data.len() {
-                let result = Some(
-                    data[*pointer..*pointer + (length * BYTE)]
-                        .chunks_exact(BYTE)
-                        .map(|x| x.load_le::<u8>())
-                        .collect::<Vec<u8>>()
-                        .to_variant(),
-                );
-                *pointer += length * BYTE;
-                return result;
-            }
-            return None;
+            NetValue::Bounded(value, *range)
         }
-    }
+    };
+    Some(value)
+}
+pub fn decode_with_known_type(
+    data: &BitSlice<u64>,
+    pointer: &mut usize,
+    object_type: &NetworkedValueTypes,
+) -> Option<Variant> {
+    decode_value(data, pointer, object_type).map(|x| x.to_variant())
 }
 pub fn encode_with_known_type(
     object: &Variant,
     object_type: &NetworkedValueTypes,
 ) -> BitVec<u64, Lsb0> {
-    match object_type {
-        NetworkedValueTypes::Nil => BitVec::new(),
-        NetworkedValueTypes::Bool => {
-            let value: bool = bool::from_variant(object);
-            let mut bitvec = BitVec::with_capacity(1);
-            bitvec.push(value);
-            bitvec
+    NetValue::from_variant(object, object_type).encode()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const TYPES: [NetworkedValueTypes; 22] = NetworkedValueTypes::ALL;
+    fn round_trip(value: &NetValue) -> NetValue {
+        let data = value.encode();
+        let mut pointer = 0;
+        let decoded = decode_value(&data, &mut pointer, &value.value_type())
+            .expect("encoded value failed to decode");
+        assert_eq!(pointer, data.len(), "decode left bits unread");
+        decoded
+    }
+    // floats are compared by bits so nan values can be checked too
+    fn same_bits(a: &NetValue, b: &NetValue) -> bool {
+        a.encode() == b.encode() && a.value_type() == b.value_type()
+    }
+    fn bytes(data: &BitSlice<u64>) -> Vec<u8> {
+        data.chunks(BYTE).map(|x| x.load_le::<u8>()).collect()
+    }
+
+    fn any_f32s<const N: usize>() -> impl Strategy<Value = [f32; N]> {
+        proptest::array::uniform::<_, N>(any::<u32>()).prop_map(|x| x.map(f32::from_bits))
+    }
+    fn any_scalar() -> impl Strategy<Value = NetValue> {
+        prop_oneof![
+            Just(NetValue::Nil),
+            any::<bool>().prop_map(NetValue::Bool),
+            any::<u8>().prop_map(NetValue::Unsigned8),
+            any::<u16>().prop_map(NetValue::Unsigned16),
+            any::<i64>().prop_map(NetValue::Signed64),
+            any::<u32>().prop_map(|x| NetValue::Float32(f32::from_bits(x))),
+            any_f32s::<3>().prop_map(NetValue::Vector3),
+            ".*".prop_map(NetValue::String),
+            proptest::collection::vec(any::<u8>(), 0..64).prop_map(NetValue::ByteArray),
+            any::<i32>().prop_map(NetValue::Signed32),
+            any::<u64>().prop_map(|x| NetValue::Float64(f64::from_bits(x))),
+            any_f32s::<2>().prop_map(NetValue::Vector2),
+            any_f32s::<4>().prop_map(NetValue::Quaternion),
+            any_f32s::<9>().prop_map(NetValue::Basis),
+            any_f32s::<12>().prop_map(NetValue::Transform3D),
+            any_f32s::<4>().prop_map(NetValue::Color),
+            ".*".prop_map(NetValue::StringName),
+            proptest::collection::vec(any::<u32>().prop_map(f32::from_bits), 0..16)
+                .prop_map(NetValue::Float32Array),
+            any::<u64>().prop_map(NetValue::VarUInt),
+            any::<i64>().prop_map(NetValue::VarInt),
+        ]
+    }
+    fn any_value() -> impl Strategy<Value = NetValue> {
+        any_scalar().prop_recursive(4, 32, 4, |inner| {
+            prop_oneof![
+                proptest::collection::vec(inner.clone(), 0..4).prop_map(NetValue::Array),
+                proptest::collection::vec((inner.clone(), inner), 0..4)
+                    .prop_map(NetValue::Dictionary),
+            ]
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn values_round_trip(value in any_value()) {
+            prop_assert!(same_bits(&round_trip(&value), &value));
         }
-        NetworkedValueTypes::Unsigned8 => {
-            let value = u8::from_variant(object);
-            let mut bitvec = BitVec::with_capacity(BYTE);
-            bitvec.extend(value.view_bits::<Lsb0>());
-            bitvec
+
+        #[test]
+        fn values_round_trip_after_other_data(
+            offset in 0usize..64,
+            first in any_value(),
+            second in any_value(),
+        ) {
+            let mut data: BitVec<u64, Lsb0> = BitVec::repeat(false, offset);
+            data.extend(first.encode());
+            data.extend(second.encode());
+            let mut pointer = offset;
+            let decoded_first = decode_value(&data, &mut pointer, &first.value_type()).unwrap();
+            let decoded_second = decode_value(&data, &mut pointer, &second.value_type()).unwrap();
+            prop_assert!(same_bits(&decoded_first, &first));
+            prop_assert!(same_bits(&decoded_second, &second));
+            prop_assert_eq!(pointer, data.len());
         }
-        NetworkedValueTypes::Unsigned16 => {
-            let value = u16::from_variant(object);
-            let mut bitvec = BitVec::with_capacity(BYTES2);
-            bitvec.extend(value.view_bits::<Lsb0>());
-            bitvec
+
+        #[test]
+        fn truncated_values_fail_without_moving_the_pointer(value in any_value()) {
+            let data = value.encode();
+            for length in 0..data.len() {
+                let mut pointer = 0;
+                prop_assert_eq!(decode_value(&data[..length], &mut pointer, &value.value_type()), None);
+                prop_assert_eq!(pointer, 0);
+            }
         }
-        NetworkedValueTypes::Signed64 => {
-            let value = i64::from_variant(object) as u64;
-            let mut bitvec = BitVec::with_capacity(BYTES8);
-            bitvec.extend(value.view_bits::<Lsb0>());
-            bitvec
-        }
-        NetworkedValueTypes::Float32 => {
-            let value: u32 = u32::from_ne_bytes(f32::from_variant(object).to_ne_bytes());
-            let mut bitvec = BitVec::with_capacity(BYTES4);
-            bitvec.extend(value.view_bits::<Lsb0>());
-            bitvec
-        }
-        NetworkedValueTypes::Vector3 => {
-            let vector = Vector3::from_variant(object);
-            let mut bitvec = BitVec::with_capacity(BYTES4 * 3);
-            let x = u32::from_ne_bytes(vector.x.to_ne_bytes());
-            bitvec.extend(x.view_bits::<Lsb0>());
-            let y = u32::from_ne_bytes(vector.y.to_ne_bytes());
-            bitvec.extend(y.view_bits::<Lsb0>());
-            let z = u32::from_ne_bytes(vector.z.to_ne_bytes());
-            bitvec.extend(z.view_bits::<Lsb0>());
-            bitvec
+
+        #[test]
+        fn bounded_values_round_trip(min in -1000i64..1000, span in 0i64..100_000, offset in 0.0f64..=1.0) {
+            let range = BoundedRange::new(min, min + span).unwrap();
+            let value = NetValue::Bounded(min + (span as f64 * offset) as i64, range);
+            prop_assert_eq!(value.encode().len(), range.bits());
+            prop_assert_eq!(round_trip(&value), value);
+            prop_assert_eq!(BoundedRange::from_id(range.id()), Some(range));
         }
-        NetworkedValueTypes::String => {
-            let string = String::from_variant(object);
-            let length = string.len() as u32;
-            let mut bitvec = BitVec::with_capacity(BYTES4 + (BYTE * length as usize));
-            bitvec.extend(length.view_bits::<Lsb0>());
-            for char in string.bytes() {
-                bitvec.extend(char.view_bits::<Lsb0>());
-            }
-            bitvec
-        }
-        NetworkedValueTypes::ByteArray => {
-            let bytes: Array<i64> = Array::from_variant(object);
-            let length = bytes.len() as u32;
-            let mut bitvec = BitVec::with_capacity(BYTES4 + (BYTE * length as usize));
-            bitvec.extend(length.view_bits::<Lsb0>());
-            for byte in bytes.iter_shared().map(|x| x.try_into().unwrap_or(0u8)) {
-                bitvec.extend(byte.view_bits::<Lsb0>());
-            }
-            bitvec
+
+        #[test]
+        fn random_input_never_panics(
+            data in proptest::collection::vec(any::<u8>(), 0..64),
+            type_index in 0..TYPES.len(),
+        ) {
+            let data: BitVec<u64, Lsb0> = BitVec::from_vec(data.chunks(8).map(|x| {
+                let mut word = [0u8; 8];
+                word[..x.len()].copy_from_slice(x);
+                u64::from_le_bytes(word)
+            }).collect());
+            let mut pointer = 0;
+            if let Some(value) = decode_value(&data, &mut pointer, &TYPES[type_index]) {
+                prop_assert!(pointer <= data.len());
+                prop_assert_eq!(value.value_type(), TYPES[type_index]);
+            } else {
+                prop_assert_eq!(pointer, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn integer_boundaries_round_trip() {
+        for value in [
+            NetValue::Bool(false),
+            NetValue::Bool(true),
+            NetValue::Unsigned8(0),
+            NetValue::Unsigned8(u8::MAX),
+            NetValue::Unsigned16(0),
+            NetValue::Unsigned16(u16::MAX),
+            NetValue::Signed64(0),
+            NetValue::Signed64(-1),
+            NetValue::Signed64(i64::MIN),
+            NetValue::Signed64(i64::MAX),
+        ] {
+            assert_eq!(round_trip(&value), value);
+        }
+    }
+
+    #[test]
+    fn special_floats_round_trip() {
+        for float in [
+            0.0,
+            -0.0,
+            f32::MIN,
+            f32::MAX,
+            f32::MIN_POSITIVE,
+            f32::EPSILON,
+            f32::INFINITY,
+            f32::NEG_INFINITY,
+            f32::NAN,
+            -f32::NAN,
+            // subnormal
+            f32::from_bits(1),
+        ] {
+            let value = NetValue::Float32(float);
+            assert!(same_bits(&round_trip(&value), &value), "{:?}", float);
+            let value = NetValue::Vector3([float, -float, 1.0]);
+            assert!(same_bits(&round_trip(&value), &value), "{:?}", float);
+        }
+    }
+
+    #[test]
+    fn encoding_is_little_endian() {
+        assert_eq!(bytes(&NetValue::Unsigned16(0x0102).encode()), [0x02, 0x01]);
+        assert_eq!(
+            bytes(&NetValue::Signed64(-2).encode()),
+            [0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(
+            bytes(&NetValue::Float32(1.0).encode()),
+            [0x00, 0x00, 0x80, 0x3f]
+        );
+        assert_eq!(
+            bytes(&NetValue::String("hi".to_string()).encode()),
+            [2, 0, 0, 0, b'h', b'i']
+        );
+        assert_eq!(
+            bytes(&NetValue::ByteArray(vec![7, 8]).encode()),
+            [2, 0, 0, 0, 7, 8]
+        );
+        assert!(NetValue::Nil.encode().is_empty());
+        assert_eq!(NetValue::Bool(true).encode().len(), 1);
+    }
+
+    #[test]
+    fn strings_and_byte_arrays_keep_their_contents() {
+        for string in ["", "a", "héllo wörld", "🦋", "\0null\0"] {
+            let value = NetValue::String(string.to_string());
+            assert_eq!(round_trip(&value), value);
+        }
+        let value = NetValue::ByteArray((0..=u8::MAX).collect());
+        assert_eq!(round_trip(&value), value);
+        let value = NetValue::ByteArray(Vec::new());
+        assert_eq!(round_trip(&value), value);
+    }
+
+    #[test]
+    fn invalid_utf8_is_rejected() {
+        let data = NetValue::ByteArray(vec![0xff, 0xfe, b'a']).encode();
+        let mut pointer = 0;
+        assert_eq!(
+            decode_value(&data, &mut pointer, &NetworkedValueTypes::String),
+            None
+        );
+        assert_eq!(pointer, 0);
+    }
+
+    #[test]
+    fn oversized_lengths_fail() {
+        for value_type in [NetworkedValueTypes::String, NetworkedValueTypes::ByteArray] {
+            let mut data: BitVec<u64, Lsb0> = BitVec::new();
+            data.extend(u32::MAX.view_bits::<Lsb0>());
+            data.extend(0xabu8.view_bits::<Lsb0>());
+            let mut pointer = 0;
+            assert_eq!(decode_value(&data, &mut pointer, &value_type), None);
+            assert_eq!(pointer, 0);
+        }
+    }
+
+    #[test]
+    fn type_ids_are_stable() {
+        let ids: Vec<i64> = NetworkedValueTypes::ALL.iter().map(|x| x.id()).collect();
+        assert_eq!(
+            ids,
+            [
+                -2, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20
+            ]
+        );
+        for value_type in NetworkedValueTypes::ALL {
+            assert_eq!(
+                NetworkedValueTypes::try_from(value_type.id()),
+                Ok(value_type)
+            );
+        }
+        assert!(NetworkedValueTypes::try_from(-1).is_err());
+        assert!(NetworkedValueTypes::try_from(21).is_err());
+        assert!(NetworkedValueTypes::try_from(i64::MIN).is_err());
+    }
+
+    #[test]
+    fn containers_tag_their_elements() {
+        let value = NetValue::Array(vec![NetValue::Unsigned8(5), NetValue::Nil]);
+        assert_eq!(bytes(&value.encode()), [2, 0, 0, 0, 1, 5, 0xfe]);
+        let value = NetValue::Dictionary(vec![(
+            NetValue::String("a".to_string()),
+            NetValue::Signed32(-1),
+        )]);
+        assert_eq!(
+            bytes(&value.encode()),
+            [1, 0, 0, 0, 6, 1, 0, 0, 0, b'a', 8, 0xff, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(round_trip(&value), value);
+    }
+
+    #[test]
+    fn unknown_element_tags_are_rejected() {
+        for tag in [0xffu8, 21, 0x80] {
+            let mut data: BitVec<u64, Lsb0> = BitVec::new();
+            data.extend(1u32.view_bits::<Lsb0>());
+            data.extend(tag.view_bits::<Lsb0>());
+            data.extend(0u64.view_bits::<Lsb0>());
+            let mut pointer = 0;
+            assert_eq!(
+                decode_value(&data, &mut pointer, &NetworkedValueTypes::Array),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let mut value = NetValue::Array(Vec::new());
+        for _ in 0..MAX_NESTING - 1 {
+            value = NetValue::Array(vec![value]);
+        }
+        assert_eq!(round_trip(&value), value);
+        let value = NetValue::Array(vec![value]);
+        let data = value.encode();
+        let mut pointer = 0;
+        assert_eq!(
+            decode_value(&data, &mut pointer, &NetworkedValueTypes::Array),
+            None
+        );
+    }
+
+    #[test]
+    fn wide_numbers_round_trip() {
+        for value in [
+            NetValue::Signed32(i32::MIN),
+            NetValue::Signed32(i32::MAX),
+            NetValue::Signed32(-1),
+            NetValue::Float64(f64::MAX),
+            NetValue::Float64(f64::MIN_POSITIVE),
+            NetValue::Float64(f64::NEG_INFINITY),
+            NetValue::Float64(f64::NAN),
+            NetValue::Float32Array(vec![f32::NAN, f32::INFINITY, -0.0]),
+        ] {
+            assert!(same_bits(&round_trip(&value), &value), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn varints_use_7_bits_per_byte() {
+        assert_eq!(bytes(&NetValue::VarUInt(0).encode()), [0]);
+        assert_eq!(bytes(&NetValue::VarUInt(127).encode()), [0x7f]);
+        assert_eq!(bytes(&NetValue::VarUInt(128).encode()), [0x80, 0x01]);
+        assert_eq!(bytes(&NetValue::VarUInt(300).encode()), [0xac, 0x02]);
+        assert_eq!(NetValue::VarUInt(u64::MAX).encode().len(), BYTE * 10);
+        assert_eq!(bytes(&NetValue::VarInt(0).encode()), [0]);
+        assert_eq!(bytes(&NetValue::VarInt(-1).encode()), [1]);
+        assert_eq!(bytes(&NetValue::VarInt(1).encode()), [2]);
+        assert_eq!(bytes(&NetValue::VarInt(-64).encode()), [0x7f]);
+        for value in [i64::MIN, i64::MAX, -1, 0, 1] {
+            let value = NetValue::VarInt(value);
+            assert_eq!(round_trip(&value), value);
+        }
+        let value = NetValue::VarUInt(u64::MAX);
+        assert_eq!(round_trip(&value), value);
+    }
+
+    #[test]
+    fn overlong_and_overflowing_varints_are_rejected() {
+        let decode = |input: &[u8]| {
+            let mut data: BitVec<u64, Lsb0> = BitVec::new();
+            data.extend_from_bitslice(input.view_bits::<Lsb0>());
+            let mut pointer = 0;
+            decode_value(&data, &mut pointer, &NetworkedValueTypes::VarUInt)
+        };
+        assert_eq!(decode(&[0x80, 0x00]), None);
+        assert_eq!(
+            decode(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02]),
+            None
+        );
+        assert_eq!(decode(&[0x80; 11]), None);
+        assert_eq!(decode(&[0x80]), None);
+        assert_eq!(decode(&[0x81, 0x01]), Some(NetValue::VarUInt(129)));
+    }
+
+    #[test]
+    fn bounded_ranges_use_the_fewest_bits() {
+        let bits = |min, max| BoundedRange::new(min, max).unwrap().bits();
+        // an enum with 5 states
+        assert_eq!(bits(0, 4), 3);
+        // health from 0 to 100
+        assert_eq!(bits(0, 100), 7);
+        assert_eq!(bits(7, 7), 0);
+        assert_eq!(bits(-1, 0), 1);
+        assert_eq!(bits(0, 255), 8);
+        assert_eq!(bits(0, 256), 9);
+        assert_eq!(bits(-5, (1 << MAX_BOUNDED_BITS) - 6), 30);
+        assert!(BoundedRange::new(0, 1 << MAX_BOUNDED_BITS).is_err());
+        assert!(BoundedRange::new(5, 4).is_err());
+        assert!(BoundedRange::new(i64::MIN, 0).is_err());
+        assert!(BoundedRange::new(-1, i64::MAX).is_err());
+    }
+
+    #[test]
+    fn bounded_types_have_stable_ids() {
+        let range = BoundedRange::new(-3, 100).unwrap();
+        let id = NetworkedValueTypes::Bounded(range).id();
+        assert_eq!(id, BOUNDED_TYPE_FLAG | 103 << 32 | (-3i32 as u32 as i64));
+        assert_eq!(
+            NetworkedValueTypes::try_from(id),
+            Ok(NetworkedValueTypes::Bounded(range))
+        );
+        let value = NetValue::Bounded(-3, range);
+        assert_eq!(round_trip(&value), value);
+        assert!(value.encode().not_any());
+    }
+
+    #[test]
+    fn unused_bounded_offsets_are_rejected() {
+        let range = BoundedRange::new(0, 4).unwrap();
+        let mut data: BitVec<u64, Lsb0> = BitVec::new();
+        data.extend(&7u8.view_bits::<Lsb0>()[..3]);
+        let mut pointer = 0;
+        assert_eq!(
+            decode_value(&data, &mut pointer, &NetworkedValueTypes::Bounded(range)),
+            None
+        );
+    }
+
+    #[test]
+    fn bounded_values_in_containers_are_sent_as_ints() {
+        let range = BoundedRange::new(0, 10).unwrap();
+        let value = NetValue::Array(vec![NetValue::Bounded(4, range)]);
+        assert_eq!(
+            round_trip(&value),
+            NetValue::Array(vec![NetValue::Signed64(4)])
+        );
+    }
+
+    #[test]
+    fn large_byte_arrays_keep_their_contents_at_any_offset() {
+        let bytes: Vec<u8> = (0..1000u32).map(|x| (x * 7) as u8).collect();
+        for offset in [0, 1, 7, 8, 63, 64] {
+            let mut data: BitVec<u64, Lsb0> = BitVec::repeat(true, offset);
+            data.extend(NetValue::ByteArray(bytes.clone()).encode());
+            let mut pointer = offset;
+            assert_eq!(
+                decode_value(&data, &mut pointer, &NetworkedValueTypes::ByteArray),
+                Some(NetValue::ByteArray(bytes.clone()))
+            );
         }
     }
 }
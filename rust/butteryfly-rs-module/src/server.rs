@@ -1,9 +1,19 @@
 // functionallity for the NetNodeManager server
+use crate::capture::{Direction, FileCapture};
+use crate::logging::{log_debug, log_error, log_info, log_warn};
 use crate::messages::*;
-use crate::net_nodes::NetworkedNode;
-use crate::serializer::*;
+#[cfg(feature = "metrics")]
+use crate::metrics::{ServerGauges, ServerMetrics};
+use crate::moderation::{self, BanList, ModerationAction, ModerationEvent};
+use crate::net_nodes::{self, NetworkedNode, NodeIndex, SnapshotCache, UpdateError};
+use crate::object_ids::{ObjectId, ObjectIdAllocator};
+use crate::players::PlayerInfo;
+use crate::rate_limit::{LimitPolicy, MessageLimits, PacketLimits, TokenBucket};
+use crate::registry::{self, MessageRegistry, RegistryError};
+use crate::stats::PeerStats;
 use crate::voice;
 use crate::voice::FRAME_LENGTH;
+use crate::wire::{self, CHANNEL_SPLIT, DecodeError, DecodeErrors, RawPacket, SplitAssembler};
 use bitvec::prelude::*;
 use build_time::build_time_utc;
 use std::collections::{HashSet, VecDeque};
@@ -18,21 +28,72 @@ const BYTE: usize = 8;
 const BYTES2: usize = 16;
 const BYTES8: usize = 64;
 const PACKET_HEADER_SIZE: usize = BYTES2 + BYTES8;
-const PACKET_HEADER_SIZE_ACK: usize = BYTES2;
 const CHANNEL1_HEADER_SIZE: usize = BYTES8;
 const HIT_RATE_HISTORY_LENGTH: usize = 128;
 const CHANNEL_CLIENT_ID: u16 = u16::MAX - 1;
+const CHANNEL_CONTROL: u16 = 0;
+const MAX_SPLIT_CHUNKS: u64 = 64;
+// malformed_packet_limit applies over this many seconds
+const MALFORMED_PACKET_WINDOW_SECONDS: f64 = 10.0;
+// channels clients are allowed to send on, other than acks
+const CLIENT_CHANNELS: [u16; 7] = [
+    CHANNEL_CONTROL,
+    1,
+    3,
+    4,
+    5,
+    CHANNEL_MESSAGES_UNORDERED,
+    CHANNEL_MESSAGES_UNRELIABLE,
+];
+// control packet kinds, the first byte of a channel 0 payload
+const CONTROL_LEAVE: u8 = 0;
+const CONTROL_RESUME: u8 = 1;
+const CONTROL_RESUME_ACCEPTED: u8 = 2;
+const CONTROL_RESUME_REJECTED: u8 = 3;
+const CONTROL_SYNC_FINISHED: u8 = 4;
+const CONTROL_KICKED: u8 = 5;
+// connect tokens stop working after this many seconds, their pending profiles are dropped with them
+const TOKEN_EXPIREY_TIME: i32 = 600;
+// stands in for the metrics when the module is built without them so the server's fields stay the same
+#[cfg(not(feature = "metrics"))]
+type ServerMetrics = ();
 #[derive(GodotClass)]
 #[class(init, base=Node)]
 pub struct NetNodeServer {
     #[var]
     pub id: u16,
-    next_id: u16,
+    // physics ticks since the server started, stamped on messages so handlers know when they were received
+    pub tick: u64,
+    object_ids: ObjectIdAllocator,
     pub networked_nodes: Vec<Gd<NetworkedNode>>,
+    node_index: NodeIndex,
+    snapshots: SnapshotCache,
     voice_manager: voice::VoiceStreamManager,
     server_networker: ServerNetworker,
-    message_buffer: VecDeque<BitVec<u64, Lsb0>>,
+    // every message sent so far along with who it goes to, new clients are caught up from the start
+    // unreliable messages are not kept here, they go straight to the clients connected at the time
+    message_buffer: VecDeque<(BitVec<u64, Lsb0>, MessageTarget, DeliveryMode)>,
     message_handlers: HashMap<u16, Gd<MessageHandler>>,
+    // types given to named message handlers, every new name is sent to clients as a registry message
+    pub message_registry: MessageRegistry,
+    pub players: HashMap<u16, PlayerInfo>,
+    // profiles for issued tokens that have not connected yet, keyed by the token's client id
+    pending_profiles: HashMap<u64, (PlayerInfo, Instant)>,
+    // how long a dropped player's session is kept so they can resume it
+    #[var]
+    #[init(val = 30.0)]
+    pub reconnect_window_seconds: f64,
+    pub bans: BanList,
+    // used for message types without a handler or where the handler leaves a limit at its default
+    pub message_limits: MessageLimits,
+    pub packet_limits: PacketLimits,
+    // malformed packets a client can send within 10 seconds before it is kicked, 0 to never kick
+    pub malformed_packet_limit: u32,
+    // signals for changes made through NetNodeManager, they are emitted on the next tick as the manager is still bound when they happen
+    queued_signals: Vec<ServerSignal>,
+    // set once start_metrics succeeds
+    #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+    metrics: Option<ServerMetrics>,
     base: Base<Node>,
 }
 
@@ -42,15 +103,30 @@ pub impl NetNodeServer {
     pub fn player_joined(player: u16);
     #[signal]
     pub fn player_left(player: u16);
+    #[signal]
+    pub fn player_reconnecting(player: u16);
+    #[signal]
+    pub fn player_resumed(player: u16);
+    #[signal]
+    pub fn player_info_changed(player: u16);
+    #[signal]
+    pub fn player_info_removed(player: u16);
+    #[signal]
+    pub fn moderation_event(player: u16, action: GString, reason: GString);
+    // a client went over one of the packet_limits, emitted whatever the policy is
+    #[signal]
+    pub fn packet_limit_exceeded(player: u16, violation: GString);
     pub fn register_node(&mut self, new_node_ref: Gd<NetworkedNode>, new_node: &mut NetworkedNode) {
         self.queue_message(MessageHandler::create_id_sync_message(
             new_node_ref.clone().upcast(),
             new_node.objectid,
             Some(new_node.owner_id),
         ));
+        self.node_index.insert(new_node.objectid, &new_node_ref);
         self.networked_nodes.push(new_node_ref);
     }
-    pub fn unregister_node(&mut self, removed_node_ref: Gd<NetworkedNode>) {
+    pub fn unregister_node(&mut self, removed_node_ref: Gd<NetworkedNode>, objectid: ObjectId) {
+        self.object_ids.release(objectid, Instant::now());
         for client in self.server_networker.clients.values_mut() {
             if let Some(idx) = client
                 .priorities
@@ -60,6 +136,7 @@ pub impl NetNodeServer {
                 client.priorities.remove(idx);
             }
         }
+        self.node_index.remove(&removed_node_ref);
         if let Some(idx) = self
             .networked_nodes
             .iter()
@@ -70,26 +147,173 @@ pub impl NetNodeServer {
     }
     pub fn unregister_all(&mut self) {
         // todo: update this
-        self.next_id = 0;
+        self.object_ids.release_all(Instant::now());
         self.networked_nodes.clear();
+        self.node_index.clear();
+    }
+    // see NetNodeManager::get_client_stats
+    pub fn client_stats(&self, player: u16) -> Option<Dictionary> {
+        self.server_networker
+            .session(player)
+            .map(|x| x.network_stats())
     }
-    pub fn get_next_object_id(&mut self) -> u16 {
-        self.next_id += 1;
-        self.next_id
+    // see NetNodeManager::get_debug_dump
+    pub fn debug_dump(&self) -> Dictionary {
+        let mut dump = Dictionary::new();
+        dump.set("role", "server");
+        dump.set("id", self.id);
+        dump.set("tick", self.tick);
+        dump.set("nodes", net_nodes::debug_dump_nodes(&self.networked_nodes));
+        dump.set(
+            "clients",
+            self.server_networker
+                .clients
+                .values()
+                .map(|x| x.debug_dump())
+                .collect::<Array<Dictionary>>(),
+        );
+        dump
+    }
+    // serves prometheus metrics on localhost, port 0 picks a free one
+    pub fn start_metrics(&mut self, port: u16) {
+        #[cfg(feature = "metrics")]
+        match ServerMetrics::start(port) {
+            Ok(metrics) => {
+                log_info!(Transport, "serving metrics on {}", metrics.address());
+                self.metrics = Some(metrics);
+            }
+            Err(error) => log_error!(
+                Transport,
+                "failed to serve metrics on port {}: {}",
+                port,
+                error
+            ),
+        }
+        #[cfg(not(feature = "metrics"))]
+        log_warn!(
+            Transport,
+            "can't serve metrics on port {}, the module was built without the metrics feature",
+            port
+        );
     }
-    pub fn register_message(&mut self, handler: Gd<MessageHandler>, message_type: u16) {
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    fn record_metrics(
+        &mut self,
+        tick_server: Duration,
+        voice_mixing: Duration,
+        send_packets: Duration,
+    ) {
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = self.metrics.as_mut() {
+            metrics.tick_server.observe(tick_server);
+            metrics.voice_mixing.observe(voice_mixing);
+            metrics.send_packets.observe(send_packets);
+            let now = Instant::now();
+            if metrics.render_due(now) {
+                let gauges = ServerGauges {
+                    tick: self.tick,
+                    players: self.server_networker.clients.len(),
+                    reconnecting_players: self.server_networker.reconnecting_clients.len(),
+                    networked_nodes: self.networked_nodes.len(),
+                };
+                let clients = self
+                    .server_networker
+                    .clients
+                    .values()
+                    .map(|x| (x.id, &x.stats));
+                metrics.render(now, gauges, clients);
+            }
+        }
+    }
+    pub fn get_next_object_id(&mut self) -> ObjectId {
+        self.object_ids.allocate(Instant::now())
+    }
+    pub fn register_message(
+        &mut self,
+        handler: Gd<MessageHandler>,
+        message_type: u16,
+        name: String,
+    ) -> Option<u16> {
+        let result = if name.is_empty() {
+            registry::check_manual_type(message_type).map(|_| (message_type, false))
+        } else {
+            self.message_registry.allocate(&name)
+        };
+        let (message_type, new) = match result {
+            Ok(x) => x,
+            Err(error) => {
+                log_error!(Messages, "failed to register message handler: {}", error);
+                return None;
+            }
+        };
+        if self.message_handlers.contains_key(&message_type) {
+            let error = if name.is_empty() {
+                RegistryError::TypeInUse(message_type)
+            } else {
+                RegistryError::NameInUse(name, Some(message_type))
+            };
+            log_error!(Messages, "failed to register message handler: {}", error);
+            return None;
+        }
+        if new {
+            self.queue_message(MessageHandler::create_registry_message(&name, message_type));
+        }
         self.message_handlers.insert(message_type, handler);
+        Some(message_type)
     }
-    pub fn unregister_message(&mut self, message_type: u16) {
-        self.message_handlers.remove(&message_type);
+    // named types stay allocated after their handler is removed so clients never see an id reused
+    pub fn unregister_message(&mut self, handler: Gd<MessageHandler>) {
+        self.message_handlers.retain(|_, x| *x != handler);
     }
     pub fn queue_message(&mut self, message: BitVec<u64, Lsb0>) {
-        self.message_buffer.push_back(message);
+        self.queue_message_from(
+            message,
+            SERVER_SENDER,
+            MessageTarget::Everyone,
+            Delivery::default(),
+        );
     }
-    pub fn start_server(&mut self, bind_addr: String, private_key: [u8; 32]) {
+    // the sender's id and the current tick are put right after the message type so clients know who a message really came from
+    pub fn queue_message_from(
+        &mut self,
+        message: BitVec<u64, Lsb0>,
+        sender: u16,
+        target: MessageTarget,
+        delivery: Delivery,
+    ) {
+        if message.len() < BYTES2 {
+            log_warn!(Messages, "tried to queue message with invalid size");
+            return;
+        }
+        let mut packet: BitVec<u64, Lsb0> = BitVec::with_capacity(message.len() + BYTES2 + BYTES8);
+        packet.extend(&message[..BYTES2]);
+        packet.extend(sender.view_bits::<Lsb0>());
+        packet.extend(self.tick.view_bits::<Lsb0>());
+        packet.extend(&message[BYTES2..]);
+        if delivery.mode == DeliveryMode::Unreliable {
+            let deadline = delivery.deadline();
+            for client in self.server_networker.clients.values_mut() {
+                if target.includes(client.id) {
+                    client
+                        .unreliable_messages
+                        .push_back((packet.clone(), deadline));
+                }
+            }
+            return;
+        }
+        self.message_buffer
+            .push_back((packet, target, delivery.mode));
+    }
+    pub fn start_server(
+        &mut self,
+        bind_addr: String,
+        private_key: [u8; 32],
+        capture: Option<FileCapture>,
+    ) {
         const PROTOCOL_ID: u64 = 0;
         self.server_networker = ServerNetworker {
             server: Server::new(bind_addr, PROTOCOL_ID, private_key).unwrap(),
+            capture,
             ..Default::default()
         };
     }
@@ -100,6 +324,233 @@ pub impl NetNodeServer {
         result.extend(tmp);
         result
     }
+    // netcode doesnt expose token user data to the server once connected so the profile is bound to the token's client id here instead
+    pub fn get_next_client_with_profile(&mut self, profile: PlayerInfo) -> PackedByteArray {
+        let result = self.get_next_client();
+        let now = Instant::now();
+        let expiry = Duration::from_secs(TOKEN_EXPIREY_TIME as u64);
+        self.pending_profiles
+            .retain(|_, x| now.duration_since(x.1) < expiry);
+        self.pending_profiles
+            .insert(self.server_networker.next_client, (profile, now));
+        result
+    }
+    pub fn set_player_info(&mut self, player: u16, display_name: String, roles: Vec<String>) {
+        if let Some(info) = self.players.get_mut(&player) {
+            info.display_name = display_name;
+            info.roles = roles;
+            let message = MessageHandler::create_player_info_message(info);
+            self.queue_message(message);
+            self.queued_signals
+                .push(ServerSignal::PlayerInfoChanged(player));
+        } else {
+            log_warn!(Auth, "tried to set info for unknown player {:#?}", player);
+        }
+    }
+    fn add_player_info(&mut self, player: u16) {
+        let token_id = self
+            .server_networker
+            .clients
+            .values()
+            .find(|x| x.id == player)
+            .map(|x| x.token_id);
+        let mut info = token_id
+            .and_then(|x| self.pending_profiles.remove(&x))
+            .map(|x| x.0)
+            .unwrap_or_else(|| PlayerInfo::anonymous(player));
+        info.id = player;
+        if info.display_name.is_empty() {
+            info.display_name = PlayerInfo::anonymous(player).display_name;
+        }
+        self.queue_message(MessageHandler::create_player_info_message(&info));
+        self.players.insert(player, info);
+        self.queued_signals
+            .push(ServerSignal::PlayerInfoChanged(player));
+    }
+    fn set_player_reconnecting(&mut self, player: u16, reconnecting: bool) {
+        if let Some(info) = self.players.get_mut(&player) {
+            info.reconnecting = reconnecting;
+            let message = MessageHandler::create_player_info_message(info);
+            self.queue_message(message);
+            self.queued_signals
+                .push(ServerSignal::PlayerInfoChanged(player));
+        }
+    }
+    fn remove_player_info(&mut self, player: u16) {
+        if self.players.remove(&player).is_some() {
+            self.queue_message(MessageHandler::create_player_removed_message(player));
+            self.queued_signals
+                .push(ServerSignal::PlayerInfoRemoved(player));
+        }
+    }
+    pub fn load_bans(&mut self, path: String) {
+        self.bans = BanList::load(path);
+    }
+    pub fn kick(&mut self, player: u16, reason: String) -> bool {
+        if !self.remove_player(player, ModerationAction::Kicked, reason) {
+            log_warn!(Auth, "tried to kick unknown player {:#?}", player);
+            return false;
+        }
+        true
+    }
+    // target is an account id or a token target from moderation::token_target, a duration of None bans permanently
+    // any connected players matching the target are removed straight away
+    pub fn ban(&mut self, target: String, duration_seconds: Option<u64>, reason: String) {
+        self.bans
+            .add(target.clone(), duration_seconds, reason.clone());
+        let networker = &self.server_networker;
+        let banned: Vec<u16> = networker
+            .clients
+            .values()
+            .chain(networker.reconnecting_clients.iter().map(|x| &x.0))
+            .filter(|x| {
+                moderation::token_target(x.token_id) == target
+                    || self.players.get(&x.id).is_some_and(|info| {
+                        !info.account_id.is_empty() && info.account_id == target
+                    })
+            })
+            .map(|x| x.id)
+            .collect();
+        for player in banned {
+            self.remove_player(player, ModerationAction::Banned, reason.clone());
+        }
+    }
+    // bans the player's account, or their token if they joined without one
+    pub fn ban_player(
+        &mut self,
+        player: u16,
+        duration_seconds: Option<u64>,
+        reason: String,
+    ) -> bool {
+        let Some(session) = self.server_networker.session(player) else {
+            log_warn!(Auth, "tried to ban unknown player {:#?}", player);
+            return false;
+        };
+        let target = self
+            .players
+            .get(&player)
+            .map(|x| x.account_id.clone())
+            .filter(|x| !x.is_empty())
+            .unwrap_or_else(|| moderation::token_target(session.token_id));
+        self.ban(target, duration_seconds, reason);
+        true
+    }
+    pub fn unban(&mut self, target: &str) -> bool {
+        self.bans.remove(target)
+    }
+    // how many malformed packets a player has sent this session
+    pub fn malformed_packets(&self, player: u16) -> Option<u64> {
+        self.server_networker
+            .session(player)
+            .map(|x| x.decode_errors.total)
+    }
+    pub fn set_muted(&mut self, player: u16, muted: bool) -> bool {
+        let action = if muted {
+            ModerationAction::Muted
+        } else {
+            ModerationAction::Unmuted
+        };
+        self.set_voice_restriction(player, action, |info| &mut info.muted, muted)
+    }
+    pub fn set_deafened(&mut self, player: u16, deafened: bool) -> bool {
+        let action = if deafened {
+            ModerationAction::Deafened
+        } else {
+            ModerationAction::Undeafened
+        };
+        self.set_voice_restriction(player, action, |info| &mut info.deafened, deafened)
+    }
+    fn set_voice_restriction(
+        &mut self,
+        player: u16,
+        action: ModerationAction,
+        flag: fn(&mut PlayerInfo) -> &mut bool,
+        value: bool,
+    ) -> bool {
+        let Some(info) = self.players.get_mut(&player) else {
+            log_warn!(
+                Auth,
+                "tried to {} unknown player {:#?}",
+                action.as_str(),
+                player
+            );
+            return false;
+        };
+        if *flag(info) == value {
+            return true;
+        }
+        *flag(info) = value;
+        let message = MessageHandler::create_player_info_message(info);
+        self.queue_message(message);
+        self.queued_signals
+            .push(ServerSignal::PlayerInfoChanged(player));
+        self.queue_moderation_event(player, action, String::new());
+        true
+    }
+    // ends the player's session for good, the client is told why before it is disconnected
+    fn remove_player(&mut self, player: u16, action: ModerationAction, reason: String) -> bool {
+        let kick_reason = if reason.is_empty() {
+            action.as_str().to_string()
+        } else {
+            format!("{}: {}", action.as_str(), reason)
+        };
+        if let Some(index) = self.server_networker.client_index(player) {
+            self.server_networker.kick_client(index, &kick_reason);
+        } else if let Some(idx) = self
+            .server_networker
+            .reconnecting_clients
+            .iter()
+            .position(|x| x.0.id == player)
+        {
+            self.server_networker.reconnecting_clients.swap_remove(idx);
+        } else {
+            return false;
+        }
+        // sent before the player is removed so clients still know who it was about
+        self.queue_moderation_event(player, action, reason);
+        self.queued_signals.push(ServerSignal::PlayerLeft(player));
+        self.remove_player_info(player);
+        true
+    }
+    fn queue_moderation_event(&mut self, player: u16, action: ModerationAction, reason: String) {
+        let event = ModerationEvent {
+            player,
+            action,
+            reason,
+        };
+        self.queue_message(MessageHandler::create_moderation_message(&event));
+        self.queued_signals.push(ServerSignal::Moderation(event));
+    }
+    // returns the ban reason if a newly joined player is banned
+    fn join_ban_reason(&self, player: u16) -> Option<String> {
+        let token_id = self.server_networker.session(player)?.token_id;
+        let account_id = self
+            .pending_profiles
+            .get(&token_id)
+            .map(|x| x.0.account_id.as_str())
+            .unwrap_or_default();
+        self.bans
+            .find(&[&moderation::token_target(token_id), account_id])
+            .map(|x| x.reason.clone())
+    }
+    fn emit_queued_signals(&mut self) {
+        for signal in std::mem::take(&mut self.queued_signals) {
+            match signal {
+                ServerSignal::PlayerLeft(player) => self.signals().player_left().emit(player),
+                ServerSignal::PlayerInfoChanged(player) => {
+                    self.signals().player_info_changed().emit(player)
+                }
+                ServerSignal::PlayerInfoRemoved(player) => {
+                    self.signals().player_info_removed().emit(player)
+                }
+                ServerSignal::Moderation(event) => self.signals().moderation_event().emit(
+                    event.player,
+                    &GString::from(event.action.as_str()),
+                    &GString::from(&event.reason),
+                ),
+            }
+        }
+    }
     pub fn register_player_object(&mut self, client_id: u16, object: Gd<Node3D>) {
         let client = self
             .server_networker
@@ -112,26 +563,38 @@ pub impl NetNodeServer {
     }
     fn update_network_nodes(&mut self) {
         for client in self.server_networker.clients.iter_mut() {
-            for packet_tuple in client.1.packet_buffers.get_mut(0).unwrap().drain(..) {
+            let packets = std::mem::take(client.1.packet_buffers.get_mut(0).unwrap());
+            for packet_tuple in packets {
                 let packet = packet_tuple.0;
                 let mut pointer: usize = PACKET_HEADER_SIZE + CHANNEL1_HEADER_SIZE;
                 while pointer + BYTES2 <= packet.len() {
-                    let next_obj: u16 = packet[pointer..pointer + BYTES2].load_le();
-                    pointer += BYTES2;
-                    let tmp = self
-                        .networked_nodes
-                        .iter()
-                        .find(|x| Gd::bind(x).objectid == next_obj);
-                    if tmp.is_none() {
-                        godot_warn!(
+                    let entry = match wire::read_node_entry(&packet, &mut pointer) {
+                        Ok(entry) => entry,
+                        Err(error) => {
+                            client
+                                .1
+                                .record_decode_error(error, self.malformed_packet_limit);
+                            break;
+                        }
+                    };
+                    // the server gives out every objectid so an unknown one is a node that was just removed, it is skipped rather than held
+                    let Some(node) = self.node_index.get(entry.objectid) else {
+                        log_debug!(
+                            Sync,
                             "got update for nonexistant netnode with objectid: {:#?}",
-                            next_obj
-                        ); // will give a few spurious errors if we get sync data for a netnode before the creation event
-                        break;
+                            entry.objectid
+                        );
+                        continue;
+                    };
+                    // misconfigured nodes are logged when they are applied, only bad values count against the client
+                    if let Err(UpdateError::Malformed(_)) =
+                        node.bind().update_networked_values(entry.values)
+                    {
+                        client.1.record_decode_error(
+                            DecodeError::InvalidValue("networked node update"),
+                            self.malformed_packet_limit,
+                        );
                     }
-                    let node = Gd::bind(tmp.unwrap());
-                    let types_buff: Vec<NetworkedValueTypes> = node.get_networked_values_types();
-                    node.update_networked_values(&mut pointer, packet.as_bitslice(), &types_buff);
                 }
             }
         }
@@ -144,6 +607,7 @@ pub impl NetNodeServer {
         let bandwidth_per_tick =
             BANDWIDTH_BUDGET / Engine::singleton().get_physics_ticks_per_second() as usize;
         let networker = &mut self.server_networker;
+        let tick = self.tick;
         let mut buffer: Vec<(ClientIndex, BitVec<u64>, u16)> = Vec::new();
         for client in networker.clients.iter_mut() {
             client.1.remaining_bandwidth += bandwidth_per_tick;
@@ -151,7 +615,11 @@ pub impl NetNodeServer {
                 if !client.1.id_received {
                     buffer.push((
                         *client.0,
-                        BitVec::<u64>::from_element(client.1.id as u64),
+                        BitVec::<u64>::from_slice(&[
+                            client.1.id as u64,
+                            client.1.resume_ticket as u64,
+                            (client.1.resume_ticket >> 64) as u64,
+                        ]),
                         CHANNEL_CLIENT_ID,
                     ));
                     client.1.id_received = true;
@@ -170,19 +638,37 @@ pub impl NetNodeServer {
                         client.1.remaining_bandwidth.saturating_sub(packet.len());
                     buffer.push((*client.0, packet, CHANNEL_ACK));
                 }
-                // channel 3 (messages)
+                // channels 3 and 6 (reliable messages)
                 while self.message_buffer.len() > client.1.message_buffer_position {
                     let mut packet: BitVec<u64> =
                         BitVec::with_capacity(MAX_SINGLE_PACKET_PAYLOAD_LENGTH);
-                    let message = &self.message_buffer[client.1.message_buffer_position];
+                    let (message, target, mode) =
+                        &self.message_buffer[client.1.message_buffer_position];
+                    if !target.includes(client.1.id) {
+                        client.1.message_buffer_position += 1;
+                        continue;
+                    }
                     packet.extend(message);
                     if (client.1.remaining_bandwidth as i64 - packet.len() as i64) < 0 {
                         break 'outer;
                     }
                     client.1.remaining_bandwidth -= packet.len();
-                    buffer.push((*client.0, packet, 3));
+                    buffer.push((*client.0, packet, mode.channel()));
                     client.1.message_buffer_position += 1;
                 }
+                // channel 7 (unreliable messages)
+                let now = Instant::now();
+                while let Some((message, deadline)) = client.1.unreliable_messages.pop_front() {
+                    if deadline.is_some_and(|x| x < now) {
+                        continue;
+                    }
+                    if client.1.remaining_bandwidth < message.len() {
+                        client.1.unreliable_messages.push_front((message, deadline));
+                        break 'outer;
+                    }
+                    client.1.remaining_bandwidth -= message.len();
+                    buffer.push((*client.0, message, CHANNEL_MESSAGES_UNRELIABLE));
+                }
                 let mut packet: BitVec<u64> =
                     BitVec::with_capacity(MAX_SINGLE_PACKET_PAYLOAD_LENGTH);
                 // channel 2 (initial sync)
@@ -191,9 +677,9 @@ pub impl NetNodeServer {
                 {
                     packet.extend(0u64.view_bits::<Lsb0>());
                     for index in client.1.sync_progress..self.networked_nodes.len() as u64 {
-                        let node: GdRef<NetworkedNode> =
-                            self.networked_nodes[index as usize].bind();
-                        let tmp = node.get_byte_data(&node.get_networked_values_types());
+                        let tmp = self
+                            .snapshots
+                            .get(tick, &self.networked_nodes[index as usize]);
                         if tmp.len() + packet.len()
                             > cmp::min(
                                 client.1.remaining_bandwidth,
@@ -203,7 +689,7 @@ pub impl NetNodeServer {
                             break;
                         }
                         client.1.sync_progress = index;
-                        packet.extend(tmp);
+                        packet.extend_from_bitslice(tmp);
                     }
 
                     if packet.len() > CHANNEL1_HEADER_SIZE {
@@ -211,8 +697,12 @@ pub impl NetNodeServer {
                         buffer.push((*client.0, packet, 2));
                         continue;
                     }
-                } else {
+                } else if !client.1.finished_sync {
                     client.1.finished_sync = true;
+                    // lets the client know it is fully synced without waiting for the next c1 update
+                    let mut packet: BitVec<u64> = BitVec::new();
+                    packet.extend(CONTROL_SYNC_FINISHED.view_bits::<Lsb0>());
+                    buffer.push((*client.0, packet, CHANNEL_CONTROL));
                 }
                 let mut packet: BitVec<u64> =
                     BitVec::with_capacity(MAX_SINGLE_PACKET_PAYLOAD_LENGTH);
@@ -227,15 +717,14 @@ pub impl NetNodeServer {
                 if client.1.priorities.len() != self.networked_nodes.len() {
                     client.1.priorities.clear();
                     for node_ref in self.networked_nodes.iter() {
-                        client.1.priorities.push((node_ref.clone(), 0));
+                        client.1.priorities.push((node_ref.clone(), 0, 0));
                     }
                 }
                 client.1.priorities.sort_unstable_by(|a, b| a.1.cmp(&b.1));
                 for value in client.1.priorities.iter_mut() {
                     let node_ref = &value.0;
-                    let node = Gd::bind(node_ref);
-                    if (value.1 != 0) && (node.owner_id != client.1.id) {
-                        let tmp = node.get_byte_data(&node.get_networked_values_types());
+                    if (value.1 != 0) && (node_ref.bind().owner_id != client.1.id) {
+                        let tmp = self.snapshots.get(tick, node_ref);
                         if tmp.len() + packet.len()
                             > cmp::min(
                                 client.1.remaining_bandwidth,
@@ -244,8 +733,9 @@ pub impl NetNodeServer {
                         {
                             break;
                         }
-                        packet.extend(tmp);
+                        packet.extend_from_bitslice(tmp);
                         value.1 = 0;
+                        value.2 = tick;
                     }
                 }
                 if packet.len() > CHANNEL1_HEADER_SIZE {
@@ -264,11 +754,30 @@ pub impl NetNodeServer {
     }
     fn tick_server(&mut self) {
         // cycle buffers, poll for new packets from the networker
-        let new_players = self.server_networker.poll();
-        if !new_players.is_empty() {
-            for player in new_players {
-                godot_warn!("new player");
-                self.signals().player_joined().emit(player);
+        for event in self
+            .server_networker
+            .poll(self.malformed_packet_limit, &self.packet_limits)
+        {
+            match event {
+                ConnectionEvent::Joined(player) => {
+                    if let Some(reason) = self.join_ban_reason(player) {
+                        log_info!(Auth, "refused banned player {:#?}", player);
+                        let index = self.server_networker.client_index(player).unwrap();
+                        let reason = format!("{}: {}", ModerationAction::Banned.as_str(), reason);
+                        if let Some(client) = self.server_networker.kick_client(index, &reason) {
+                            self.pending_profiles.remove(&client.token_id);
+                        }
+                        continue;
+                    }
+                    log_info!(Auth, "player {:#?} joined", player);
+                    self.add_player_info(player);
+                    self.signals().player_joined().emit(player);
+                }
+                ConnectionEvent::Resumed(player) => {
+                    log_info!(Auth, "player {:#?} resumed their session", player);
+                    self.set_player_reconnecting(player, false);
+                    self.signals().player_resumed().emit(player);
+                }
             }
         }
         // check for and handle disconnected clients
@@ -276,18 +785,44 @@ pub impl NetNodeServer {
             let mut dc_client: Option<ClientIndex> = None;
             for client in self.server_networker.clients.iter() {
                 if self.server_networker.server.client_id(*client.0).is_none() {
-                    dc_client = Some(client.0.clone());
-                    let id = client.1.id;
-                    self.signals().player_left().emit(id);
+                    dc_client = Some(*client.0);
                     break;
                 }
             }
-            if dc_client.is_some() {
-                self.server_networker.clients.remove(&dc_client.unwrap());
+            if let Some(dc_client) = dc_client {
+                let client = self.server_networker.clients.remove(&dc_client).unwrap();
+                let id = client.id;
+                if client.leaving {
+                    self.signals().player_left().emit(id);
+                    self.remove_player_info(id);
+                } else {
+                    // the connection dropped without the client leaving so hold their session open for a while
+                    self.server_networker
+                        .reconnecting_clients
+                        .push((client, Instant::now()));
+                    self.set_player_reconnecting(id, true);
+                    self.signals().player_reconnecting().emit(id);
+                }
             } else {
                 break;
             }
         }
+        let reconnect_window = Duration::from_secs_f64(self.reconnect_window_seconds.max(0.0));
+        while let Some(idx) = self
+            .server_networker
+            .reconnecting_clients
+            .iter()
+            .position(|x| x.1.elapsed() > reconnect_window)
+        {
+            let id = self
+                .server_networker
+                .reconnecting_clients
+                .swap_remove(idx)
+                .0
+                .id;
+            self.signals().player_left().emit(id);
+            self.remove_player_info(id);
+        }
         let networker = &mut self.server_networker;
         let mut current_frame_hit_rates: HashMap<ClientIndex, u64> =
             HashMap::from_iter(networker.clients.iter().map(|x| (*x.0, 0)));
@@ -300,8 +835,9 @@ pub impl NetNodeServer {
             }
             let packet = &packet_tuple.0;
             let client = networker.clients.get_mut(&packet_tuple.1).unwrap();
-            if packet.len() < BYTES2 {
-                godot_warn!("got packet with invalid size");
+            // headers are checked when packets are received so this should never happen
+            if packet.len() < PACKET_HEADER_SIZE {
+                log_warn!(Transport, "got packet with invalid size");
                 continue;
             }
             let mut pointer: usize = 0;
@@ -310,13 +846,20 @@ pub impl NetNodeServer {
             let packet_number: u64 = packet[pointer..pointer + BYTES8].load_le();
             pointer += BYTES8;
             match channelid {
-                0 => {
-                    // control packets
+                CHANNEL_CONTROL => {
+                    // resume requests are handled when the connection is first seen so only leaving matters here
+                    if packet
+                        .get(pointer..pointer + BYTE)
+                        .map(|x| x.load_le::<u8>())
+                        == Some(CONTROL_LEAVE)
+                    {
+                        client.leaving = true;
+                    }
                 }
                 // get channel 1 data for inputs and remote owned objects and send to buffer cycle
                 1 => {
                     if packet.len() < PACKET_HEADER_SIZE + CHANNEL1_HEADER_SIZE {
-                        godot_warn!("got c1 packet with invalid size");
+                        log_warn!(Transport, "got c1 packet with invalid size");
                         continue;
                     }
                     const PACKET_LATENCY_DISCARD_THRESHOLD: Duration = Duration::from_millis(1000);
@@ -332,10 +875,11 @@ pub impl NetNodeServer {
                     let current_time: Duration =
                         SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
 
-                    // latency calculations
-                    let latency: Duration = current_time - packet_send_time;
+                    // latency calculations, the send time comes from the client so it can be ahead of our clock
+                    let latency: Duration = current_time.saturating_sub(packet_send_time);
                     if latency > PACKET_LATENCY_DISCARD_THRESHOLD {
-                        godot_warn!(
+                        log_info!(
+                            Transport,
                             "ignoring packet with high latency: {:#?}ms",
                             latency.as_millis()
                         );
@@ -345,6 +889,7 @@ pub impl NetNodeServer {
                         client.latency_buffer.pop_front();
                     }
                     client.latency_buffer.push_back(latency);
+                    client.stats.record_latency(latency);
                     client.latency = Duration::from_millis(
                         client
                             .latency_buffer
@@ -375,12 +920,24 @@ pub impl NetNodeServer {
                     if packet_accepted {
                         *current_frame_hit_rates.get_mut(&client_index).unwrap() += 1;
                     } else {
-                        godot_warn!("ignoring packet due to jitter",);
+                        log_debug!(Sync, "ignoring packet due to jitter",);
 
                         *current_frame_miss_rates.get_mut(&client_index).unwrap() += 1;
                     }
                 }
                 3 => {
+                    // resends of packets we already handled are ignored
+                    if packet_number < client.next_c3_packet_number {
+                        continue;
+                    }
+                    if let Some(violation) = self.packet_limits.check_reliable(
+                        channelid,
+                        packet_number,
+                        client.next_c3_packet_number,
+                    ) && !client.exceed_packet_limit(violation, self.packet_limits.policy)
+                    {
+                        continue;
+                    }
                     client
                         .c3_buffered_packets
                         .insert(packet_number, packet_tuple.0);
@@ -389,32 +946,61 @@ pub impl NetNodeServer {
                         .remove(&client.next_c3_packet_number)
                     {
                         client.next_c3_packet_number += 1;
-                        let mut pointer: usize = BYTES2 + BYTES8;
-                        self.message_buffer.push_back(packet[pointer..].to_bitvec());
-                        let message_type: u16 = packet[pointer..pointer + BYTES2].load_le();
-                        pointer += BYTES2;
-                        if let Some(handler) = self.message_handlers.get_mut(&message_type) {
-                            handler
-                                .bind_mut()
-                                .handle_message(packet.as_bitslice(), &mut pointer);
-                        } else {
-                            godot_warn!(
-                                "received unhandled message with type: {:#?}",
-                                message_type
-                            );
+                        if let Err(error) = NetNodeServer::receive_client_message(
+                            client,
+                            packet.as_bitslice(),
+                            &mut self.message_handlers,
+                            &self.message_limits,
+                            self.tick,
+                            true,
+                        ) {
+                            client.record_decode_error(error, self.malformed_packet_limit);
                         }
                     }
                 }
+                CHANNEL_MESSAGES_UNORDERED => {
+                    if client.c6_received.first_time(packet_number)
+                        && let Err(error) = NetNodeServer::receive_client_message(
+                            client,
+                            packet,
+                            &mut self.message_handlers,
+                            &self.message_limits,
+                            self.tick,
+                            true,
+                        )
+                    {
+                        client.record_decode_error(error, self.malformed_packet_limit);
+                    }
+                }
+                CHANNEL_MESSAGES_UNRELIABLE => {
+                    if let Err(error) = NetNodeServer::receive_client_message(
+                        client,
+                        packet,
+                        &mut self.message_handlers,
+                        &self.message_limits,
+                        self.tick,
+                        false,
+                    ) {
+                        client.record_decode_error(error, self.malformed_packet_limit);
+                    }
+                }
                 5 => {
                     let buffer: Vec<u8> = packet.chunks(BYTE).map(|x| x.load_le::<u8>()).collect();
+                    if let Some(violation) = self.packet_limits.check_voice(
+                        buffer.len().saturating_sub(PACKET_HEADER_SIZE / BYTE),
+                        client.voice_packet_buffer.len(),
+                    ) && !client.exceed_packet_limit(violation, self.packet_limits.policy)
+                    {
+                        continue;
+                    }
                     if client.next_c5_packet_number <= packet_number {
                         client
                             .voice_packet_buffer
-                            .push((packet_number, buffer[10..].into_iter().copied().collect()));
+                            .push((packet_number, buffer[wire::PACKET_HEADER_BYTES..].to_vec()));
                     }
                 }
                 _ => {
-                    godot_warn!("unhandled channel: {:#?}", channelid);
+                    log_warn!(Transport, "unhandled channel: {:#?}", channelid);
                 }
             }
         }
@@ -481,6 +1067,99 @@ pub impl NetNodeServer {
             latency_info.c1_miss_rate_average_percent = latency_info.c1_miss_rate_average
                 / (latency_info.c1_hit_rate_average + latency_info.c1_miss_rate_average);
         }
+        let violations: Vec<(u16, String)> = networker
+            .clients
+            .values_mut()
+            .flat_map(|x| {
+                let player = x.id;
+                std::mem::take(&mut x.limit_violations)
+                    .into_iter()
+                    .map(move |violation| (player, violation))
+            })
+            .collect();
+        // clients that went over a limit with the kick policy
+        let kicked: Vec<(u16, String)> = networker
+            .clients
+            .values_mut()
+            .filter_map(|x| x.kick_reason.take().map(|reason| (x.id, reason)))
+            .collect();
+        for (player, violation) in violations {
+            self.signals()
+                .packet_limit_exceeded()
+                .emit(player, &GString::from(&violation));
+        }
+        for (player, reason) in kicked {
+            log_warn!(Auth, "kicking player {:#?}: {}", player, reason);
+            self.remove_player(player, ModerationAction::Kicked, reason);
+        }
+    }
+    // limits, routing and authorization for a message from a client, shared by all the message channels
+    fn receive_client_message(
+        client: &mut Client,
+        packet: &BitSlice<u64, Lsb0>,
+        handlers: &mut HashMap<u16, Gd<MessageHandler>>,
+        default_limits: &MessageLimits,
+        tick: u64,
+        reliable: bool,
+    ) -> Result<(), DecodeError> {
+        let mut pointer: usize = BYTES2 + BYTES8;
+        let message_type = wire::read_u16(packet, &mut pointer, "message type")?;
+        pointer -= BYTES2;
+        if is_reserved_message_type(message_type) {
+            return Err(DecodeError::InvalidValue("message type"));
+        }
+        let mut handler = handlers.get_mut(&message_type);
+        let limits = handler
+            .as_ref()
+            .map(|x| x.bind().limits(default_limits))
+            .unwrap_or(*default_limits);
+        let size = (packet.len() - pointer) / BYTE;
+        if let Some(violation) = limits.check(
+            client.message_buckets.entry(message_type).or_default(),
+            size,
+        ) {
+            if let Some(handler) = handler.as_mut() {
+                handler
+                    .bind_mut()
+                    .notify_limit_exceeded(client.id, violation.clone());
+            }
+            match limits.policy {
+                LimitPolicy::Drop => return Ok(()),
+                LimitPolicy::Warn => log_warn!(
+                    Messages,
+                    "player {:#?} went over the limits for message type {:#?}: {}",
+                    client.id,
+                    message_type,
+                    violation
+                ),
+                LimitPolicy::Kick => {
+                    client.kick_reason = Some(violation);
+                    return Ok(());
+                }
+            }
+        }
+        pointer += BYTES2;
+        let target = MessageTarget::decode(packet, &mut pointer)
+            .ok_or(DecodeError::InvalidValue("message target"))?;
+        // nothing is relayed until the server's handler has approved it
+        if let Some(handler) = handler {
+            handler
+                .bind_mut()
+                .handle_client_message(
+                    packet,
+                    &mut pointer,
+                    MessageContext::create(client.id, tick, reliable),
+                    target,
+                )
+                .map_err(DecodeError::Message)?;
+        } else {
+            log_warn!(
+                Messages,
+                "dropping unhandled message with type: {:#?}",
+                message_type
+            );
+        }
+        Ok(())
     }
     fn process_voice_input(&mut self) {
         const DISTANCE_FALLOFF_START: f32 = 10.0;
@@ -505,17 +1184,33 @@ pub impl NetNodeServer {
                 buffer = Vec::new();
             }
             client.next_c5_packet_number += 1;
-            client.audio_input_buffer = self
+            client.audio_input_buffer = match self
                 .voice_manager
-                .decode_audio(client.voice_input_stream.unwrap(), &buffer);
+                .decode_audio(client.voice_input_stream.unwrap(), &buffer)
+            {
+                Ok(audio) => audio,
+                Err(error) => {
+                    client.record_decode_error(
+                        DecodeError::Voice(error.to_string()),
+                        self.malformed_packet_limit,
+                    );
+                    vec![0.0; FRAME_LENGTH]
+                }
+            };
         }
         // todo: would probably be a good idea to use an audio library to handle this for us
         // then we could properly spatialize audio with hrtf, model room dampening, and handle falloff better
-        let audio_streams: Vec<(Vec<f32>, ClientIndex)> = self
+        let audio_streams: Vec<(Vec<f32>, ClientIndex, bool)> = self
             .server_networker
             .clients
             .values()
-            .map(|x| (x.audio_input_buffer.clone(), x.index))
+            .map(|x| {
+                (
+                    x.audio_input_buffer.clone(),
+                    x.index,
+                    self.players.get(&x.id).is_some_and(|x| x.muted),
+                )
+            })
             .collect();
         let positions: Vec<(Vector3, ClientIndex)> = self
             .server_networker
@@ -560,8 +1255,13 @@ pub impl NetNodeServer {
                 .get_quaternion()
                 .inverse();
             let mut final_audio: Vec<(f32, f32)> = Vec::new();
+            // deafened players still get a stream so their decoder keeps running, it is just silent
+            let deafened = self.players.get(&client.id).is_some_and(|x| x.deafened);
             for audio_source in 0..audio_streams.len() {
-                if audio_streams[audio_source].1 == client.index {
+                if audio_streams[audio_source].1 == client.index
+                    || audio_streams[audio_source].2
+                    || deafened
+                {
                     continue;
                 }
                 let l_r_bias: f32; // directionality, -1.0 for fully left, 1.0 for fully right
@@ -634,6 +1334,7 @@ pub impl NetNodeServer {
 #[godot_api]
 impl INode for NetNodeServer {
     fn physics_process(&mut self, _delta: f64) {
+        self.tick += 1;
         // cycle channel 1 packet buffers
         for client in self.server_networker.clients.iter_mut() {
             client.1.packet_buffers.pop_front();
@@ -648,10 +1349,19 @@ impl INode for NetNodeServer {
                 }
             }
         }
+        self.emit_queued_signals();
+        let started = Instant::now();
         self.tick_server();
+        let tick_server_time = started.elapsed();
+        self.emit_queued_signals();
         self.update_network_nodes();
+        let started = Instant::now();
         self.process_voice_input();
+        let voice_mixing_time = started.elapsed();
+        let started = Instant::now();
         self.send_packets_server();
+        let send_packets_time = started.elapsed();
+        self.record_metrics(tick_server_time, voice_mixing_time, send_packets_time);
     }
 }
 
@@ -662,6 +1372,10 @@ struct ServerNetworker {
     next_client: u64,
     next_client_id: u16,
     clients: HashMap<ClientIndex, Client>,
+    // clients whose connection dropped, along with when it dropped
+    reconnecting_clients: Vec<(Client, Instant)>,
+    // every payload sent and received is written here when capturing
+    capture: Option<FileCapture>,
 }
 impl Default for ServerNetworker {
     fn default() -> Self {
@@ -677,12 +1391,13 @@ impl Default for ServerNetworker {
             next_client: 0,
             next_client_id: 0,
             clients: HashMap::new(),
+            reconnecting_clients: Vec::new(),
+            capture: None,
         }
     }
 }
 impl ServerNetworker {
     fn get_token(&mut self) -> ConnectToken {
-        const TOKEN_EXPIREY_TIME: i32 = -1;
         const TOKEN_TIMEOUT_THRESHOLD: i32 = 30;
         self.next_client += 1;
         self.server
@@ -698,6 +1413,11 @@ impl ServerNetworker {
         let reliable: bool;
         let mut packet_number: Option<u64> = None;
         match channel {
+            CHANNEL_CONTROL => {
+                reliable = true;
+                packet_number = Some(client.packet_number_c0);
+                client.packet_number_c0 += 1;
+            }
             1 => {
                 reliable = false;
                 packet_number = Some(client.packet_number_c1);
@@ -723,13 +1443,23 @@ impl ServerNetworker {
                 packet_number = Some(client.packet_number_c5);
                 client.packet_number_c5 += 1;
             }
+            CHANNEL_MESSAGES_UNORDERED => {
+                reliable = true;
+                packet_number = Some(client.packet_number_c6);
+                client.packet_number_c6 += 1;
+            }
+            CHANNEL_MESSAGES_UNRELIABLE => {
+                reliable = false;
+                packet_number = Some(client.packet_number_c7);
+                client.packet_number_c7 += 1;
+            }
             CHANNEL_CLIENT_ID => {
                 reliable = true;
                 packet_number = Some(0);
             }
             u16::MAX => reliable = false,
             _ => {
-                godot_warn!("unhandled / invalid channel sent");
+                log_warn!(Transport, "unhandled / invalid channel sent");
                 reliable = false;
             }
         }
@@ -753,10 +1483,14 @@ impl ServerNetworker {
             final_packet.push(bits.load_le::<u8>());
         }
         self.server.send(&final_packet, client_index).unwrap();
+        client.stats.record_sent(channel, final_packet.len());
+        if let Some(capture) = self.capture.as_mut() {
+            capture.packet(Direction::Sent, client.id, &final_packet);
+        }
         if packet_number.is_some() && reliable {
             client.reliable_packets.insert(
                 (channel, packet_number.unwrap()),
-                (final_packet, Instant::now()),
+                (final_packet, Instant::now(), false),
             );
         }
     }
@@ -772,37 +1506,88 @@ impl ServerNetworker {
             self.send(chunk, 4, client_index);
         }
     }
-    fn poll(&mut self) -> Vec<u16> {
+    fn poll(&mut self, malformed_packet_limit: u32, limits: &PacketLimits) -> Vec<ConnectionEvent> {
+        if let Some(capture) = self.capture.as_mut() {
+            capture.tick();
+            if capture.failed() {
+                log_error!(Transport, "failed to write the packet capture, stopping it");
+                self.capture = None;
+            }
+        }
         self.server.update(self.start_time.elapsed().as_secs_f64());
-        let mut new_players: Vec<u16> = Vec::new();
+        let mut events: Vec<ConnectionEvent> = Vec::new();
         while let Some(packet) = self.server.recv() {
-            if self.clients.contains_key(&packet.1) {
-                self.clients
-                    .get_mut(&packet.1)
-                    .unwrap()
-                    .last_packet_send_time = Instant::now();
+            if let Some(capture) = self.capture.as_mut() {
+                // connections that aren't a player yet are recorded as peer 0
+                let peer = self.clients.get(&packet.1).map_or(0, |x| x.id);
+                capture.packet(Direction::Received, peer, &packet.0);
+            }
+            if let Some(client) = self.clients.get_mut(&packet.1) {
+                client.last_packet_send_time = Instant::now();
+                if let Some(violation) = limits.check_rate(&mut client.packet_bucket)
+                    && !client.exceed_packet_limit(violation, limits.policy)
+                {
+                    continue;
+                }
+            } else if let Some(ticket) = ServerNetworker::parse_resume_request(&packet.0) {
+                if let Some(mut client) = self.take_resumable_client(ticket) {
+                    client.index = packet.1;
+                    client.token_id = self.server.client_id(packet.1).unwrap_or_default();
+                    client.last_packet_send_time = Instant::now();
+                    let id = client.id;
+                    self.clients.insert(packet.1, client);
+                    let mut accepted: BitVec<u64, Lsb0> = BitVec::new();
+                    accepted.extend(CONTROL_RESUME_ACCEPTED.view_bits::<Lsb0>());
+                    self.send(accepted.as_bitslice(), CHANNEL_CONTROL, packet.1);
+                    events.push(ConnectionEvent::Resumed(id));
+                } else {
+                    // unknown or expired session, the client has to join again from scratch
+                    let mut rejection: Vec<u8> = Vec::with_capacity(PACKET_HEADER_SIZE / BYTE + 1);
+                    rejection.extend(CHANNEL_CONTROL.to_le_bytes());
+                    rejection.extend(0u64.to_le_bytes());
+                    rejection.push(CONTROL_RESUME_REJECTED);
+                    let _ = self.server.send(&rejection, packet.1);
+                    if let Some(capture) = self.capture.as_mut() {
+                        capture.packet(Direction::Sent, 0, &rejection);
+                    }
+                    let _ = self.server.disconnect(packet.1);
+                    continue;
+                }
             } else {
-                godot_warn!("new player packet");
+                log_debug!(Transport, "new player packet");
                 self.next_client_id += 1;
                 self.clients.insert(
                     packet.1,
                     Client {
                         index: packet.1,
+                        resume_ticket: u128::from_le_bytes(
+                            netcode::generate_key()[..16].try_into().unwrap(),
+                        ),
+                        leaving: false,
+                        kick_reason: None,
+                        limit_violations: Vec::new(),
+                        message_buckets: HashMap::new(),
+                        packet_bucket: TokenBucket::default(),
+                        decode_errors: DecodeErrors::default(),
+                        token_id: self.server.client_id(packet.1).unwrap_or_default(),
                         finished_sync: false,
                         remaining_bandwidth: 0,
+                        packet_number_c0: 0,
                         packet_number_c1: 0,
                         packet_number_c2: 0,
                         packet_number_c3: 0,
                         packet_number_c4: 0,
                         packet_number_c5: 0,
+                        packet_number_c6: 0,
+                        packet_number_c7: 0,
+                        c6_received: ReceivedPackets::default(),
+                        unreliable_messages: VecDeque::new(),
                         player_position_object: None,
                         voice_input_stream: None,
                         audio_output_stream: None,
                         voice_packet_buffer: Vec::new(),
                         audio_input_buffer: vec![0.0; FRAME_LENGTH],
-                        c4_remaining_packet_chunks: 0,
-                        c4_packet_chunks: Vec::new(),
-                        c4_waiting_packets: HashMap::new(),
+                        c4_assembler: SplitAssembler::default(),
                         sync_progress: 0,
                         last_packet_send_time: Instant::now(),
                         reliable_packets: HashMap::new(),
@@ -815,126 +1600,166 @@ impl ServerNetworker {
                         priorities: Vec::new(),
                         c1_latency_info: LatencyInfo::default(),
                         next_c3_packet_number: 0,
-                        next_c4_packet_number: 0,
                         next_c5_packet_number: 0,
                         c3_buffered_packets: HashMap::new(),
                         packet_buffers: VecDeque::from_iter([Vec::new(), Vec::new()]),
+                        stats: PeerStats::default(),
                     },
                 );
-                new_players.push(self.next_client_id);
+                events.push(ConnectionEvent::Joined(self.next_client_id));
             }
             let client = self.clients.get_mut(&packet.1).unwrap();
-            let channel: u16 = u16::from_le_bytes([packet.0[0], packet.0[1]]);
-            if channel == CHANNEL_ACK {
-                let mut pointer: usize = PACKET_HEADER_SIZE_ACK / BYTE;
-                while pointer + ((BYTES8 + BYTES2) / BYTE) <= packet.0.len() {
-                    let packet_channel =
-                        u16::from_le_bytes([packet.0[pointer], packet.0[pointer + 1]]);
-                    pointer += BYTES2 / BYTE;
-                    let packet_num = u64::from_le_bytes([
-                        packet.0[pointer],
-                        packet.0[pointer + 1],
-                        packet.0[pointer + 2],
-                        packet.0[pointer + 3],
-                        packet.0[pointer + 4],
-                        packet.0[pointer + 5],
-                        packet.0[pointer + 6],
-                        packet.0[pointer + 7],
-                    ]);
-                    pointer += 8;
-                    client
-                        .reliable_packets
-                        .remove(&(packet_channel, packet_num));
-                }
-                continue;
-            }
-            let packet_number: u64 = u64::from_le_bytes([
-                packet.0[2],
-                packet.0[3],
-                packet.0[4],
-                packet.0[5],
-                packet.0[6],
-                packet.0[7],
-                packet.0[8],
-                packet.0[9],
-            ]);
-            client.waiting_acks.insert((channel, packet_number));
-            if channel == 4 {
-                client.c4_waiting_packets.insert(packet_number, packet.0);
-
-                while let Some(packet) = client
-                    .c4_waiting_packets
-                    .remove(&client.next_c4_packet_number)
-                {
-                    client.next_c4_packet_number += 1;
-                    if client.c4_remaining_packet_chunks == 0 {
-                        client.c4_remaining_packet_chunks = u64::from_le_bytes([
-                            packet[10], packet[11], packet[12], packet[13], packet[14], packet[15],
-                            packet[16], packet[17],
-                        ]);
-                    } else {
-                        client.c4_packet_chunks.push(packet);
-                        client.c4_remaining_packet_chunks -= 1;
-                        if client.c4_remaining_packet_chunks == 0 {
-                            let mut packet: Vec<u8> = Vec::with_capacity(
-                                client.c4_packet_chunks.iter().map(|x| x.len()).sum(),
-                            );
-                            for chunk in client.c4_packet_chunks.iter() {
-                                packet.extend(chunk[10..].iter());
-                            }
-                            client.c4_packet_chunks.clear();
-                            let channel: u16 = u16::from_le_bytes([packet[0], packet[1]]);
-                            if channel == CHANNEL_ACK {
-                                let mut pointer: usize = PACKET_HEADER_SIZE_ACK / BYTE;
-                                while pointer + ((BYTES8 + BYTES2) / BYTE) <= packet.len() {
-                                    let packet_channel =
-                                        u16::from_le_bytes([packet[pointer], packet[pointer + 1]]);
-                                    pointer += BYTES2 / BYTE;
-                                    let packet_num = u64::from_le_bytes([
-                                        packet[pointer],
-                                        packet[pointer + 1],
-                                        packet[pointer + 2],
-                                        packet[pointer + 3],
-                                        packet[pointer + 4],
-                                        packet[pointer + 5],
-                                        packet[pointer + 6],
-                                        packet[pointer + 7],
-                                    ]);
-                                    pointer += 8;
-                                    client
-                                        .reliable_packets
-                                        .remove(&(packet_channel, packet_num));
-                                }
-                                continue;
-                            }
-                            let mut packet_bits: BitVec<u64, Lsb0> =
-                                BitVec::with_capacity(packet.len() * 8);
-                            for byte in packet {
-                                packet_bits.extend(byte.view_bits::<Lsb0>());
-                            }
-                            self.packet_buffer.push((packet_bits, client.index));
-                        }
-                    }
-                }
-                continue;
-            }
-
-            let mut packet_bits: BitVec<u64, Lsb0> = BitVec::with_capacity(packet.0.len() * 8);
-            for byte in packet.0 {
-                packet_bits.extend(byte.view_bits::<Lsb0>());
+            if let Err(error) =
+                ServerNetworker::receive_packet(client, packet.0, &mut self.packet_buffer, limits)
+            {
+                client.record_decode_error(error, malformed_packet_limit);
             }
-            self.packet_buffer.push((packet_bits, packet.1));
         }
         let now = Instant::now();
         for client in self.clients.iter_mut() {
             for packet in client.1.reliable_packets.iter_mut() {
                 if now - packet.1.1 > (client.1.latency + Duration::from_millis(32)) * 3 {
                     ServerNetworker::resend(&mut self.server, &packet.1.0, client.0.to_owned());
+                    if let Some(capture) = self.capture.as_mut() {
+                        capture.packet(Direction::Sent, client.1.id, &packet.1.0);
+                    }
+                    client.1.stats.record_resend(packet.0.0, packet.1.0.len());
                     packet.1.1 = Instant::now();
+                    packet.1.2 = true;
+                }
+            }
+        }
+        events
+    }
+    // checks the header of a packet from a connected client, acks and split packets are handled here
+    // everything else is queued for tick_server
+    fn receive_packet(
+        client: &mut Client,
+        packet: Vec<u8>,
+        packet_buffer: &mut Vec<(BitVec<u64, Lsb0>, ClientIndex)>,
+        limits: &PacketLimits,
+    ) -> Result<(), DecodeError> {
+        if let Ok(channel) = wire::read_channel(&packet) {
+            client.stats.record_received(channel, packet.len());
+        }
+        let (channel, packet_number) = match wire::read_packet(&packet)? {
+            RawPacket::Acks(acks) => {
+                for ack in acks {
+                    client.acknowledge(ack);
+                }
+                return Ok(());
+            }
+            RawPacket::Data {
+                channel,
+                packet_number,
+            } => (channel, packet_number),
+        };
+        if !CLIENT_CHANNELS.contains(&channel) {
+            return Err(DecodeError::UnknownChannel(channel));
+        }
+        if channel == 1 {
+            client.stats.record_sequence(packet_number);
+        }
+        client.waiting_acks.insert((channel, packet_number));
+        if channel != CHANNEL_SPLIT {
+            packet_buffer.push((wire::to_bits(&packet), client.index));
+            return Ok(());
+        }
+        if let Some(violation) = limits.check_reliable(
+            channel,
+            packet_number,
+            client.c4_assembler.next_packet_number(),
+        ) && !client.exceed_packet_limit(violation, limits.policy)
+        {
+            return Ok(());
+        }
+        let mut completed: Vec<Vec<u8>> = Vec::new();
+        // the window was checked above so chunks the policy lets through are always buffered
+        let result = client.c4_assembler.push(
+            packet_number,
+            packet,
+            MAX_SPLIT_CHUNKS,
+            u64::MAX,
+            &mut completed,
+        );
+        if let Err(DecodeError::TooManyChunks(_)) = result {
+            client.kick_reason = Some("sent an oversized split packet".to_string());
+        }
+        for packet in completed {
+            match wire::read_packet(&packet)? {
+                RawPacket::Acks(acks) => {
+                    for ack in acks {
+                        client.acknowledge(ack);
+                    }
+                }
+                RawPacket::Data { channel, .. }
+                    if !CLIENT_CHANNELS.contains(&channel) || channel == CHANNEL_SPLIT =>
+                {
+                    return Err(DecodeError::UnknownChannel(channel));
+                }
+                RawPacket::Data { .. } => {
+                    packet_buffer.push((wire::to_bits(&packet), client.index));
                 }
             }
         }
-        new_players
+        result
+    }
+    // returns the ticket if the packet is a resume request
+    fn parse_resume_request(packet: &[u8]) -> Option<u128> {
+        const TICKET_START: usize = PACKET_HEADER_SIZE / BYTE + 1;
+        if packet.get(..BYTES2 / BYTE) != Some(&CHANNEL_CONTROL.to_le_bytes())
+            || packet.get(PACKET_HEADER_SIZE / BYTE) != Some(&CONTROL_RESUME)
+        {
+            return None;
+        }
+        let ticket: [u8; 16] = packet
+            .get(TICKET_START..TICKET_START + 16)?
+            .try_into()
+            .ok()?;
+        Some(u128::from_le_bytes(ticket))
+    }
+    fn take_resumable_client(&mut self, ticket: u128) -> Option<Client> {
+        if let Some(idx) = self
+            .reconnecting_clients
+            .iter()
+            .position(|x| x.0.resume_ticket == ticket)
+        {
+            return Some(self.reconnecting_clients.swap_remove(idx).0);
+        }
+        // the client can notice the drop before we do, in which case its old connection is still around
+        let old_index = *self.clients.iter().find(|x| x.1.resume_ticket == ticket)?.0;
+        let _ = self.server.disconnect(old_index);
+        self.clients.remove(&old_index)
+    }
+    fn client_index(&self, player: u16) -> Option<ClientIndex> {
+        self.clients
+            .values()
+            .find(|x| x.id == player)
+            .map(|x| x.index)
+    }
+    // finds a player's session whether they are connected or waiting to resume
+    fn session(&self, player: u16) -> Option<&Client> {
+        self.clients
+            .values()
+            .chain(self.reconnecting_clients.iter().map(|x| &x.0))
+            .find(|x| x.id == player)
+    }
+    // tells the client why it is being removed and drops the connection straight away
+    // the reason is best effort, if it is lost the client just sees a normal disconnect
+    fn kick_client(&mut self, index: ClientIndex, reason: &str) -> Option<Client> {
+        const MAX_REASON_BYTES: usize = 256;
+        let mut end = reason.len().min(MAX_REASON_BYTES);
+        while !reason.is_char_boundary(end) {
+            end -= 1;
+        }
+        let mut packet: BitVec<u64, Lsb0> = BitVec::new();
+        packet.extend(CONTROL_KICKED.view_bits::<Lsb0>());
+        for byte in reason[..end].bytes() {
+            packet.extend(byte.view_bits::<Lsb0>());
+        }
+        self.send(packet.as_bitslice(), CHANNEL_CONTROL, index);
+        let _ = self.server.disconnect(index);
+        self.clients.remove(&index)
     }
     fn resend(server: &mut Server<NetcodeSocket, ()>, final_packet: &[u8], client: ClientIndex) {
         server.send(final_packet, client).unwrap();
@@ -943,37 +1768,147 @@ impl ServerNetworker {
 
 struct Client {
     index: ClientIndex,
+    // the client id the connect token was issued with
+    token_id: u64,
+    // secret given to the client so it can take this session over from a new connection
+    resume_ticket: u128,
+    // set when the client tells us it is disconnecting on purpose
+    leaving: bool,
+    // set when the client breaks a limit badly enough to be kicked, handled at the end of the tick
+    kick_reason: Option<String>,
+    // packet limits the client went over this tick, emitted as packet_limit_exceeded at the end of the tick
+    limit_violations: Vec<String>,
+    message_buckets: HashMap<u16, TokenBucket>,
+    packet_bucket: TokenBucket,
+    decode_errors: DecodeErrors,
     finished_sync: bool,
     remaining_bandwidth: usize,
+    packet_number_c0: u64,
     packet_number_c1: u64,
     packet_number_c2: u64,
     packet_number_c3: u64,
     packet_number_c4: u64,
     packet_number_c5: u64,
+    packet_number_c6: u64,
+    packet_number_c7: u64,
+    c6_received: ReceivedPackets,
+    // unreliable messages waiting to be sent and when they expire
+    unreliable_messages: VecDeque<(BitVec<u64, Lsb0>, Option<Instant>)>,
     player_position_object: Option<Gd<Node3D>>,
     voice_input_stream: Option<usize>,
     audio_output_stream: Option<usize>,
     voice_packet_buffer: Vec<(u64, Vec<u8>)>,
     audio_input_buffer: Vec<f32>,
-    c4_remaining_packet_chunks: u64,
-    c4_packet_chunks: Vec<Vec<u8>>,
-    c4_waiting_packets: HashMap<u64, Vec<u8>>,
+    c4_assembler: SplitAssembler,
     sync_progress: u64,
     last_packet_send_time: Instant,
-    reliable_packets: HashMap<(u16, u64), (Vec<u8>, Instant)>,
+    // the packet, when it was last sent and whether it has been resent
+    reliable_packets: HashMap<(u16, u64), (Vec<u8>, Instant, bool)>,
     latency: Duration,
     latency_buffer: VecDeque<Duration>,
     waiting_acks: HashSet<(u16, u64)>,
     id: u16,
     id_received: bool,
     message_buffer_position: usize,
-    priorities: Vec<(Gd<NetworkedNode>, i64)>,
+    // each node's accumulated priority and the tick it was last sent on, 0 if it hasn't been yet
+    priorities: Vec<(Gd<NetworkedNode>, i64, u64)>,
     c1_latency_info: LatencyInfo,
     next_c3_packet_number: u64,
-    next_c4_packet_number: u64,
     next_c5_packet_number: u64,
     c3_buffered_packets: HashMap<u64, BitVec<u64, Lsb0>>,
     packet_buffers: VecDeque<Vec<(BitVec<u64, Lsb0>, ClientIndex)>>,
+    stats: PeerStats,
+}
+impl Client {
+    fn acknowledge(&mut self, ack: (u16, u64)) {
+        if let Some((_, sent, resent)) = self.reliable_packets.remove(&ack)
+            && !resent
+        {
+            self.stats.record_rtt(sent.elapsed());
+        }
+    }
+    fn network_stats(&self) -> Dictionary {
+        let mut stats = self.stats.to_dictionary();
+        stats.set("latency_ms", self.latency.as_secs_f64() * 1000.0);
+        stats.set(
+            "late_packets",
+            self.c1_latency_info.c1_miss_rate_average_percent,
+        );
+        stats.set("pending_reliable", self.reliable_packets.len() as i64);
+        stats.set("jitter_buffer_depth", self.packet_buffers.len() as i64);
+        stats.set("voice_buffer_depth", self.voice_packet_buffer.len() as i64);
+        stats
+    }
+    fn debug_dump(&self) -> Dictionary {
+        let mut dump = Dictionary::new();
+        dump.set("id", self.id);
+        dump.set("latency_ms", self.latency.as_secs_f64() * 1000.0);
+        dump.set("finished_sync", self.finished_sync);
+        dump.set("sync_progress", self.sync_progress);
+        dump.set("remaining_bandwidth", self.remaining_bandwidth as i64);
+        dump.set("pending_reliable", self.reliable_packets.len() as i64);
+        dump.set("unreliable_messages", self.unreliable_messages.len() as i64);
+        dump.set("ordered_buffer", self.c3_buffered_packets.len() as i64);
+        dump.set("voice_buffer", self.voice_packet_buffer.len() as i64);
+        let mut priorities: Array<Dictionary> = Array::new();
+        for (node, priority, last_sent) in
+            self.priorities.iter().filter(|x| x.0.is_instance_valid())
+        {
+            let mut entry = Dictionary::new();
+            entry.set("objectid", node.bind().objectid);
+            entry.set("priority", *priority);
+            entry.set("last_sent_tick", *last_sent);
+            priorities.push(&entry);
+        }
+        dump.set("priorities", priorities);
+        dump
+    }
+    // applies the packet limit policy to a violation, returns true if the packet should still be handled
+    fn exceed_packet_limit(&mut self, violation: String, policy: LimitPolicy) -> bool {
+        let handle = match policy {
+            LimitPolicy::Drop => false,
+            LimitPolicy::Warn => {
+                log_warn!(
+                    Transport,
+                    "player {:#?} went over the packet limits: {}",
+                    self.id,
+                    violation
+                );
+                true
+            }
+            LimitPolicy::Kick => {
+                self.kick_reason = Some(violation.clone());
+                false
+            }
+        };
+        self.limit_violations.push(violation);
+        handle
+    }
+    // malformed packets are dropped, clients that keep sending them are kicked at the end of the tick
+    fn record_decode_error(&mut self, error: DecodeError, limit: u32) {
+        log_warn!(
+            Transport,
+            "malformed packet from player {:#?}: {}",
+            self.id,
+            error
+        );
+        if self
+            .decode_errors
+            .record(error, limit, MALFORMED_PACKET_WINDOW_SECONDS)
+        {
+            self.kick_reason = Some("sent too many malformed packets".to_string());
+        }
+    }
+}
+enum ServerSignal {
+    PlayerLeft(u16),
+    PlayerInfoChanged(u16),
+    PlayerInfoRemoved(u16),
+    Moderation(ModerationEvent),
+}
+enum ConnectionEvent {
+    Joined(u16),
+    Resumed(u16),
 }
 #[derive(Debug, Default, Clone)]
 struct LatencyInfo {
@@ -0,0 +1,163 @@
+// connection quality counters, the client keeps one for the server and the server keeps one per client
+use godot::prelude::*;
+use std::collections::HashMap;
+use std::time::Duration;
+
+// channel 1 packets counted before the loss fraction is recalculated
+const LOSS_WINDOW: u64 = 128;
+
+#[derive(Debug, Default)]
+pub struct PeerStats {
+    // bytes by channel including headers, acks are counted under CHANNEL_ACK
+    bytes_sent: HashMap<u16, u64>,
+    bytes_received: HashMap<u16, u64>,
+    packets_sent: u64,
+    packets_received: u64,
+    resends: u64,
+    // smoothed round trip time of reliable packets, from their acks
+    rtt: Option<Duration>,
+    // mean deviation of channel 1 latency as in rfc 3550, any clock offset between us and the peer cancels out
+    jitter_seconds: f64,
+    last_latency: Option<Duration>,
+    // channel 1 packet numbers in the current loss window
+    window_start: Option<u64>,
+    window_received: u64,
+    loss: f64,
+}
+impl PeerStats {
+    pub fn record_sent(&mut self, channel: u16, bytes: usize) {
+        *self.bytes_sent.entry(channel).or_default() += bytes as u64;
+        self.packets_sent += 1;
+    }
+    pub fn record_received(&mut self, channel: u16, bytes: usize) {
+        *self.bytes_received.entry(channel).or_default() += bytes as u64;
+        self.packets_received += 1;
+    }
+    pub fn record_resend(&mut self, channel: u16, bytes: usize) {
+        self.record_sent(channel, bytes);
+        self.resends += 1;
+    }
+    // callers skip packets that were resent since we can't know which send their ack was for
+    pub fn record_rtt(&mut self, sample: Duration) {
+        self.rtt = Some(match self.rtt {
+            Some(rtt) => rtt.mul_f64(0.875) + sample.mul_f64(0.125),
+            None => sample,
+        });
+    }
+    pub fn record_latency(&mut self, latency: Duration) {
+        if let Some(last) = self.last_latency {
+            let difference = latency.abs_diff(last).as_secs_f64();
+            self.jitter_seconds += (difference - self.jitter_seconds) / 16.0;
+        }
+        self.last_latency = Some(latency);
+    }
+    // packets from before the current window are ignored, they were already counted as lost
+    pub fn record_sequence(&mut self, packet_number: u64) {
+        let start = *self.window_start.get_or_insert(packet_number);
+        if packet_number < start {
+            return;
+        }
+        if packet_number - start >= LOSS_WINDOW {
+            let expected = packet_number - start;
+            self.loss = 1.0 - (self.window_received.min(expected) as f64 / expected as f64);
+            self.window_start = Some(packet_number);
+            self.window_received = 0;
+        }
+        self.window_received += 1;
+    }
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+    pub fn jitter(&self) -> Duration {
+        Duration::from_secs_f64(self.jitter_seconds)
+    }
+    // fraction of channel 1 packets that never arrived in the last full window
+    pub fn loss(&self) -> f64 {
+        self.loss
+    }
+    pub fn to_dictionary(&self) -> Dictionary {
+        let channels = |counts: &HashMap<u16, u64>| {
+            let mut dictionary = Dictionary::new();
+            for (channel, bytes) in counts {
+                dictionary.set(*channel, *bytes);
+            }
+            dictionary
+        };
+        let mut dictionary = Dictionary::new();
+        // -1 until a reliable packet has been acked
+        dictionary.set(
+            "rtt_ms",
+            self.rtt().map_or(-1.0, |x| x.as_secs_f64() * 1000.0),
+        );
+        dictionary.set("jitter_ms", self.jitter().as_secs_f64() * 1000.0);
+        dictionary.set("packet_loss", self.loss());
+        dictionary.set("bytes_sent", channels(&self.bytes_sent));
+        dictionary.set("bytes_received", channels(&self.bytes_received));
+        dictionary.set("packets_sent", self.packets_sent);
+        dictionary.set("packets_received", self.packets_received);
+        dictionary.set("resends", self.resends);
+        dictionary
+    }
+}
+// read by the prometheus exporter
+#[cfg(feature = "metrics")]
+impl PeerStats {
+    pub fn bytes_sent(&self) -> &HashMap<u16, u64> {
+        &self.bytes_sent
+    }
+    pub fn bytes_received(&self) -> &HashMap<u16, u64> {
+        &self.bytes_received
+    }
+    pub fn packets_sent(&self) -> u64 {
+        self.packets_sent
+    }
+    pub fn packets_received(&self) -> u64 {
+        self.packets_received
+    }
+    pub fn resends(&self) -> u64 {
+        self.resends
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loss_counts_missing_packet_numbers() {
+        let mut stats = PeerStats::default();
+        for packet_number in (0..LOSS_WINDOW).filter(|x| x % 4 != 3) {
+            stats.record_sequence(packet_number);
+        }
+        assert_eq!(stats.loss(), 0.0);
+        stats.record_sequence(LOSS_WINDOW);
+        assert_eq!(stats.loss(), 0.25);
+        // late packets from the finished window don't count towards the next one
+        stats.record_sequence(3);
+        for packet_number in LOSS_WINDOW + 1..LOSS_WINDOW * 2 + 1 {
+            stats.record_sequence(packet_number);
+        }
+        assert_eq!(stats.loss(), 0.0);
+    }
+
+    #[test]
+    fn jitter_ignores_constant_latency() {
+        let mut stats = PeerStats::default();
+        for _ in 0..32 {
+            stats.record_latency(Duration::from_millis(80));
+        }
+        assert_eq!(stats.jitter(), Duration::ZERO);
+        stats.record_latency(Duration::from_millis(96));
+        assert!((stats.jitter().as_secs_f64() - 0.001).abs() < 1e-9);
+    }
+
+    #[test]
+    fn rtt_is_smoothed() {
+        let mut stats = PeerStats::default();
+        assert_eq!(stats.rtt(), None);
+        stats.record_rtt(Duration::from_millis(80));
+        stats.record_rtt(Duration::from_millis(160));
+        let rtt = stats.rtt().unwrap().as_secs_f64();
+        assert!((rtt - 0.09).abs() < 1e-9);
+    }
+}
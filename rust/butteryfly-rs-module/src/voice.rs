@@ -67,22 +67,23 @@ impl VoiceStreamManager {
             Vec::new()
         }
     }
-    pub fn decode_audio(&mut self, stream: usize, samples: &[u8]) -> Vec<f32> {
+    // samples come from the network so decoding can fail, the caller decides what to play instead
+    pub fn decode_audio(&mut self, stream: usize, samples: &[u8]) -> Result<Vec<f32>> {
         if let Some(decoder) = self.decoders.get_mut(&stream) {
             let mut output = vec![0.0; FRAME_LENGTH];
-            decoder.decode_float(samples, &mut output, false).unwrap();
-            output
+            decoder.decode_float(samples, &mut output, false)?;
+            Ok(output)
         } else {
-            Vec::new()
+            Ok(Vec::new())
         }
     }
-    pub fn decode_stereo_audio(&mut self, stream: usize, samples: &[u8]) -> Vec<f32> {
+    pub fn decode_stereo_audio(&mut self, stream: usize, samples: &[u8]) -> Result<Vec<f32>> {
         if let Some(decoder) = self.decoders.get_mut(&stream) {
             let mut output = vec![0.0; FRAME_LENGTH * 2];
-            decoder.decode_float(samples, &mut output, false).unwrap();
-            output
+            decoder.decode_float(samples, &mut output, false)?;
+            Ok(output)
         } else {
-            Vec::new()
+            Ok(Vec::new())
         }
     }
 }
@@ -0,0 +1,480 @@
+// fallible parsing for packets straight off the network, anything a peer sends goes through here before it is trusted
+// this is kept free of godot so it can be used anywhere, malformed input returns a DecodeError instead of panicking
+use crate::object_ids::ObjectId;
+use crate::rate_limit::TokenBucket;
+use crate::serializer;
+use bitvec::prelude::*;
+use std::collections::HashMap;
+use std::fmt;
+
+const BYTE: usize = 8;
+// channel and packet number
+pub const PACKET_HEADER_BYTES: usize = 10;
+pub const ACK_HEADER_BYTES: usize = 2;
+const ACK_ENTRY_BYTES: usize = 10;
+pub const CHANNEL_ACK: u16 = u16::MAX;
+pub const CHANNEL_SPLIT: u16 = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    // the packet ended before a field it should contain
+    Truncated {
+        field: &'static str,
+        needed: usize,
+        available: usize,
+    },
+    UnknownChannel(u16),
+    // a field had a value that isnt allowed, like a message target kind that doesnt exist
+    InvalidValue(&'static str),
+    // a split packet claimed more chunks than we are willing to buffer
+    TooManyChunks(u64),
+    // values in a message that dont match what its handler expects
+    Message(String),
+    Voice(String),
+}
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Truncated {
+                field,
+                needed,
+                available,
+            } => write!(
+                f,
+                "packet too short for {}, needed {} bits but {} were left",
+                field, needed, available
+            ),
+            DecodeError::UnknownChannel(channel) => write!(f, "unknown channel {}", channel),
+            DecodeError::InvalidValue(field) => write!(f, "invalid value for {}", field),
+            DecodeError::TooManyChunks(chunks) => {
+                write!(f, "split packet with {} chunks is too large", chunks)
+            }
+            DecodeError::Message(error) => write!(f, "malformed message: {}", error),
+            DecodeError::Voice(error) => write!(f, "malformed voice packet: {}", error),
+        }
+    }
+}
+
+fn truncated(field: &'static str, needed: usize, available: usize) -> DecodeError {
+    DecodeError::Truncated {
+        field,
+        needed: needed * BYTE,
+        available: available * BYTE,
+    }
+}
+
+// the header of a raw packet before it is turned into bits
+pub fn read_channel(packet: &[u8]) -> Result<u16, DecodeError> {
+    let bytes = packet
+        .get(..2)
+        .ok_or(truncated("channel", 2, packet.len()))?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+pub fn read_packet_number(packet: &[u8]) -> Result<u64, DecodeError> {
+    let bytes: [u8; 8] = packet
+        .get(2..PACKET_HEADER_BYTES)
+        .and_then(|x| x.try_into().ok())
+        .ok_or(truncated(
+            "packet number",
+            8,
+            packet.len().saturating_sub(2),
+        ))?;
+    Ok(u64::from_le_bytes(bytes))
+}
+pub enum RawPacket {
+    // channel and packet number pairs the peer has received
+    Acks(Vec<(u16, u64)>),
+    Data { channel: u16, packet_number: u64 },
+}
+// works out what kind of packet this is, every packet starts with its channel
+pub fn read_packet(packet: &[u8]) -> Result<RawPacket, DecodeError> {
+    let channel = read_channel(packet)?;
+    if channel == CHANNEL_ACK {
+        return Ok(RawPacket::Acks(read_acks(packet)?));
+    }
+    Ok(RawPacket::Data {
+        channel,
+        packet_number: read_packet_number(packet)?,
+    })
+}
+// the packets being acknowledged as channel and packet number pairs
+pub fn read_acks(packet: &[u8]) -> Result<Vec<(u16, u64)>, DecodeError> {
+    let body = packet.get(ACK_HEADER_BYTES..).ok_or(truncated(
+        "ack header",
+        ACK_HEADER_BYTES,
+        packet.len(),
+    ))?;
+    if body.len() % ACK_ENTRY_BYTES != 0 {
+        return Err(truncated(
+            "ack",
+            ACK_ENTRY_BYTES,
+            body.len() % ACK_ENTRY_BYTES,
+        ));
+    }
+    Ok(body
+        .chunks_exact(ACK_ENTRY_BYTES)
+        .map(|x| {
+            (
+                u16::from_le_bytes([x[0], x[1]]),
+                u64::from_le_bytes([x[2], x[3], x[4], x[5], x[6], x[7], x[8], x[9]]),
+            )
+        })
+        .collect())
+}
+// the first packet of a split send only holds how many chunks follow
+pub fn read_split_count(packet: &[u8], max_chunks: u64) -> Result<u64, DecodeError> {
+    let bytes: [u8; 8] = packet
+        .get(PACKET_HEADER_BYTES..PACKET_HEADER_BYTES + 8)
+        .and_then(|x| x.try_into().ok())
+        .ok_or(truncated(
+            "split chunk count",
+            8,
+            packet.len().saturating_sub(PACKET_HEADER_BYTES),
+        ))?;
+    let chunks = u64::from_le_bytes(bytes);
+    if chunks > max_chunks {
+        return Err(DecodeError::TooManyChunks(chunks));
+    }
+    Ok(chunks)
+}
+// joins the payloads of split chunks back into the original packet
+pub fn reassemble(chunks: &[Vec<u8>]) -> Result<Vec<u8>, DecodeError> {
+    let mut packet: Vec<u8> = Vec::with_capacity(chunks.iter().map(|x| x.len()).sum());
+    for chunk in chunks {
+        packet.extend(chunk.get(PACKET_HEADER_BYTES..).ok_or(truncated(
+            "split chunk",
+            PACKET_HEADER_BYTES,
+            chunk.len(),
+        ))?);
+    }
+    read_channel(&packet)?;
+    Ok(packet)
+}
+// puts split packets back together, a split starts with a packet holding the chunk count followed by the chunks in order
+#[derive(Debug, Default)]
+pub struct SplitAssembler {
+    next_packet_number: u64,
+    remaining_chunks: u64,
+    chunks: Vec<Vec<u8>>,
+    // chunks that arrived ahead of the next one we need
+    waiting: HashMap<u64, Vec<u8>>,
+}
+impl SplitAssembler {
+    // the packet number of the next chunk needed
+    pub fn next_packet_number(&self) -> u64 {
+        self.next_packet_number
+    }
+    // finished packets are added to completed, chunks more than window packets ahead are dropped
+    pub fn push(
+        &mut self,
+        packet_number: u64,
+        packet: Vec<u8>,
+        max_chunks: u64,
+        window: u64,
+        completed: &mut Vec<Vec<u8>>,
+    ) -> Result<(), DecodeError> {
+        if packet_number < self.next_packet_number
+            || packet_number >= self.next_packet_number.saturating_add(window)
+        {
+            return Ok(());
+        }
+        self.waiting.insert(packet_number, packet);
+        while let Some(packet) = self.waiting.remove(&self.next_packet_number) {
+            self.next_packet_number += 1;
+            if self.remaining_chunks == 0 {
+                self.remaining_chunks = read_split_count(&packet, max_chunks)?;
+                continue;
+            }
+            self.chunks.push(packet);
+            self.remaining_chunks -= 1;
+            if self.remaining_chunks != 0 {
+                continue;
+            }
+            let packet = reassemble(&self.chunks);
+            self.chunks.clear();
+            completed.push(packet?);
+        }
+        Ok(())
+    }
+}
+pub fn to_bits(packet: &[u8]) -> BitVec<u64, Lsb0> {
+    let mut bits: BitVec<u64, Lsb0> = BitVec::with_capacity(packet.len() * BYTE);
+    for byte in packet {
+        bits.extend(byte.view_bits::<Lsb0>());
+    }
+    bits
+}
+
+// reads from packets that have already been turned into bits
+pub fn read_u16(
+    data: &BitSlice<u64>,
+    pointer: &mut usize,
+    field: &'static str,
+) -> Result<u16, DecodeError> {
+    read_bits(data, pointer, BYTE * 2, field).map(|x| x as u16)
+}
+pub fn read_u64(
+    data: &BitSlice<u64>,
+    pointer: &mut usize,
+    field: &'static str,
+) -> Result<u64, DecodeError> {
+    read_bits(data, pointer, BYTE * 8, field)
+}
+fn read_bits(
+    data: &BitSlice<u64>,
+    pointer: &mut usize,
+    bits: usize,
+    field: &'static str,
+) -> Result<u64, DecodeError> {
+    let value = data
+        .get(*pointer..*pointer + bits)
+        .ok_or(DecodeError::Truncated {
+            field,
+            needed: bits,
+            available: data.len().saturating_sub(*pointer),
+        })?
+        .load_le::<u64>();
+    *pointer += bits;
+    Ok(value)
+}
+
+// object ids are sent as their index and generation, both as varints since they are usually small
+pub fn push_object_id(packet: &mut BitVec<u64, Lsb0>, id: ObjectId) {
+    serializer::push_varint(packet, id.index() as u64);
+    serializer::push_varint(packet, id.generation() as u64);
+}
+pub fn read_object_id(
+    data: &BitSlice<u64>,
+    pointer: &mut usize,
+    field: &'static str,
+) -> Result<ObjectId, DecodeError> {
+    let index = serializer::read_varint(data, pointer).and_then(|x| u32::try_from(x).ok());
+    let generation = serializer::read_varint(data, pointer).and_then(|x| u16::try_from(x).ok());
+    match (index, generation) {
+        (Some(index), Some(generation)) => Ok(ObjectId::new(index, generation)),
+        _ => Err(DecodeError::InvalidValue(field)),
+    }
+}
+
+// a node's values in a channel 1 packet, framed with their length in bits so entries that can't be applied are skipped instead of ending the packet
+pub struct NodeEntry<'a> {
+    pub objectid: ObjectId,
+    pub values: &'a BitSlice<u64>,
+}
+pub fn push_node_entry(packet: &mut BitVec<u64, Lsb0>, objectid: ObjectId, values: &BitSlice<u64>) {
+    push_object_id(packet, objectid);
+    serializer::push_varint(packet, values.len() as u64);
+    packet.extend_from_bitslice(values);
+}
+pub fn read_node_entry<'a>(
+    packet: &'a BitSlice<u64>,
+    pointer: &mut usize,
+) -> Result<NodeEntry<'a>, DecodeError> {
+    let objectid = read_object_id(packet, pointer, "node objectid")?;
+    let length = serializer::read_varint(packet, pointer)
+        .ok_or(DecodeError::InvalidValue("node entry length"))?;
+    let available = packet.len().saturating_sub(*pointer);
+    if length > available as u64 {
+        return Err(DecodeError::Truncated {
+            field: "node values",
+            needed: length as usize,
+            available,
+        });
+    }
+    let values = &packet[*pointer..*pointer + length as usize];
+    *pointer += length as usize;
+    Ok(NodeEntry { objectid, values })
+}
+
+// counts malformed packets from one peer so repeat offenders can be disconnected
+#[derive(Debug, Default)]
+pub struct DecodeErrors {
+    pub total: u64,
+    pub last_error: Option<DecodeError>,
+    bucket: TokenBucket,
+}
+impl DecodeErrors {
+    // a peer can send up to limit malformed packets within window_seconds, returns true once it goes over
+    // a limit of 0 never disconnects
+    pub fn record(&mut self, error: DecodeError, limit: u32, window_seconds: f64) -> bool {
+        self.total += 1;
+        self.last_error = Some(error);
+        if limit == 0 || window_seconds <= 0.0 {
+            return false;
+        }
+        let limit = limit as f64;
+        !self.bucket.take(limit / window_seconds, limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(channel: u16, packet_number: u64, payload: &[u8]) -> Vec<u8> {
+        let mut packet = channel.to_le_bytes().to_vec();
+        packet.extend(packet_number.to_le_bytes());
+        packet.extend(payload);
+        packet
+    }
+
+    #[test]
+    fn acks_must_be_whole_entries() {
+        let mut acks = CHANNEL_ACK.to_le_bytes().to_vec();
+        acks.extend(packet(3, 7, &[]));
+        assert!(matches!(read_packet(&acks), Ok(RawPacket::Acks(x)) if x == [(3, 7)]));
+        acks.push(0);
+        assert_eq!(
+            read_acks(&acks),
+            Err(DecodeError::Truncated {
+                field: "ack",
+                needed: ACK_ENTRY_BYTES * BYTE,
+                available: BYTE,
+            })
+        );
+        assert!(read_acks(&acks[..1]).is_err());
+    }
+
+    #[test]
+    fn split_count_is_limited() {
+        let start = packet(CHANNEL_SPLIT, 0, &5u64.to_le_bytes());
+        assert_eq!(read_split_count(&start, 5), Ok(5));
+        assert_eq!(
+            read_split_count(&start, 4),
+            Err(DecodeError::TooManyChunks(5))
+        );
+        assert!(matches!(
+            read_split_count(&start[..PACKET_HEADER_BYTES + 4], 5),
+            Err(DecodeError::Truncated { .. })
+        ));
+    }
+
+    #[test]
+    fn split_chunks_are_reordered() {
+        let original = packet(1, 9, &[1, 2, 3, 4]);
+        let (first, second) = original.split_at(6);
+        let mut assembler = SplitAssembler::default();
+        let mut completed = Vec::new();
+        assembler
+            .push(2, packet(CHANNEL_SPLIT, 2, second), 8, 16, &mut completed)
+            .unwrap();
+        assembler
+            .push(1, packet(CHANNEL_SPLIT, 1, first), 8, 16, &mut completed)
+            .unwrap();
+        assert!(completed.is_empty());
+        assembler
+            .push(
+                0,
+                packet(CHANNEL_SPLIT, 0, &2u64.to_le_bytes()),
+                8,
+                16,
+                &mut completed,
+            )
+            .unwrap();
+        assert_eq!(completed, [original]);
+    }
+
+    #[test]
+    fn split_chunks_outside_the_window_are_dropped() {
+        let mut assembler = SplitAssembler::default();
+        let mut completed = Vec::new();
+        assembler
+            .push(4, packet(CHANNEL_SPLIT, 4, &[]), 8, 4, &mut completed)
+            .unwrap();
+        assert!(assembler.waiting.is_empty());
+        assembler
+            .push(
+                0,
+                packet(CHANNEL_SPLIT, 0, &1u64.to_le_bytes()),
+                8,
+                4,
+                &mut completed,
+            )
+            .unwrap();
+        // already handled so it isnt read as a chunk
+        assembler
+            .push(0, packet(CHANNEL_SPLIT, 0, &[1, 0]), 8, 4, &mut completed)
+            .unwrap();
+        assert!(completed.is_empty());
+        assert_eq!(assembler.next_packet_number, 1);
+    }
+
+    #[test]
+    fn short_split_chunks_are_rejected() {
+        let mut assembler = SplitAssembler::default();
+        let mut completed = Vec::new();
+        assembler
+            .push(
+                0,
+                packet(CHANNEL_SPLIT, 0, &1u64.to_le_bytes()),
+                8,
+                16,
+                &mut completed,
+            )
+            .unwrap();
+        assert!(matches!(
+            assembler.push(1, vec![0; PACKET_HEADER_BYTES - 1], 8, 16, &mut completed),
+            Err(DecodeError::Truncated { .. })
+        ));
+        assert!(completed.is_empty());
+        // the next split still works
+        assembler
+            .push(
+                2,
+                packet(CHANNEL_SPLIT, 2, &1u64.to_le_bytes()),
+                8,
+                16,
+                &mut completed,
+            )
+            .unwrap();
+        assembler
+            .push(3, packet(CHANNEL_SPLIT, 3, &[1, 0]), 8, 16, &mut completed)
+            .unwrap();
+        assert_eq!(completed, [vec![1, 0]]);
+    }
+
+    #[test]
+    fn node_entries_cant_overrun_the_packet() {
+        let values: BitVec<u64, Lsb0> = BitVec::repeat(true, 12);
+        let mut data = BitVec::new();
+        push_node_entry(&mut data, ObjectId::new(3, 1), &values);
+        let mut pointer = 0;
+        let entry = read_node_entry(&data, &mut pointer).unwrap();
+        assert_eq!(entry.objectid, ObjectId::new(3, 1));
+        assert_eq!(entry.values, values);
+        assert_eq!(pointer, data.len());
+
+        let mut data = BitVec::new();
+        push_object_id(&mut data, ObjectId::new(3, 1));
+        serializer::push_varint(&mut data, 64);
+        data.extend(values);
+        assert_eq!(
+            read_node_entry(&data, &mut 0).err(),
+            Some(DecodeError::Truncated {
+                field: "node values",
+                needed: 64,
+                available: 12,
+            })
+        );
+    }
+
+    #[test]
+    fn decode_errors_go_over_the_limit() {
+        let mut errors = DecodeErrors::default();
+        for _ in 0..3 {
+            assert!(!errors.record(DecodeError::InvalidValue("test"), 3, 60.0));
+        }
+        assert!(errors.record(DecodeError::UnknownChannel(9), 3, 60.0));
+        assert_eq!(errors.total, 4);
+        assert_eq!(errors.last_error, Some(DecodeError::UnknownChannel(9)));
+    }
+
+    #[test]
+    fn decode_errors_without_a_limit_never_go_over() {
+        let mut errors = DecodeErrors::default();
+        for _ in 0..1000 {
+            assert!(!errors.record(DecodeError::InvalidValue("test"), 0, 60.0));
+        }
+        assert_eq!(errors.total, 1000);
+    }
+}
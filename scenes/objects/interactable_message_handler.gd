@@ -25,7 +25,7 @@ func _get_value_type(_previous_value: Variant, idx: int) -> int:
 			return 7
 	return -1
 
-func _process_message(values: Array) -> void:
+func _process_message(values: Array, _context: MessageContext) -> void:
 	var target:Node = get_tree().root
 	if values[1] != []:
 		# scene tree could be desynced for us so dont blindly trust the path
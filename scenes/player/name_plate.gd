@@ -5,7 +5,19 @@ var id:int
 
 func _ready() -> void:
 	id = player_access.networker.owner_id
-	set_player_name("Player" + str(id))
+	update_player_name(id)
+	(NetworkManager as NetNodeManager).player_info_changed.connect(update_player_name)
+
+func update_player_name(player:int) -> void:
+	if player != id:
+		return
+	var info:Dictionary = (NetworkManager as NetNodeManager).get_player_info(id)
+	var player_name:String = info.get("display_name", "Player" + str(id))
+	if info.get("reconnecting", false):
+		player_name += " (reconnecting)"
+	elif info.get("muted", false):
+		player_name += " (muted)"
+	set_player_name(player_name)
 
 func set_player_name(player_name:String) -> void:
 	@warning_ignore("unsafe_property_access")
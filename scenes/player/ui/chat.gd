@@ -6,6 +6,7 @@ func _ready() -> void:
 		add_message(message)
 	@warning_ignore("unsafe_property_access", "unsafe_method_access")
 	GlobalWorldAccess.current_world.chat_box_manager.new_message_sent.connect(add_message)
+	(NetworkManager as NetNodeManager).moderation_event.connect(add_moderation_event)
 
 func add_message(new_message:ChatBoxManager.Message) -> void:
 	var player:int = new_message.player
@@ -13,8 +14,17 @@ func add_message(new_message:ChatBoxManager.Message) -> void:
 	if player == 0:
 		player_name = "SYSTEM"
 	else:
-		player_name = "Player " + str(player)
+		var info:Dictionary = (NetworkManager as NetNodeManager).get_player_info(player)
+		player_name = info.get("display_name", "Player " + str(player))
 	var message:String = player_name + ": "
 	message += new_message.text
 	message += "\n"
 	text += message
+
+func add_moderation_event(player:int, action:String, reason:String) -> void:
+	var info:Dictionary = (NetworkManager as NetNodeManager).get_player_info(player)
+	var message:String = "SYSTEM: " + info.get("display_name", "Player " + str(player)) + " was " + action
+	if reason != "":
+		message += " (" + reason + ")"
+	message += "\n"
+	text += message
@@ -20,5 +20,11 @@ func _get_value_type(_previous_value: Variant, idx: int) -> int:
 			return 2
 	return -1
 
-func _process_message(values: Array) -> void:
+# players can only act for themselves
+func _authorize_message(sender: int, values: Array) -> Variant:
+	if values[0] != sender:
+		return null
+	return values
+
+func _process_message(values: Array, _context: MessageContext) -> void:
 	avatar_changed.emit(values[0], values[1])
@@ -24,7 +24,12 @@ func _get_value_type(_previous_value: Variant, idx: int) -> int:
 			return 6
 	return -1
 
-func _process_message(values: Array) -> void:
+# the player id in the message is replaced with the real sender so nobody can chat as someone else
+func _authorize_message(sender: int, values: Array) -> Variant:
+	values[0] = sender
+	return values
+
+func _process_message(values: Array, _context: MessageContext) -> void:
 	var message:Message = Message.new()
 	message.player = values[0]
 	message.text = values[1]
@@ -11,7 +11,11 @@ func _get_value_type(_previous_value: Variant, idx: int) -> int:
 		0:
 			return 2
 	return -1
-func _process_message(values: Array) -> void:
+# only the server announces players
+func _authorize_message(_sender: int, _values: Array) -> Variant:
+	return null
+
+func _process_message(values: Array, _context: MessageContext) -> void:
 	handle_on_dc(values)
 
 func handle_on_dc(values: Array) -> void:
@@ -27,7 +27,13 @@ func _get_value_type(_previous_value: Variant, idx: int) -> int:
 			return 7
 	return -1
 
-func _process_message(values: Array) -> void:
+# players can only act for themselves
+func _authorize_message(sender: int, values: Array) -> Variant:
+	if values[0] != sender:
+		return null
+	return values
+
+func _process_message(values: Array, _context: MessageContext) -> void:
 	var target:Node = get_tree().root
 	if values[1] != []:
 		# scene tree could be desynced for us so dont blindly trust the path
@@ -12,7 +12,11 @@ func _get_value_type(_previous_value: Variant, idx: int) -> int:
 			return 2
 	return -1
 
-func _process_message(values: Array) -> void:
+# only the server announces players
+func _authorize_message(_sender: int, _values: Array) -> Variant:
+	return null
+
+func _process_message(values: Array, _context: MessageContext) -> void:
 	var player_owner:int = values[0]
 	var world:WorldController = GlobalWorldAccess.current_world
 	var player:Player = preload("res://scenes/player/player.tscn").instantiate()
@@ -17,4 +17,5 @@ func _ready() -> void:
 		chat_box_manager.new_message_sent.connect(log_chat_to_console)
 
 func log_chat_to_console(message:ChatBoxManager.Message) -> void:
-	print("Player " + str(message.player) + ": " + message.text)
+	var info:Dictionary = (NetworkManager as NetNodeManager).get_player_info(message.player)
+	print(info.get("display_name", "Player " + str(message.player)) + ": " + message.text)